@@ -0,0 +1,175 @@
+//! ROM disassembler
+//!
+//! Prints the disassembly of a range of ROM banks. Bank 0 is shown at 0x0000-0x3FFF and every
+//! other bank at 0x4000-0x7FFF, where the MBC maps them.
+//!
+//! Usage: disassemble <rom> [options]
+//!
+//! Option                    Explanation                                              \
+//! --banks <n>[-<m>]         Only disassemble bank n, or banks n to m (default all)   \
+//! --start <addr>            Skip the instructions before addr in each bank (hex)     \
+//! --end <addr>              Stop at addr in each bank (hex, inclusive)               \
+//! --sym <path>              Symbol file to label the output with (default: the .sym  \
+//!                           file next to the ROM, if there is one)                   \
+
+use std::{env, fs, path::PathBuf, process::ExitCode};
+
+use gioboycolor::core::{
+    disassembler::{disassemble, Labels},
+    symbols::SymbolTable,
+};
+
+const BANK_SIZE: usize = 0x4000;
+
+const USAGE: &str =
+    "Usage: disassemble <rom> [--banks <n>[-<m>]] [--start <addr>] [--end <addr>] [--sym <path>]";
+
+struct Options {
+    rom_path: PathBuf,
+    // Inclusive, None for every bank
+    banks: Option<(usize, usize)>,
+    start: u16,
+    end: u16,
+    sym_path: Option<PathBuf>,
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex value: {}", text))
+}
+
+fn parse_bank(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("Invalid bank number: {}", text))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        banks: None,
+        start: 0x0000,
+        end: 0xFFFF,
+        sym_path: None,
+    };
+    let mut rom_path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--banks" => {
+                let banks = value()?;
+                options.banks = Some(match banks.split_once('-') {
+                    Some((first, last)) => (parse_bank(first)?, parse_bank(last)?),
+                    None => (parse_bank(&banks)?, parse_bank(&banks)?),
+                });
+            }
+            "--start" => options.start = parse_hex(&value()?)?,
+            "--end" => options.end = parse_hex(&value()?)?,
+            "--sym" => options.sym_path = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(options)
+}
+
+fn disassemble_bank(rom: &[u8], bank: usize, options: &Options, symbols: &SymbolTable) {
+    let bytes = &rom[bank * BANK_SIZE..((bank + 1) * BANK_SIZE).min(rom.len())];
+    let base = if bank == 0 { 0x0000 } else { BANK_SIZE };
+
+    println!("; Bank {:02X}", bank);
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = (base + offset) as u16;
+        let instruction = disassemble(&bytes[offset..], address, bank, symbols);
+
+        if address > options.end {
+            break;
+        }
+
+        if address >= options.start {
+            if let Some(label) = symbols.label(bank, address) {
+                println!("{}:", label);
+            }
+
+            let end = (offset + instruction.length).min(bytes.len());
+            let hex: Vec<String> = bytes[offset..end]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+
+            println!(
+                "{:02X}:{:04X}  {:<8}  {}",
+                bank,
+                address,
+                hex.join(" "),
+                instruction.text
+            );
+        }
+
+        offset += instruction.length;
+    }
+
+    println!();
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let rom = match fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Unable to read {}: {}", options.rom_path.display(), error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // An explicit symbol file has to exist, the one next to the ROM is optional
+    let sym_path = options
+        .sym_path
+        .clone()
+        .unwrap_or(options.rom_path.with_extension("sym"));
+    let symbols = if options.sym_path.is_some() || sym_path.exists() {
+        match SymbolTable::load(&sym_path) {
+            Ok(symbols) => symbols,
+            Err(error) => {
+                eprintln!("Unable to read {}: {}", sym_path.display(), error);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        SymbolTable::new()
+    };
+
+    let bank_count = rom.len().div_ceil(BANK_SIZE);
+    let (first, last) = options.banks.unwrap_or((0, bank_count.saturating_sub(1)));
+
+    if first > last || last >= bank_count {
+        eprintln!(
+            "The ROM only has banks 0 to {}",
+            bank_count.saturating_sub(1)
+        );
+        return ExitCode::FAILURE;
+    }
+
+    for bank in first..=last {
+        disassemble_bank(&rom, bank, &options, &symbols);
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,344 @@
+//! Headless runner
+//!
+//! Runs a ROM without a window, until a frame limit, a stop condition or a timeout, and
+//! reports the result through the exit code. Meant for running test ROMs on CI machines.
+//!
+//! Usage: headless <rom> [options]
+//!
+//! Option                    Explanation                                              \
+//! --frames <n>              Stop after n frames                                      \
+//! --pc <addr>               Stop when PC reaches addr (hex)                          \
+//! --serial <text>           Stop when the serial output contains text                \
+//! --memory <addr>=<value>   Stop when the byte at addr equals value (both hex)       \
+//! --timeout <seconds>       Give up after this much wall-clock time (default 60)     \
+//! --png <path>              Save the last frame as a PNG                             \
+//! --wav <path>              Record the audio as a WAV file                           \
+//! --trace <path>            Log every instruction to a file                          \
+//! --trace-format <format>   doctor (default) or rich, see core::trace                \
+//! --printer <dir>           Connect a Game Boy Printer saving printouts to dir       \
+//! --ir-script <path>        Face the infrared port with a scripted peer, see         \
+//!                           core::infrared::ScriptedPeer                             \
+//!
+//! The CPU doesn't implement every instruction yet, so most ROMs eventually stop with exit code
+//! 4, reporting the unsupported opcode and its address.
+//!
+//! Rich traces are labelled with the symbol file next to the ROM, e.g. game.sym. Cheats are
+//! loaded from the cheat file next to it, e.g. game.cht, see core::cheats.
+//!
+//! Exit code    Explanation                                       \
+//! 0            A condition was met, or all frames ran if none    \
+//! 1            The frame limit was reached before a condition    \
+//! 2            The timeout was reached                           \
+//! 3            Invalid arguments, or a file couldn't be used     \
+//! 4            The emulator panicked, e.g. on an unsupported     \
+//!              opcode                                            \
+
+use std::{
+    any::Any,
+    env,
+    fs::File,
+    io::BufWriter,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use gioboycolor::core::{
+    apu::sink::WavWriter,
+    cheats::Cheats,
+    gbc::GioBoyColor,
+    infrared::ScriptedPeer,
+    printer::Printer,
+    symbols::SymbolTable,
+    trace::{TraceFormat, Tracer},
+};
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 60;
+
+const EXIT_CONDITION_MET: u8 = 0;
+const EXIT_FRAME_LIMIT: u8 = 1;
+const EXIT_TIMEOUT: u8 = 2;
+const EXIT_USAGE: u8 = 3;
+const EXIT_PANIC: u8 = 4;
+
+const USAGE: &str = "Usage: headless <rom> [--frames <n>] [--pc <addr>] [--serial <text>] \
+[--memory <addr>=<value>] [--timeout <seconds>] [--png <path>] [--wav <path>] [--trace <path>] \
+[--trace-format <doctor|rich>] [--printer <dir>] [--ir-script <path>]";
+
+struct Options {
+    rom_path: PathBuf,
+    frames: Option<u64>,
+    pc: Option<u16>,
+    serial: Option<String>,
+    memory: Option<(u16, u8)>,
+    timeout: Duration,
+    png_path: Option<PathBuf>,
+    wav_path: Option<PathBuf>,
+    trace_path: Option<PathBuf>,
+    trace_format: TraceFormat,
+    printer_path: Option<PathBuf>,
+    ir_script_path: Option<PathBuf>,
+}
+
+impl Options {
+    fn has_condition(&self) -> bool {
+        self.pc.is_some() || self.serial.is_some() || self.memory.is_some()
+    }
+
+    // Checked after every instruction
+    fn condition_met(&self, gbc: &GioBoyColor) -> bool {
+        self.pc.is_some_and(|pc| gbc.cpu.registers.pc == pc)
+            || self.serial.as_ref().is_some_and(|text| {
+                String::from_utf8_lossy(gbc.serial.output()).contains(text.as_str())
+            })
+            || self
+                .memory
+                .is_some_and(|(address, value)| gbc.peek(address) == value)
+    }
+}
+
+fn parse_hex<T: TryFrom<u32>>(text: &str) -> Result<T, String> {
+    let digits = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid hex value: {}", text))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        frames: None,
+        pc: None,
+        serial: None,
+        memory: None,
+        timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
+        png_path: None,
+        wav_path: None,
+        trace_path: None,
+        trace_format: TraceFormat::Doctor,
+        printer_path: None,
+        ir_script_path: None,
+    };
+    let mut rom_path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--frames" => {
+                let frames = value()?;
+                options.frames = Some(
+                    frames
+                        .parse()
+                        .map_err(|_| format!("Invalid frame count: {}", frames))?,
+                );
+            }
+            "--pc" => options.pc = Some(parse_hex(&value()?)?),
+            "--serial" => options.serial = Some(value()?),
+            "--memory" => {
+                let condition = value()?;
+                let (address, data) = condition
+                    .split_once('=')
+                    .ok_or(format!("Expected <addr>=<value>, got {}", condition))?;
+                options.memory = Some((parse_hex(address)?, parse_hex(data)?));
+            }
+            "--timeout" => {
+                let seconds = value()?;
+                options.timeout = seconds
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or(format!("Invalid timeout: {}", seconds))?;
+            }
+            "--png" => options.png_path = Some(PathBuf::from(value()?)),
+            "--wav" => options.wav_path = Some(PathBuf::from(value()?)),
+            "--trace" => options.trace_path = Some(PathBuf::from(value()?)),
+            "--trace-format" => {
+                let format = value()?;
+                options.trace_format = TraceFormat::from_name(&format)
+                    .ok_or(format!("Unknown trace format: {}", format))?;
+            }
+            "--printer" => options.printer_path = Some(PathBuf::from(value()?)),
+            "--ir-script" => options.ir_script_path = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(options)
+}
+
+fn run(gbc: &mut GioBoyColor, options: &Options, mut wav: Option<&mut WavWriter>) -> u8 {
+    let start = Instant::now();
+    let mut frames = 0;
+
+    loop {
+        let result = gbc.run_frame_until(|gbc| options.condition_met(gbc));
+
+        if let Some(wav) = wav.as_deref_mut() {
+            if let Err(error) = gbc.flush_audio(wav) {
+                eprintln!("Unable to write the audio: {}", error);
+                return EXIT_USAGE;
+            }
+        }
+
+        if result.condition_met {
+            println!("Condition met after {} frames", frames);
+            return EXIT_CONDITION_MET;
+        }
+
+        frames += 1;
+
+        if options.frames.is_some_and(|limit| frames >= limit) {
+            println!("Ran {} frames", frames);
+            return if options.has_condition() {
+                EXIT_FRAME_LIMIT
+            } else {
+                EXIT_CONDITION_MET
+            };
+        }
+
+        if start.elapsed() >= options.timeout {
+            println!("Timed out after {} frames", frames);
+            return EXIT_TIMEOUT;
+        }
+    }
+}
+
+// Panics carry either a String or a &str
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown cause")
+}
+
+fn save_png(gbc: &GioBoyColor, path: &PathBuf) -> Result<(), png::EncodingError> {
+    // SGB games are saved with their border
+    let (width, height) = gbc.screen_size();
+
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    // The frame buffer is 0RGB
+    let data: Vec<u8> = gbc
+        .screen()
+        .iter()
+        .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+        .collect();
+
+    encoder.write_header()?.write_image_data(&data)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    if !options.rom_path.is_file() {
+        eprintln!("ROM not found: {}", options.rom_path.display());
+        return ExitCode::from(EXIT_USAGE);
+    }
+
+    let mut gbc = GioBoyColor::new();
+
+    if let Some(path) = &options.trace_path {
+        match Tracer::to_file(options.trace_format, path) {
+            Ok(mut tracer) => {
+                if let Some(symbols) = SymbolTable::for_rom(&options.rom_path) {
+                    tracer.set_symbols(symbols);
+                }
+                gbc.set_tracer(Some(tracer));
+            }
+            Err(error) => {
+                eprintln!("Unable to create {}: {}", path.display(), error);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+    }
+
+    if let Some(path) = &options.printer_path {
+        gbc.serial.connect(Box::new(Printer::new(path)));
+    }
+
+    if let Some(path) = &options.ir_script_path {
+        match ScriptedPeer::load(path) {
+            Ok(peer) => {
+                gbc.infrared.connect(Box::new(peer));
+            }
+            Err(error) => {
+                eprintln!("Unable to load {}: {}", path.display(), error);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+    }
+
+    if let Err(error) = gbc.load_rom(&options.rom_path) {
+        eprintln!("Unable to load {}: {}", options.rom_path.display(), error);
+        return ExitCode::from(EXIT_USAGE);
+    }
+    gbc.cheats = Cheats::for_rom(&options.rom_path);
+
+    let mut wav = None;
+    if let Some(path) = &options.wav_path {
+        match WavWriter::create(path, gbc.apu.output.sample_rate()) {
+            Ok(writer) => wav = Some(writer),
+            Err(error) => {
+                eprintln!("Unable to create {}: {}", path.display(), error);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+    }
+
+    // Unimplemented instructions and registers panic, which is reported as a failure
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(&mut gbc, &options, wav.as_mut())));
+
+    let mut code = match result {
+        Ok(code) => code,
+        Err(payload) => {
+            eprintln!("The emulator panicked: {}", panic_message(&*payload));
+            EXIT_PANIC
+        }
+    };
+
+    // Flushes the trace, and the printout in progress
+    gbc.set_tracer(None);
+    gbc.serial.disconnect();
+
+    let serial = gbc.serial.output();
+    if !serial.is_empty() {
+        println!("Serial output:\n{}", String::from_utf8_lossy(serial));
+    }
+
+    // Patches the sizes in the header, even if writing the audio failed half way
+    if let (Some(wav), Some(path)) = (wav, &options.wav_path) {
+        if let Err(error) = wav.finish() {
+            eprintln!("Unable to save {}: {}", path.display(), error);
+            code = code.max(EXIT_USAGE);
+        }
+    }
+
+    if let Some(path) = &options.png_path {
+        if let Err(error) = save_png(&gbc, path) {
+            eprintln!("Unable to save {}: {}", path.display(), error);
+            code = code.max(EXIT_USAGE);
+        }
+    }
+
+    ExitCode::from(code)
+}
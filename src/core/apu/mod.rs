@@ -0,0 +1,371 @@
+mod noise;
+pub mod output;
+mod pulse;
+pub mod sink;
+mod units;
+mod wave;
+
+use super::memory_map::*;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+use noise::NoiseChannel;
+use output::{AudioOutput, DEFAULT_SAMPLE_RATE};
+use pulse::PulseChannel;
+use wave::WaveChannel;
+
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+/// Audio Processing Unit
+///
+/// Register    Explanation                                                             \
+/// NR50        Bit 7: VIN left, bits 6-4: left volume, bit 3: VIN right,               \
+///             bits 2-0: right volume                                                  \
+/// NR51        Bits 7-4: channels 4-1 to the left, bits 3-0: channels 4-1 to the right \
+/// NR52        Bit 7: audio on/off, bits 3-0: channel 4-1 on (read-only)               \
+///
+/// Turning audio off through NR52 clears all audio registers and makes them read-only until
+/// it is turned on again. Wave RAM is not affected.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Audio_Registers.html))
+pub struct Apu {
+    powered: bool,
+    channel1: PulseChannel,
+    channel2: PulseChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    master_volume: u8,
+    panning: u8,
+    frame_sequencer_timer: u32,
+    frame_sequencer_step: u8,
+    pub output: AudioOutput,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            powered: false,
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            master_volume: 0,
+            panning: 0,
+            frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            frame_sequencer_step: 0,
+            output: AudioOutput::new(DEFAULT_SAMPLE_RATE),
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR10..=NR14 => self.channel1.read(address - NR10),
+            NR21..=NR24 => self.channel2.read(address - NR20),
+            NR30..=NR34 => self.channel3.read(address - NR30),
+            NR41..=NR44 => self.channel4.read(address - NR40),
+            NR50 => self.master_volume,
+            NR51 => self.panning,
+            NR52 => {
+                0x70 | (self.powered as u8) << 7
+                    | (self.channel4.enabled as u8) << 3
+                    | (self.channel3.enabled as u8) << 2
+                    | (self.channel2.enabled as u8) << 1
+                    | self.channel1.enabled as u8
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.channel3.wave_ram[(address - WAVE_RAM_START) as usize]
+            }
+            // Unused addresses in the audio register range
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        if let WAVE_RAM_START..=WAVE_RAM_END = address {
+            self.channel3.wave_ram[(address - WAVE_RAM_START) as usize] = data;
+            return;
+        }
+
+        if address == NR52 {
+            self.set_power(data & 0x80 != 0);
+            return;
+        }
+
+        // All other registers are read-only while audio is off
+        if !self.powered {
+            return;
+        }
+
+        match address {
+            NR10..=NR14 => self.channel1.write(address - NR10, data),
+            NR21..=NR24 => self.channel2.write(address - NR20, data),
+            NR30..=NR34 => self.channel3.write(address - NR30, data),
+            NR41..=NR44 => self.channel4.write(address - NR40, data),
+            NR50 => self.master_volume = data,
+            NR51 => self.panning = data,
+            _ => (),
+        }
+    }
+
+    fn set_power(&mut self, powered: bool) {
+        if self.powered && !powered {
+            // Clear every register, keeping wave RAM
+            let wave_ram = self.channel3.wave_ram;
+
+            self.channel1 = PulseChannel::new(true);
+            self.channel2 = PulseChannel::new(false);
+            self.channel3 = WaveChannel::new();
+            self.channel3.wave_ram = wave_ram;
+            self.channel4 = NoiseChannel::new();
+            self.master_volume = 0;
+            self.panning = 0;
+        } else if !self.powered && powered {
+            self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+            self.frame_sequencer_step = 0;
+        }
+
+        self.powered = powered;
+    }
+
+    // Advances the APU by the given amount of T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        if self.powered {
+            self.tick_channels(cycles);
+        }
+
+        self.output.update(cycles, self.sample());
+    }
+
+    fn tick_channels(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+
+        // Split the update at frame sequencer ticks so channels see them at the right time
+        while remaining > 0 {
+            let elapsed = remaining.min(self.frame_sequencer_timer);
+
+            self.channel1.tick(elapsed);
+            self.channel2.tick(elapsed);
+            self.channel3.tick(elapsed);
+            self.channel4.tick(elapsed);
+
+            self.frame_sequencer_timer -= elapsed;
+            remaining -= elapsed;
+
+            if self.frame_sequencer_timer == 0 {
+                self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                self.clock_frame_sequencer();
+            }
+        }
+    }
+
+    // Step    Length    Sweep    Envelope
+    // 0       Clock     -        -
+    // 2       Clock     Clock    -
+    // 4       Clock     -        -
+    // 6       Clock     Clock    -
+    // 7       -         -        Clock
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+
+        if step.is_multiple_of(2) {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep();
+        }
+
+        if step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+
+        self.frame_sequencer_step = (step + 1) % 8;
+    }
+
+    // Changing the rate discards any samples not taken yet
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.output = AudioOutput::new(sample_rate);
+    }
+
+    // Current stereo output (left, right), each in the range -1.0 to 1.0
+    pub fn sample(&self) -> (f32, f32) {
+        let channels = [
+            dac_output(self.channel1.dac_enabled(), self.channel1.output()),
+            dac_output(self.channel2.dac_enabled(), self.channel2.output()),
+            dac_output(self.channel3.dac_enabled(), self.channel3.output()),
+            dac_output(self.channel4.dac_enabled(), self.channel4.output()),
+        ];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        for (index, output) in channels.iter().enumerate() {
+            if self.panning & (0x10 << index) != 0 {
+                left += output;
+            }
+            if self.panning & (0x01 << index) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = ((self.master_volume >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.master_volume & 0x07) as f32 + 1.0;
+
+        (
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        )
+    }
+
+    // The audio output belongs to the host and isn't part of the state
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.powered);
+        self.channel1.save_state(writer);
+        self.channel2.save_state(writer);
+        self.channel3.save_state(writer);
+        self.channel4.save_state(writer);
+        writer.write_u8(self.master_volume);
+        writer.write_u8(self.panning);
+        writer.write_u32(self.frame_sequencer_timer);
+        writer.write_u8(self.frame_sequencer_step);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.powered = reader.read_bool()?;
+        self.channel1.load_state(reader)?;
+        self.channel2.load_state(reader)?;
+        self.channel3.load_state(reader)?;
+        self.channel4.load_state(reader)?;
+        self.master_volume = reader.read_u8()?;
+        self.panning = reader.read_u8()?;
+        self.frame_sequencer_timer = reader.read_u32()?;
+        // A timer of 0 would never clock the frame sequencer
+        if self.frame_sequencer_timer == 0 || self.frame_sequencer_timer > FRAME_SEQUENCER_PERIOD {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.frame_sequencer_step = reader.read_u8()?;
+        if self.frame_sequencer_step >= 8 {
+            return Err(SaveStateError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+// Converts a digital value (0 to 15) to an analog one (1.0 to -1.0), disabled DACs output 0
+fn dac_output(dac_enabled: bool, value: u8) -> f32 {
+    if dac_enabled {
+        1.0 - value as f32 / 7.5
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu
+    }
+
+    #[test]
+    fn length_counter_disables_a_channel() {
+        let mut apu = powered();
+
+        // One length tick left, with the length enabled
+        apu.write(NR10 + 1, 0x3F);
+        apu.write(NR10 + 2, 0xF0);
+        apu.write(NR14, 0xC0);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+
+        // The first frame sequencer step clocks lengths
+        apu.tick(FRAME_SEQUENCER_PERIOD - 1);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+        apu.tick(1);
+        assert_eq!(apu.read(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn envelope_is_clocked_on_step_7() {
+        let mut apu = powered();
+
+        // Volume 15, going down every tick
+        apu.write(NR10 + 2, 0xF1);
+        apu.write(NR14, 0x80);
+
+        apu.tick(FRAME_SEQUENCER_PERIOD * 7);
+        assert_eq!(apu.channel1.envelope.volume, 15);
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        assert_eq!(apu.channel1.envelope.volume, 14);
+        apu.tick(FRAME_SEQUENCER_PERIOD * 8);
+        assert_eq!(apu.channel1.envelope.volume, 13);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut apu = powered();
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0xFF);
+        apu.write(NR10 + 2, 0xF0);
+        apu.write(NR14, 0x80);
+        apu.write(WAVE_RAM_START, 0x12);
+
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(NR52), 0x70);
+        assert_eq!(apu.read(NR50), 0x00);
+        assert_eq!(apu.read(NR51), 0x00);
+        assert_eq!(apu.read(NR10 + 2), 0x00);
+
+        // Registers are read-only until powered again, wave RAM is kept and still writable
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x00);
+        assert_eq!(apu.read(WAVE_RAM_START), 0x12);
+        apu.write(WAVE_RAM_START, 0x34);
+        assert_eq!(apu.read(WAVE_RAM_START), 0x34);
+
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x77);
+    }
+
+    #[test]
+    fn dac_off_silences_the_output() {
+        let mut apu = powered();
+        apu.write(NR51, 0x11);
+
+        assert_eq!(apu.sample(), (0.0, 0.0));
+
+        // A powered DAC outputs its highest level for a digital 0
+        apu.write(NR10 + 2, 0x08);
+        assert_eq!(apu.sample(), (1.0 / 32.0, 1.0 / 32.0));
+
+        apu.write(NR10 + 2, 0x00);
+        assert_eq!(apu.sample(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let mut writer = StateWriter::new();
+        powered().save_state(&mut writer);
+        let mut state = writer.into_bytes();
+
+        let load = |state: &[u8]| Apu::new().load_state(&mut StateReader::new(state));
+        assert!(load(&state).is_ok());
+
+        // The frame sequencer step comes last
+        *state.last_mut().unwrap() = 8;
+        assert!(load(&state).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use super::units::{Envelope, LengthCounter};
+use crate::core::save_state::{SaveStateError, StateReader, StateWriter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Noise channel (channel 4)
+///
+/// Register    Explanation                                           \
+/// NR41        Initial length timer (write-only)                     \
+/// NR42        Volume envelope                                       \
+/// NR43        Bits 7-4: clock shift, bit 3: LFSR width (1 = 7-bit), \
+///             bits 2-0: clock divider                               \
+/// NR44        Bit 7: trigger, bit 6: length enable                  \
+///
+/// The channel outputs the inverted lowest bit of a linear feedback shift register, which is
+/// clocked at 262144 / (divider * 2^shift) Hz.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Audio_Registers.html))
+pub struct NoiseChannel {
+    pub enabled: bool,
+    polynomial: u8,
+    lfsr: u16,
+    timer: u32,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            polynomial: 0,
+            lfsr: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    // Registers are indexed 1 to 4 (NR41 to NR44)
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            1 => 0xFF,
+            2 => self.envelope.read(),
+            3 => self.polynomial,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            1 => self.length.load(data & 0x3F),
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial = data,
+            4 => {
+                self.length.enabled = data & 0x40 != 0;
+
+                if data & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.timer_reload();
+        self.lfsr = 0;
+        self.length.trigger();
+        self.envelope.trigger();
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.timer_reload();
+            self.clock_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    fn clock_lfsr(&mut self) {
+        // Bit 15 receives the XNOR of bits 0 and 1
+        let bit = !(self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr & !(1 << 15)) | (bit << 15);
+
+        // In 7-bit mode, the bit is also copied into bit 7
+        if self.polynomial & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !(1 << 7)) | (bit << 7);
+        }
+
+        self.lfsr >>= 1;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // Digital output, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        (self.lfsr & 1) as u8 * self.envelope.volume
+    }
+
+    fn timer_reload(&self) -> u32 {
+        let shift = self.polynomial >> 4;
+        DIVISORS[(self.polynomial & 0x07) as usize] << shift
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.polynomial);
+        writer.write_u16(self.lfsr);
+        writer.write_u32(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.polynomial = reader.read_u8()?;
+        self.lfsr = reader.read_u16()?;
+        self.timer = reader.read_u32()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)
+    }
+}
@@ -0,0 +1,279 @@
+use std::f64::consts::PI;
+
+// T-cycles per second
+pub const CLOCK_RATE: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// Taps of the band-limited step kernel, output is delayed by half of them
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+// Cutoff frequency relative to the output Nyquist frequency
+const KERNEL_CUTOFF: f64 = 0.9;
+
+// Deltas are flushed into samples at least this often, in T-cycles
+const MAX_FRAME_CLOCKS: u32 = 65_536;
+// Seconds of samples kept when nobody drains the output
+const MAX_BUFFERED_SECONDS: u32 = 1;
+
+/// Band-limited synthesis
+///
+/// The APU output is a sum of steps, which would alias if sampled directly at the host rate.
+/// Instead every change in amplitude is added as a delta convolved with a windowed sinc
+/// impulse at its exact (fractional) position in the output, and the output is obtained by
+/// integrating the deltas. This is the same technique used by blargg's Blip_Buffer.
+struct BlipBuffer {
+    // Output samples per T-cycle
+    factor: f64,
+    // Output position of the first T-cycle of the current frame
+    offset: f64,
+    deltas: Vec<f32>,
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    fn new(sample_rate: u32) -> BlipBuffer {
+        BlipBuffer {
+            factor: sample_rate as f64 / CLOCK_RATE as f64,
+            offset: 0.0,
+            deltas: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+        }
+    }
+
+    fn add_delta(&mut self, kernel: &[[f32; KERNEL_WIDTH]], clock: u32, delta: f32) {
+        let position = self.offset + clock as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * KERNEL_PHASES as f64) as usize;
+
+        if self.deltas.len() < index + KERNEL_WIDTH {
+            self.deltas.resize(index + KERNEL_WIDTH, 0.0);
+        }
+
+        for (tap, weight) in kernel[phase].iter().enumerate() {
+            self.deltas[index + tap] += delta * weight;
+        }
+    }
+
+    // Integrates every sample that can no longer be affected by future deltas
+    fn end_frame(&mut self, clocks: u32, samples: &mut Vec<f32>) {
+        let end = self.offset + clocks as f64 * self.factor;
+        let count = end as usize;
+
+        if self.deltas.len() < count + KERNEL_WIDTH {
+            self.deltas.resize(count + KERNEL_WIDTH, 0.0);
+        }
+
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            samples.push(self.integrator);
+        }
+
+        self.offset = end - count as f64;
+    }
+}
+
+// Builds the windowed sinc impulse for each fractional phase
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half = (KERNEL_WIDTH / 2) as f64;
+
+    (0..KERNEL_PHASES)
+        .map(|phase| {
+            let fraction = phase as f64 / KERNEL_PHASES as f64;
+            let mut taps = [0.0; KERNEL_WIDTH];
+
+            for (tap, weight) in taps.iter_mut().enumerate() {
+                let t = tap as f64 - fraction - half + 1.0;
+                *weight = (sinc(KERNEL_CUTOFF * t) * blackman(t, half)) as f32;
+            }
+
+            // Normalize so each phase has unity gain
+            let sum: f32 = taps.iter().sum();
+            for weight in taps.iter_mut() {
+                *weight /= sum;
+            }
+
+            taps
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman(t: f64, half: f64) -> f64 {
+    if t.abs() >= half {
+        return 0.0;
+    }
+    0.42 + 0.5 * (PI * t / half).cos() + 0.08 * (2.0 * PI * t / half).cos()
+}
+
+/// High-pass filter
+///
+/// The console's output goes through a capacitor that removes any DC offset, so a channel with
+/// its DAC on but silent slowly decays back to 0 instead of sitting at a constant level.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Audio_details.html#obscure-behavior))
+struct HighPassFilter {
+    capacitor: f32,
+    charge_factor: f32,
+}
+
+impl HighPassFilter {
+    fn new(sample_rate: u32) -> HighPassFilter {
+        HighPassFilter {
+            capacitor: 0.0,
+            // Charge factor of the CGB capacitor per T-cycle, scaled to the output rate
+            charge_factor: 0.998943_f64.powf(CLOCK_RATE as f64 / sample_rate as f64) as f32,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge_factor;
+        output
+    }
+}
+
+// Converts the APU output into stereo samples at the host rate
+pub struct AudioOutput {
+    sample_rate: u32,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    left: BlipBuffer,
+    right: BlipBuffer,
+    high_pass_left: HighPassFilter,
+    high_pass_right: HighPassFilter,
+    high_pass_enabled: bool,
+    last_sample: (f32, f32),
+    clock: u32,
+    // Interleaved stereo samples (left, right) waiting to be drained
+    samples: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(sample_rate: u32) -> AudioOutput {
+        AudioOutput {
+            sample_rate,
+            kernel: build_kernel(),
+            left: BlipBuffer::new(sample_rate),
+            right: BlipBuffer::new(sample_rate),
+            high_pass_left: HighPassFilter::new(sample_rate),
+            high_pass_right: HighPassFilter::new(sample_rate),
+            high_pass_enabled: true,
+            last_sample: (0.0, 0.0),
+            clock: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_high_pass_enabled(&mut self, enabled: bool) {
+        self.high_pass_enabled = enabled;
+    }
+
+    // Advances the output by the given amount of T-cycles, ending at the given APU output
+    pub fn update(&mut self, cycles: u32, sample: (f32, f32)) {
+        self.clock += cycles;
+
+        if sample.0 != self.last_sample.0 {
+            self.left
+                .add_delta(&self.kernel, self.clock, sample.0 - self.last_sample.0);
+        }
+        if sample.1 != self.last_sample.1 {
+            self.right
+                .add_delta(&self.kernel, self.clock, sample.1 - self.last_sample.1);
+        }
+        self.last_sample = sample;
+
+        if self.clock >= MAX_FRAME_CLOCKS {
+            self.end_frame();
+        }
+    }
+
+    // Takes all the interleaved stereo samples generated so far
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.end_frame();
+        std::mem::take(&mut self.samples)
+    }
+
+    fn end_frame(&mut self) {
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+
+        self.left.end_frame(self.clock, &mut left);
+        self.right.end_frame(self.clock, &mut right);
+        self.clock = 0;
+
+        for (left, right) in left.into_iter().zip(right) {
+            let (left, right) = if self.high_pass_enabled {
+                (
+                    self.high_pass_left.filter(left),
+                    self.high_pass_right.filter(right),
+                )
+            } else {
+                (left, right)
+            };
+
+            self.samples.push(left.clamp(-1.0, 1.0));
+            self.samples.push(right.clamp(-1.0, 1.0));
+        }
+
+        // Drop the oldest samples if the frontend isn't consuming them
+        let max_samples = (self.sample_rate * MAX_BUFFERED_SECONDS * 2) as usize;
+        if self.samples.len() > max_samples {
+            let excess = self.samples.len() - max_samples;
+            self.samples.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A full-scale square wave, whose edges ring and, through the high-pass filter, swing
+    // beyond the range of the input
+    fn square_wave(output: &mut AudioOutput, period: u32, seconds: u32) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for half_period in 0..CLOCK_RATE * seconds / (period / 2) {
+            let level = if half_period % 2 == 0 { 1.0 } else { -1.0 };
+            output.update(period / 2, (level, -level));
+            samples.extend(output.take_samples());
+        }
+        samples
+    }
+
+    #[test]
+    fn output_stays_within_range() {
+        for high_pass_enabled in [true, false] {
+            let mut output = AudioOutput::new(DEFAULT_SAMPLE_RATE);
+            output.set_high_pass_enabled(high_pass_enabled);
+
+            let samples = square_wave(&mut output, 8192, 1);
+
+            assert!(samples.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+            assert!(samples.contains(&1.0));
+            assert!(samples.contains(&-1.0));
+        }
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        let mut output = AudioOutput::new(DEFAULT_SAMPLE_RATE);
+        output.update(CLOCK_RATE / 2, (1.0, 1.0));
+        output.update(CLOCK_RATE / 2, (1.0, 1.0));
+
+        let samples = output.take_samples();
+        assert!(samples.iter().any(|sample| *sample > 0.5));
+        assert!(samples[samples.len() - 2..]
+            .iter()
+            .all(|sample| sample.abs() < 0.01));
+    }
+}
@@ -0,0 +1,259 @@
+use super::units::{Envelope, LengthCounter, Sweep};
+use crate::core::save_state::{SaveStateError, StateReader, StateWriter};
+
+// Waveforms for each duty cycle (12.5%, 25%, 50%, 75%), one bit per step
+const DUTY_WAVEFORMS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Pulse channel (channels 1 and 2)
+///
+/// Register    Explanation                                                 \
+/// NRx0        Sweep, channel 1 only                                       \
+/// NRx1        Bits 7-6: duty cycle, bits 5-0: initial length timer        \
+/// NRx2        Volume envelope                                             \
+/// NRx3        Period low 8 bits (write-only)                              \
+/// NRx4        Bit 7: trigger, bit 6: length enable, bits 2-0: period high \
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Audio_Registers.html))
+pub struct PulseChannel {
+    pub enabled: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_step: u8,
+    period: u16,
+    timer: u32,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+}
+
+impl PulseChannel {
+    pub fn new(with_sweep: bool) -> PulseChannel {
+        PulseChannel {
+            enabled: false,
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    // Registers are indexed 0 to 4 (NRx0 to NRx4)
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 => self.sweep.as_ref().map_or(0xFF, |sweep| sweep.read()),
+            1 => 0x3F | (self.duty << 6),
+            2 => self.envelope.read(),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.write(data);
+                }
+            }
+            1 => {
+                self.duty = data >> 6;
+                self.length.load(data & 0x3F);
+            }
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.period = (self.period & 0x700) | data as u16,
+            4 => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x07) << 8);
+                self.length.enabled = data & 0x40 != 0;
+
+                if data & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.timer_reload();
+        self.length.trigger();
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.trigger(self.period) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.timer_reload();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            let (new_period, still_enabled) = sweep.clock();
+
+            if let Some(period) = new_period {
+                self.period = period;
+            }
+
+            if !still_enabled {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // Digital output, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = (DUTY_WAVEFORMS[self.duty as usize] >> (7 - self.duty_step)) & 1;
+        high * self.envelope.volume
+    }
+
+    fn timer_reload(&self) -> u32 {
+        (2048 - self.period as u32) * 4
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        if let Some(sweep) = &self.sweep {
+            sweep.save_state(writer);
+        }
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_step);
+        writer.write_u16(self.period);
+        writer.write_u32(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_state(reader)?;
+        }
+        self.duty = reader.read_u8()?;
+        self.duty_step = reader.read_u8()?;
+        if self.duty as usize >= DUTY_WAVEFORMS.len() || self.duty_step >= 8 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.period = reader.read_u16()?;
+        // Periods are 11 bits
+        if self.period > 0x7FF {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.timer = reader.read_u32()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Full volume, the given duty and the highest period, so steps are 4 T-cycles long
+    fn playing(duty: u8) -> PulseChannel {
+        let mut channel = PulseChannel::new(false);
+        channel.write(1, duty << 6);
+        channel.write(2, 0xF0);
+        channel.write(3, 0xFF);
+        channel.write(4, 0x87);
+        channel
+    }
+
+    #[test]
+    fn outputs_the_duty_waveform() {
+        let mut channel = playing(2);
+
+        let steps: Vec<u8> = (0..8)
+            .map(|_| {
+                let output = channel.output();
+                channel.tick(4);
+                output
+            })
+            .collect();
+        assert_eq!(steps, [15, 0, 0, 0, 0, 15, 15, 15]);
+    }
+
+    #[test]
+    fn dac_off_silences_the_channel() {
+        let mut channel = playing(3);
+        assert!(channel.enabled);
+
+        channel.write(2, 0x00);
+        assert!(!channel.enabled);
+        assert!(!channel.dac_enabled());
+        assert_eq!(channel.output(), 0);
+
+        // Triggering doesn't enable it without the DAC
+        channel.write(4, 0x80);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        let mut channel = PulseChannel::new(true);
+        channel.write(0, 0x11);
+        channel.write(2, 0xF0);
+        channel.write(3, 0x00);
+
+        channel.write(4, 0x86);
+        assert!(!channel.enabled);
+
+        // Period 0x500 goes to 0x780, which would overflow at the next step
+        channel.write(4, 0x85);
+        assert!(channel.enabled);
+        channel.clock_sweep();
+        assert_eq!(channel.period, 0x780);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let mut writer = StateWriter::new();
+        playing(1).save_state(&mut writer);
+        let state = writer.into_bytes();
+
+        let load = |state: &[u8]| PulseChannel::new(false).load_state(&mut StateReader::new(state));
+        assert!(load(&state).is_ok());
+
+        // Duty and duty step follow the enabled flag
+        for (index, value) in [(1, 4), (2, 8)] {
+            let mut state = state.clone();
+            state[index] = value;
+            assert!(load(&state).is_err());
+        }
+    }
+}
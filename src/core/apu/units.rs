@@ -0,0 +1,351 @@
+// Building blocks shared by the sound channels, clocked by the frame sequencer
+
+use crate::core::save_state::{SaveStateError, StateReader, StateWriter};
+
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    // The length register holds the number of ticks already elapsed
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - length as u16;
+    }
+
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // Returns true when the counter expires and the channel should be disabled
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+        writer.write_bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = reader.read_u16()?;
+        if self.counter > self.max {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// Volume envelope (NRx2)
+///
+/// Bit    Explanation                  \
+/// 7-4    Initial volume               \
+/// 3      Direction (0 = down, 1 = up) \
+/// 2-0    Sweep pace (0 = disabled)    \
+pub struct Envelope {
+    register: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+    }
+
+    // The DAC is only powered while the initial volume or direction bits are set
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.pace();
+    }
+
+    pub fn clock(&mut self) {
+        if self.pace() == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.pace();
+
+            if self.register & 0x08 != 0 {
+                if self.volume < 15 {
+                    self.volume += 1;
+                }
+            } else if self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn pace(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = reader.read_u8()?;
+        self.volume = reader.read_u8()?;
+        if self.volume > 15 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+/// Frequency sweep (NR10), only available on channel 1
+///
+/// Bit    Explanation                            \
+/// 6-4    Pace, in 128 Hz ticks (0 = disabled)   \
+/// 3      Direction (0 = increase, 1 = decrease) \
+/// 2-0    Individual step                        \
+pub struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow_period: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    pub fn new() -> Sweep {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow_period: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        0x80 | self.register
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data & 0x7F;
+    }
+
+    // Returns false if the initial overflow check disables the channel
+    pub fn trigger(&mut self, period: u16) -> bool {
+        self.shadow_period = period;
+        self.timer = self.timer_reload();
+        self.enabled = self.pace() != 0 || self.step() != 0;
+
+        self.step() == 0 || self.next_period() <= 2047
+    }
+
+    // Returns the new period (if it changed) and whether the channel is still enabled
+    pub fn clock(&mut self) -> (Option<u16>, bool) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer > 0 {
+            return (None, true);
+        }
+
+        self.timer = self.timer_reload();
+
+        if !self.enabled || self.pace() == 0 {
+            return (None, true);
+        }
+
+        let new_period = self.next_period();
+
+        if new_period > 2047 {
+            return (None, false);
+        }
+
+        if self.step() == 0 {
+            return (None, true);
+        }
+
+        self.shadow_period = new_period;
+
+        // The overflow check is run again with the new period, without writing it back
+        (Some(new_period), self.next_period() <= 2047)
+    }
+
+    fn next_period(&self) -> u16 {
+        let delta = self.shadow_period >> self.step();
+
+        if self.register & 0x08 != 0 {
+            self.shadow_period.wrapping_sub(delta)
+        } else {
+            self.shadow_period + delta
+        }
+    }
+
+    // A pace of 0 reloads the timer with 8
+    fn timer_reload(&self) -> u8 {
+        match self.pace() {
+            0 => 8,
+            pace => pace,
+        }
+    }
+
+    fn pace(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn step(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.shadow_period);
+        writer.write_u8(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = reader.read_u8()?;
+        self.enabled = reader.read_bool()?;
+        self.shadow_period = reader.read_u16()?;
+        // Periods are 11 bits, bit 7 of NR10 is unused
+        if self.register > 0x7F || self.shadow_period > 0x7FF {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counter_expires() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+
+        // Only counts down while enabled
+        assert!(!length.clock());
+        length.enabled = true;
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+
+        // Triggering an expired counter reloads it with the maximum
+        length.trigger();
+        assert_eq!(length.counter, 64);
+    }
+
+    #[test]
+    fn envelope_steps_at_its_pace() {
+        let mut envelope = Envelope::new();
+
+        // Volume 15, going down every 2 ticks
+        envelope.write(0xF2);
+        envelope.trigger();
+        let volumes: Vec<u8> = (0..4)
+            .map(|_| {
+                envelope.clock();
+                envelope.volume
+            })
+            .collect();
+        assert_eq!(volumes, [15, 14, 14, 13]);
+
+        // Volume 14, going up every tick, stops at 15
+        envelope.write(0xE9);
+        envelope.trigger();
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume, 15);
+
+        // A pace of 0 never changes the volume
+        envelope.write(0x70);
+        envelope.trigger();
+        (0..8).for_each(|_| envelope.clock());
+        assert_eq!(envelope.volume, 7);
+    }
+
+    #[test]
+    fn envelope_powers_the_dac() {
+        let mut envelope = Envelope::new();
+
+        assert!(!envelope.dac_enabled());
+        envelope.write(0x08);
+        assert!(envelope.dac_enabled());
+        envelope.write(0x07);
+        assert!(!envelope.dac_enabled());
+    }
+
+    #[test]
+    fn sweep_overflow_disables_the_channel() {
+        let mut sweep = Sweep::new();
+        // Every tick, increasing by period / 2
+        sweep.write(0x11);
+
+        // The check on trigger already overflows
+        assert!(!sweep.trigger(0x600));
+
+        // The new period is written back, but the check after it overflows
+        assert!(sweep.trigger(0x500));
+        assert_eq!(sweep.clock(), (Some(0x780), false));
+
+        // Decreasing never overflows
+        sweep.write(0x19);
+        assert!(sweep.trigger(0x7FF));
+        assert_eq!(sweep.clock(), (Some(0x400), true));
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        // Shadow periods are 11 bits
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x11);
+        writer.write_bool(true);
+        writer.write_u16(0x800);
+        writer.write_u8(1);
+        let state = writer.into_bytes();
+        let sweep = Sweep::new().load_state(&mut StateReader::new(&state));
+        assert!(sweep.is_err());
+
+        let envelope = |state: &[u8]| Envelope::new().load_state(&mut StateReader::new(state));
+        assert!(envelope(&[0xF0, 15, 0]).is_ok());
+        assert!(envelope(&[0xF0, 16, 0]).is_err());
+
+        let length = |state: &[u8]| LengthCounter::new(64).load_state(&mut StateReader::new(state));
+        assert!(length(&[64, 0, 0]).is_ok());
+        assert!(length(&[65, 0, 0]).is_err());
+    }
+}
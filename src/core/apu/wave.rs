@@ -0,0 +1,216 @@
+use super::units::LengthCounter;
+use crate::core::save_state::{SaveStateError, StateReader, StateWriter};
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+/// Wave channel (channel 3)
+///
+/// Register    Explanation                                                 \
+/// NR30        Bit 7: DAC on/off                                           \
+/// NR31        Initial length timer (write-only)                           \
+/// NR32        Bits 6-5: output level (mute, 100%, 50%, 25%)               \
+/// NR33        Period low 8 bits (write-only)                              \
+/// NR34        Bit 7: trigger, bit 6: length enable, bits 2-0: period high \
+///
+/// Wave RAM (0xFF30 to 0xFF3F) holds 32 4-bit samples, upper nibble first.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Audio_Registers.html))
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    output_level: u8,
+    period: u16,
+    timer: u32,
+    position: u8,
+    sample: u8,
+    pub length: LengthCounter,
+    pub wave_ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            period: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: LengthCounter::new(256),
+            wave_ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    // Registers are indexed 0 to 4 (NR30 to NR34)
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 => 0x7F | ((self.dac_enabled as u8) << 7),
+            1 => 0xFF,
+            2 => 0x9F | (self.output_level << 5),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = data & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(data),
+            2 => self.output_level = (data >> 5) & 0x03,
+            3 => self.period = (self.period & 0x700) | data as u16,
+            4 => {
+                self.period = (self.period & 0xFF) | ((data as u16 & 0x07) << 8);
+                self.length.enabled = data & 0x40 != 0;
+
+                if data & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.timer_reload();
+        self.position = 0;
+        self.length.trigger();
+    }
+
+    pub fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.timer_reload();
+            self.position = (self.position + 1) % 32;
+
+            let byte = self.wave_ram[self.position as usize / 2];
+            self.sample = if self.position.is_multiple_of(2) {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // Digital output, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.output_level {
+            0 => 0,
+            level => self.sample >> (level - 1),
+        }
+    }
+
+    fn timer_reload(&self) -> u32 {
+        (2048 - self.period as u32) * 2
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.dac_enabled);
+        writer.write_u8(self.output_level);
+        writer.write_u16(self.period);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.position);
+        writer.write_u8(self.sample);
+        self.length.save_state(writer);
+        writer.write_bytes(&self.wave_ram);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.dac_enabled = reader.read_bool()?;
+        self.output_level = reader.read_u8()?;
+        if self.output_level > 3 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.period = reader.read_u16()?;
+        // Periods are 11 bits
+        if self.period > 0x7FF {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.timer = reader.read_u32()?;
+        self.position = reader.read_u8()?;
+        self.sample = reader.read_u8()?;
+        // 32 samples of 4 bits
+        if self.position >= 32 || self.sample > 0x0F {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.length.load_state(reader)?;
+        reader.read_bytes(&mut self.wave_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Playing at the highest period, so samples are 2 T-cycles long
+    fn playing(output_level: u8) -> WaveChannel {
+        let mut channel = WaveChannel::new();
+        channel.wave_ram[0] = 0x0C;
+        channel.write(0, 0x80);
+        channel.write(2, output_level << 5);
+        channel.write(3, 0xFF);
+        channel.write(4, 0x87);
+        channel
+    }
+
+    #[test]
+    fn output_level_shifts_samples() {
+        for (level, output) in [(0, 0), (1, 12), (2, 6), (3, 3)] {
+            let mut channel = playing(level);
+            // The second sample, the low nibble of the first byte
+            channel.tick(2);
+            assert_eq!(channel.output(), output);
+        }
+    }
+
+    #[test]
+    fn dac_off_silences_the_channel() {
+        let mut channel = playing(1);
+        channel.tick(2);
+
+        channel.write(0, 0x00);
+        assert!(!channel.enabled);
+        assert_eq!(channel.output(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let mut writer = StateWriter::new();
+        playing(1).save_state(&mut writer);
+        let state = writer.into_bytes();
+
+        let load = |state: &[u8]| WaveChannel::new().load_state(&mut StateReader::new(state));
+        assert!(load(&state).is_ok());
+
+        // Output level, then position and sample after the period and timer
+        for (index, value) in [(2, 4), (9, 32), (10, 0x10)] {
+            let mut state = state.clone();
+            state[index] = value;
+            assert!(load(&state).is_err());
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Memory bus seen by the CPU
+//!
+//! The CPU only talks to the rest of the machine through this trait, so the same instruction
+//! implementation runs on the full GBC memory map, on flat RAM for CPU tests, or wrapped by a
+//! bus recording every access. Every read, write and tick is one machine cycle (4 T-cycles),
+//! which is when the rest of the system is advanced.
+
+use crate::core::interrupts::Interrupt;
+
+pub trait Bus {
+    // Reads a byte, taking one machine cycle
+    fn read(&mut self, address: u16) -> u8;
+    // Writes a byte, taking one machine cycle
+    fn write(&mut self, address: u16, data: u8);
+    // A machine cycle where the CPU doesn't access the bus
+    fn tick(&mut self);
+    // Reads a byte without ticking or side effects, for tools inspecting memory
+    fn peek(&self, address: u16) -> u8;
+
+    // Interrupts both requested and enabled (IF & IE), buses without interrupts never have any
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+    // Clears the request of an interrupt that is being dispatched
+    fn acknowledge_interrupt(&mut self, _interrupt: Interrupt) {}
+}
+
+// Lets a bus be wrapped without giving up ownership of it
+impl<B: Bus + ?Sized> Bus for &mut B {
+    fn read(&mut self, address: u16) -> u8 {
+        (**self).read(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        (**self).write(address, data);
+    }
+
+    fn tick(&mut self) {
+        (**self).tick();
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        (**self).peek(address)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        (**self).pending_interrupts()
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        (**self).acknowledge_interrupt(interrupt);
+    }
+}
+
+// A single machine cycle of bus activity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BusCycle {
+    Read { address: u16, data: u8 },
+    Write { address: u16, data: u8 },
+    // The CPU is busy and doesn't access the bus
+    Internal,
+}
+
+// Forwards everything to another bus, recording the activity of every machine cycle
+pub struct TracingBus<B: Bus> {
+    pub inner: B,
+    // Every cycle since the last call to take_cycles
+    cycles: Vec<BusCycle>,
+}
+
+impl<B: Bus> TracingBus<B> {
+    pub fn new(inner: B) -> TracingBus<B> {
+        TracingBus {
+            inner,
+            cycles: Vec::new(),
+        }
+    }
+
+    pub fn take_cycles(&mut self) -> Vec<BusCycle> {
+        std::mem::take(&mut self.cycles)
+    }
+}
+
+impl<B: Bus> Bus for TracingBus<B> {
+    fn read(&mut self, address: u16) -> u8 {
+        let data = self.inner.read(address);
+        self.cycles.push(BusCycle::Read { address, data });
+        data
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.inner.write(address, data);
+        self.cycles.push(BusCycle::Write { address, data });
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick();
+        self.cycles.push(BusCycle::Internal);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.inner.peek(address)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.inner.pending_interrupts()
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.inner.acknowledge_interrupt(interrupt);
+    }
+}
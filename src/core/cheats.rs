@@ -0,0 +1,427 @@
+//! Cheat codes
+//!
+//! GameShark codes write to RAM every frame, Game Genie codes patch what the CPU reads from
+//! the ROM. Codes are kept per ROM in a file named like it with the .cht extension, loaded
+//! alongside it, one code per line:
+//!
+//! Line                       Explanation                                                  \
+//! ; comment                  Ignored, as is anything after a ; on other lines             \
+//! 01FF38CD Infinite lives    A code, followed by an optional name                         \
+//! -01FF38CD Infinite lives   A disabled code                                              \
+//!
+//! GameShark    Explanation                                                                \
+//! ttvvaaaa     tt: 01 or, for 0xD000-0xDFFF, 8x or 9x for WRAM bank x, vv: value,         \
+//!              aaaa: address, low byte first                                              \
+//!
+//! Game Genie   Explanation                                                                \
+//! ABC-DEF      AB: value, FCDE: address, with F XORed with 0xF                            \
+//! ABC-DEF-GHI  Same, only when the ROM reads GI rotated right by 2 and XORed with 0xBA,   \
+//!              H is a check digit and ignored                                             \
+//!
+//! Game Genie codes patch every ROM bank, the compare byte tells the banks apart.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::core::memory_map::{ERAM_START, ROM_BANK_END};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CheatCode {
+    GameShark {
+        // WRAM bank for 0xD000-0xDFFF, otherwise the current banks are written
+        bank: Option<usize>,
+        address: u16,
+        value: u8,
+    },
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+}
+
+impl CheatCode {
+    pub fn parse(text: &str) -> Result<CheatCode, String> {
+        let digits = text.replace('-', "");
+        if !digits.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(format!("Invalid cheat code: {}", text));
+        }
+
+        match digits.len() {
+            8 if !text.contains('-') => CheatCode::parse_game_shark(&digits),
+            6 | 9 => CheatCode::parse_game_genie(&digits),
+            _ => Err(format!("Invalid cheat code: {}", text)),
+        }
+    }
+
+    fn parse_game_shark(digits: &str) -> Result<CheatCode, String> {
+        let byte =
+            |index: usize| u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16).unwrap();
+
+        let bank = match byte(0) {
+            bank @ (0x80..=0x87 | 0x90..=0x97) => Some((bank & 0x07) as usize),
+            _ => None,
+        };
+        let address = combine!(byte(3), byte(2));
+        // Only RAM can be written
+        if address < ERAM_START {
+            return Err(format!("Not a RAM address: {:04X}", address));
+        }
+
+        Ok(CheatCode::GameShark {
+            bank,
+            address,
+            value: byte(1),
+        })
+    }
+
+    fn parse_game_genie(digits: &str) -> Result<CheatCode, String> {
+        let digit = |index: usize| u8::from_str_radix(&digits[index..index + 1], 16).unwrap();
+
+        let value = digit(0) << 4 | digit(1);
+        let address = ((digit(5) ^ 0x0F) as u16) << 12
+            | (digit(2) as u16) << 8
+            | (digit(3) as u16) << 4
+            | digit(4) as u16;
+        // Only ROM can be patched
+        if address > ROM_BANK_END {
+            return Err(format!("Not a ROM address: {:04X}", address));
+        }
+        let compare =
+            (digits.len() == 9).then(|| (digit(6) << 4 | digit(8)).rotate_right(2) ^ 0xBA);
+
+        Ok(CheatCode::GameGenie {
+            address,
+            value,
+            compare,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cheat {
+    // As entered, written back to the cheat file
+    pub text: String,
+    pub name: String,
+    pub code: CheatCode,
+    pub enabled: bool,
+}
+
+#[derive(Clone)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    // Cheat file the codes are saved to
+    path: Option<PathBuf>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats {
+            cheats: Vec::new(),
+            path: None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (enabled, line) = match line.strip_prefix('-') {
+                Some(line) => (false, line),
+                None => (true, line),
+            };
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+            let index = cheats
+                .add(code, name.trim())
+                .map_err(|error| format!("Line {}: {}", index + 1, error))?;
+            cheats.set_enabled(index, enabled);
+        }
+
+        Ok(cheats)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Cheats> {
+        let mut cheats = Cheats::parse(&fs::read_to_string(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        cheats.path = Some(path.to_path_buf());
+        Ok(cheats)
+    }
+
+    // The cheat file next to a ROM, e.g. game.cht. Without one, or if it can't be read, there
+    // are no codes but they are still saved there
+    pub fn for_rom(rom_path: &Path) -> Cheats {
+        let path = rom_path.with_extension("cht");
+        let empty = Cheats {
+            cheats: Vec::new(),
+            path: Some(path.clone()),
+        };
+        if !path.exists() {
+            return empty;
+        }
+
+        match Cheats::load(&path) {
+            Ok(cheats) => {
+                println!("Loaded {} cheats from {}", cheats.len(), path.display());
+                cheats
+            }
+            Err(error) => {
+                println!("Unable to load cheats from {}: {}", path.display(), error);
+                empty
+            }
+        }
+    }
+
+    // Writes the codes back to the file they were loaded from
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no cheat file"));
+        };
+
+        let text: String = self
+            .cheats
+            .iter()
+            .map(|cheat| {
+                let disabled = if cheat.enabled { "" } else { "-" };
+                let line = format!("{}{} {}", disabled, cheat.text, cheat.name);
+                format!("{}\n", line.trim_end())
+            })
+            .collect();
+        fs::write(path, text)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    // Returns the index of the new code, enabled
+    pub fn add(&mut self, text: &str, name: &str) -> Result<usize, String> {
+        let code = CheatCode::parse(text)?;
+
+        self.cheats.push(Cheat {
+            text: text.to_uppercase(),
+            name: name.to_string(),
+            code,
+            enabled: true,
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.cheats.len() {
+            return false;
+        }
+        self.cheats.remove(index);
+        true
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = CheatCode> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| cheat.code)
+    }
+
+    // What the CPU reads from the ROM, data being the byte actually there
+    pub fn patch_rom(&self, address: u16, data: u8) -> u8 {
+        self.enabled()
+            .find_map(|code| match code {
+                CheatCode::GameGenie {
+                    address: patched,
+                    value,
+                    compare,
+                } if patched == address && compare.is_none_or(|compare| compare == data) => {
+                    Some(value)
+                }
+                _ => None,
+            })
+            .unwrap_or(data)
+    }
+
+    // Bank, address and value of the RAM writes to do every frame
+    pub fn ram_writes(&self) -> Vec<(Option<usize>, u16, u8)> {
+        self.enabled()
+            .filter_map(|code| match code {
+                CheatCode::GameShark {
+                    bank,
+                    address,
+                    value,
+                } => Some((bank, address, value)),
+                CheatCode::GameGenie { .. } => None,
+            })
+            .collect()
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Cheats {
+        Cheats::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn parses_game_shark() {
+        assert_eq!(
+            CheatCode::parse("01FF38CD"),
+            Ok(CheatCode::GameShark {
+                bank: None,
+                address: 0xCD38,
+                value: 0xFF,
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("9163a0d4"),
+            Ok(CheatCode::GameShark {
+                bank: Some(1),
+                address: 0xD4A0,
+                value: 0x63,
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("80010000").map(|_| ()),
+            Err("Not a RAM address: 0000".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_game_genie() {
+        assert_eq!(
+            CheatCode::parse("01A-23E"),
+            Ok(CheatCode::GameGenie {
+                address: 0x1A23,
+                value: 0x01,
+                compare: None,
+            })
+        );
+        // 0xB6 rotated right by 2 is 0xAD, XORed with 0xBA
+        assert_eq!(
+            CheatCode::parse("01A-23E-B76"),
+            Ok(CheatCode::GameGenie {
+                address: 0x1A23,
+                value: 0x01,
+                compare: Some(0x17),
+            })
+        );
+        assert!(CheatCode::parse("01A-230").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        for text in [
+            "",
+            "01FF38C",
+            "01FF38CD00",
+            "01FF-38CD",
+            "01G-23E",
+            "01A-23E-B7",
+        ] {
+            assert!(CheatCode::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn patches_the_rom() {
+        let mut cheats = Cheats::new();
+        cheats.add("01A-23E-B76", "").unwrap();
+        cheats.add("02A-24E", "").unwrap();
+
+        // Only when the compare byte is there
+        assert_eq!(cheats.patch_rom(0x1A23, 0x17), 0x01);
+        assert_eq!(cheats.patch_rom(0x1A23, 0x18), 0x18);
+        assert_eq!(cheats.patch_rom(0x1A24, 0x18), 0x02);
+        assert_eq!(cheats.patch_rom(0x1A25, 0x18), 0x18);
+
+        cheats.set_enabled(1, false);
+        assert_eq!(cheats.patch_rom(0x1A24, 0x18), 0x18);
+    }
+
+    #[test]
+    fn lists_ram_writes() {
+        let mut cheats = Cheats::new();
+        cheats.add("01FF38CD", "").unwrap();
+        cheats.add("01A-23E", "").unwrap();
+        cheats.add("9263A0D4", "").unwrap();
+        cheats.add("010100C0", "").unwrap();
+        cheats.set_enabled(3, false);
+
+        assert_eq!(
+            cheats.ram_writes(),
+            [(None, 0xCD38, 0xFF), (Some(2), 0xD4A0, 0x63)]
+        );
+    }
+
+    #[test]
+    fn parses_cheat_files() {
+        let cheats = Cheats::parse(
+            "; Cheats\n01FF38CD Infinite lives ; 99\n\n-01a-23e  Level select\n01A-23E-B76\n",
+        )
+        .unwrap();
+
+        let cheats = cheats.cheats();
+        assert_eq!(cheats.len(), 3);
+        assert_eq!(cheats[0].name, "Infinite lives");
+        assert!(cheats[0].enabled);
+        assert_eq!(cheats[1].text, "01A-23E");
+        assert_eq!(cheats[1].name, "Level select");
+        assert!(!cheats[1].enabled);
+        assert_eq!(cheats[2].name, "");
+
+        assert_eq!(
+            Cheats::parse("01FF38CD\nnonsense\n").map(|_| ()),
+            Err("Line 2: Invalid cheat code: nonsense".to_string())
+        );
+    }
+
+    #[test]
+    fn saves_cheat_files() {
+        let path = env::temp_dir().join(format!("gioboycolor-cheats-{}.cht", process::id()));
+        let mut cheats = Cheats::new();
+        cheats.path = Some(path.clone());
+        cheats.add("01ff38cd", "Infinite lives").unwrap();
+        cheats.add("01A-23E", "").unwrap();
+        cheats.set_enabled(1, false);
+        cheats.save().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = Cheats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(text, "01FF38CD Infinite lives\n-01A-23E\n");
+        assert_eq!(loaded.cheats(), cheats.cheats());
+        assert!(Cheats::new().save().is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Compatibility palettes
+//!
+//! When a monochrome (DMG) cartridge is started on a CGB, the boot ROM colorizes it by writing
+//! one background palette and two object palettes into CGB palette RAM. The palettes are picked
+//! from a table indexed by the checksum of the title in the cartridge header, but only for games
+//! published by Nintendo. Some checksums are shared by several titles, in which case the 4th
+//! letter of the title is used to tell them apart.
+//!
+//! Holding a direction (optionally with A or B) while the boot logo is displayed overrides the
+//! automatic choice with one of 12 fixed palette combinations.
+//!
+//! (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes))
+
+use super::{joypad::Buttons, rom::Rom};
+
+// 4 colors in CGB format (RGB555, little endian in palette RAM), lightest to darkest
+pub type Palette = [u16; 4];
+
+// Palette data as stored in the CGB boot ROM
+#[rustfmt::skip]
+const PALETTES: [Palette; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000], // 0
+    [0x639F, 0x4279, 0x15B0, 0x04CB], // 1
+    [0x7FFF, 0x6E31, 0x454A, 0x0000], // 2
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000], // 3
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000], // 4
+    [0x7FFF, 0x5294, 0x294A, 0x0000], // 5
+    [0x7FFF, 0x03FF, 0x012F, 0x0000], // 6
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000], // 7
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000], // 8
+    [0x7E74, 0x03FF, 0x0180, 0x0000], // 9
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B], // 10
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000], // 11
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000], // 12
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0], // 13
+    [0x03ED, 0x7FFF, 0x255F, 0x0000], // 14
+    [0x036A, 0x021F, 0x03FF, 0x7FFF], // 15
+    [0x7FFF, 0x01DF, 0x0112, 0x0000], // 16
+    [0x231F, 0x035F, 0x00F2, 0x0009], // 17
+    [0x7FFF, 0x03EA, 0x011F, 0x0000], // 18
+    [0x299F, 0x001A, 0x000C, 0x0000], // 19
+    [0x7FFF, 0x027F, 0x001F, 0x0000], // 20
+    [0x7FFF, 0x03E0, 0x0206, 0x0120], // 21
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00], // 22
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F], // 23
+    [0x7FFF, 0x03FF, 0x001F, 0x0000], // 24
+    [0x03FF, 0x001F, 0x000C, 0x0000], // 25
+    [0x7FFF, 0x033F, 0x0193, 0x0000], // 26
+    [0x0000, 0x4200, 0x037F, 0x7FFF], // 27
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000], // 28
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000], // 29
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PaletteSet {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+// 4 colors starting at any color of the palette data, some combinations straddle two palettes
+const fn colors_at(offset: usize) -> Palette {
+    let mut palette = [0; 4];
+    let mut index = 0;
+    while index < 4 {
+        let color = offset + index;
+        palette[index] = PALETTES[color / 4][color % 4];
+        index += 1;
+    }
+    palette
+}
+
+// Palette indices, in the order the boot ROM stores them
+const fn combination(obj0: usize, obj1: usize, bg: usize) -> PaletteSet {
+    raw_combination(obj0 * 4, obj1 * 4, bg * 4)
+}
+
+// Color offsets into the palette data
+const fn raw_combination(obj0: usize, obj1: usize, bg: usize) -> PaletteSet {
+    PaletteSet {
+        bg: colors_at(bg),
+        obj0: colors_at(obj0),
+        obj1: colors_at(obj1),
+    }
+}
+
+// Palette combinations as stored in the CGB boot ROM
+#[rustfmt::skip]
+const COMBINATIONS: [PaletteSet; 51] = [
+    combination(4, 4, 29),                          // 0
+    combination(18, 18, 18),                        // 1
+    combination(20, 20, 20),                        // 2
+    combination(24, 24, 24),                        // 3
+    combination(9, 9, 9),                           // 4
+    combination(0, 0, 0),                           // 5
+    combination(27, 27, 27),                        // 6
+    combination(5, 5, 5),                           // 7
+    combination(12, 12, 12),                        // 8
+    combination(26, 26, 26),                        // 9
+    combination(16, 8, 8),                          // 10
+    combination(4, 28, 28),                         // 11
+    combination(4, 2, 2),                           // 12
+    combination(3, 4, 4),                           // 13
+    combination(4, 29, 29),                         // 14
+    combination(28, 4, 28),                         // 15
+    combination(2, 17, 2),                          // 16
+    combination(16, 16, 8),                         // 17
+    combination(4, 4, 7),                           // 18
+    combination(4, 4, 18),                          // 19
+    combination(4, 4, 20),                          // 20
+    combination(19, 19, 9),                         // 21
+    raw_combination(4 * 4 - 1, 4 * 4 - 1, 11 * 4),  // 22
+    combination(17, 17, 2),                         // 23
+    combination(4, 4, 2),                           // 24
+    combination(4, 4, 3),                           // 25
+    combination(28, 28, 0),                         // 26
+    combination(3, 3, 0),                           // 27
+    combination(0, 0, 1),                           // 28
+    combination(18, 22, 18),                        // 29
+    combination(20, 22, 20),                        // 30
+    combination(24, 22, 24),                        // 31
+    combination(16, 22, 8),                         // 32
+    combination(17, 4, 13),                         // 33
+    raw_combination(28 * 4 - 1, 0, 14 * 4),         // 34
+    raw_combination(28 * 4 - 1, 4 * 4, 15 * 4),     // 35
+    raw_combination(19 * 4, 23 * 4 - 1, 9 * 4),     // 36
+    combination(16, 28, 10),                        // 37
+    combination(4, 23, 28),                         // 38
+    combination(17, 22, 2),                         // 39
+    combination(4, 0, 2),                           // 40
+    combination(4, 28, 3),                          // 41
+    combination(28, 3, 0),                          // 42
+    combination(3, 28, 4),                          // 43
+    combination(21, 28, 4),                         // 44
+    combination(3, 28, 0),                          // 45
+    combination(25, 3, 28),                         // 46
+    combination(0, 28, 8),                          // 47
+    combination(4, 3, 28),                          // 48
+    combination(28, 3, 6),                          // 49
+    combination(4, 28, 29),                         // 50
+];
+
+// Button combination held while the boot logo is displayed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootCombo {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl BootCombo {
+    pub const ALL: [BootCombo; 12] = [
+        BootCombo::Up,
+        BootCombo::UpA,
+        BootCombo::UpB,
+        BootCombo::Left,
+        BootCombo::LeftA,
+        BootCombo::LeftB,
+        BootCombo::Down,
+        BootCombo::DownA,
+        BootCombo::DownB,
+        BootCombo::Right,
+        BootCombo::RightA,
+        BootCombo::RightB,
+    ];
+
+    pub fn palettes(self) -> PaletteSet {
+        let combination = match self {
+            BootCombo::Up => 5,
+            BootCombo::UpA => 43,
+            BootCombo::UpB => 28,
+            BootCombo::Left => 48,
+            BootCombo::LeftA => 40,
+            BootCombo::LeftB => 7,
+            BootCombo::Down => 8,
+            BootCombo::DownA => 3,
+            BootCombo::DownB => 49,
+            BootCombo::Right => 1,
+            BootCombo::RightA => 0,
+            BootCombo::RightB => 6,
+        };
+        COMBINATIONS[combination]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BootCombo::Up => "Brown",
+            BootCombo::UpA => "Red",
+            BootCombo::UpB => "Dark Brown",
+            BootCombo::Left => "Blue",
+            BootCombo::LeftA => "Dark Blue",
+            BootCombo::LeftB => "Grayscale",
+            BootCombo::Down => "Pastel Mix",
+            BootCombo::DownA => "Orange",
+            BootCombo::DownB => "Yellow",
+            BootCombo::Right => "Green",
+            BootCombo::RightA => "Dark Green",
+            BootCombo::RightB => "Inverted",
+        }
+    }
+
+    // Returns the combination for the held buttons, if any. A direction must be held.
+    pub fn from_buttons(
+        up: bool,
+        down: bool,
+        left: bool,
+        right: bool,
+        a: bool,
+        b: bool,
+    ) -> Option<BootCombo> {
+        let (plain, with_a, with_b) = if up {
+            (BootCombo::Up, BootCombo::UpA, BootCombo::UpB)
+        } else if left {
+            (BootCombo::Left, BootCombo::LeftA, BootCombo::LeftB)
+        } else if down {
+            (BootCombo::Down, BootCombo::DownA, BootCombo::DownB)
+        } else if right {
+            (BootCombo::Right, BootCombo::RightA, BootCombo::RightB)
+        } else {
+            return None;
+        };
+
+        Some(match (a, b) {
+            (true, _) => with_a,
+            (false, true) => with_b,
+            (false, false) => plain,
+        })
+    }
+}
+
+// Title checksums, the last ones are shared by several titles
+const SHARED_CHECKSUMS_START: usize = 65;
+
+#[rustfmt::skip]
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58,
+    0xC9, 0x3E, 0x70, 0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95,
+    0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97, 0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6,
+    0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE, 0x0C, 0x29, 0xE8, 0xB7,
+    0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F, 0x6B,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D,
+    0xF4, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF,
+    0x0D, 0xF4, 0xB3,
+];
+
+// 4th letter of the title for each shared checksum
+#[rustfmt::skip]
+const FOURTH_LETTERS: [u8; 29] = [
+    b'B', b'E', b'F', b'A', b'A', b'R', b'B', b'E', b'K', b'E', b'K', b' ', b'R', b'-', b'U',
+    b'R', b'A', b'R', b' ', b'I', b'N', b'A', b'I', b'L', b'I', b'C', b'E', b' ', b'R',
+];
+
+// Palette combination for each title checksum
+#[rustfmt::skip]
+const TITLE_COMBINATIONS: [usize; 94] = [
+     0,  4,  5, 35, 34,  3, 31, 15, 10,  5, 19, 36,  7, 37, 30, 44,
+    21, 32, 31, 20,  5, 33, 13, 14,  5, 29,  5, 18,  9,  3,  2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+     5, 42,  6,  5, 33, 25, 42, 42, 40,  2, 16, 25, 42, 42,  5,  0,
+    39, 36, 22, 25,  6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46,  6, 27,  0, 47, 41, 41,  0,  0, 19, 34, 23, 18, 29,
+];
+
+// Looks up the palettes the boot ROM assigns to a Nintendo title
+pub fn palettes_for_title(checksum: u8, fourth_letter: u8) -> Option<PaletteSet> {
+    TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .find(|&(index, &entry)| {
+            entry == checksum
+                && (index < SHARED_CHECKSUMS_START
+                    || FOURTH_LETTERS[index - SHARED_CHECKSUMS_START] == fourth_letter)
+        })
+        .map(|(index, _)| COMBINATIONS[TITLE_COMBINATIONS[index]])
+}
+
+pub struct CompatPaletteConfig {
+    // Emulates holding a button combination during boot, overriding the title lookup
+    pub boot_combo: Option<BootCombo>,
+    // Used for games that are not in the title table or not published by Nintendo
+    pub fallback: BootCombo,
+    // Combination held on the joypad when the ROM was loaded
+    held_combo: Option<BootCombo>,
+}
+
+impl CompatPaletteConfig {
+    pub fn new() -> CompatPaletteConfig {
+        CompatPaletteConfig {
+            boot_combo: None,
+            // The boot ROM's default palette matches the Right + A combination
+            fallback: BootCombo::RightA,
+            held_combo: None,
+        }
+    }
+
+    pub fn latch_buttons(&mut self, buttons: &Buttons) {
+        self.held_combo = BootCombo::from_buttons(
+            buttons.up,
+            buttons.down,
+            buttons.left,
+            buttons.right,
+            buttons.a,
+            buttons.b,
+        );
+    }
+
+    pub fn select(&self, rom: &Rom) -> PaletteSet {
+        if let Some(combo) = self.boot_combo.or(self.held_combo) {
+            return combo.palettes();
+        }
+
+        if rom.has_nintendo_licensee() {
+            if let Some(palettes) = palettes_for_title(rom.title_checksum(), rom.read(0x137)) {
+                return palettes;
+            }
+        }
+
+        self.fallback.palettes()
+    }
+}
+
+impl Default for CompatPaletteConfig {
+    fn default() -> CompatPaletteConfig {
+        CompatPaletteConfig::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    const POKEMON_RED: u8 = 0x14;
+    // Shared by SUPER MARIOLAND and METROID2
+    const SHARED: u8 = 0x46;
+
+    fn load_rom(name: &str, title: &[u8], licensee: u8) -> Rom {
+        let mut data = vec![0; 0x8000];
+        data[0x134..0x134 + title.len()].copy_from_slice(title);
+        data[0x14B] = licensee;
+
+        let path = env::temp_dir().join(format!(
+            "gioboycolor-palettes-{}-{}.gb",
+            name,
+            process::id()
+        ));
+        fs::write(&path, data).unwrap();
+        let mut rom = Rom::new();
+        rom.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        rom
+    }
+
+    #[test]
+    fn unique_checksums_ignore_the_fourth_letter() {
+        let palettes = palettes_for_title(POKEMON_RED, b'E').unwrap();
+
+        assert_eq!(palettes.bg, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(palettes_for_title(POKEMON_RED, b'X'), Some(palettes));
+    }
+
+    #[test]
+    fn shared_checksums_use_the_fourth_letter() {
+        assert_eq!(palettes_for_title(SHARED, b'E'), Some(COMBINATIONS[22]));
+        assert_eq!(palettes_for_title(SHARED, b'R'), Some(COMBINATIONS[46]));
+        assert_eq!(palettes_for_title(SHARED, b'X'), None);
+    }
+
+    #[test]
+    fn unknown_checksums_have_no_palettes() {
+        assert_eq!(palettes_for_title(0x02, b'A'), None);
+    }
+
+    #[test]
+    fn selects_by_title_for_nintendo_games() {
+        let config = CompatPaletteConfig::new();
+
+        let rom = load_rom("metroid", b"METROID2", 0x01);
+        assert_eq!(rom.title_checksum(), SHARED);
+        assert_eq!(config.select(&rom), COMBINATIONS[46]);
+
+        let rom = load_rom("mario", b"SUPER MARIOLAND", 0x01);
+        assert_eq!(rom.title_checksum(), SHARED);
+        assert_eq!(config.select(&rom), COMBINATIONS[22]);
+    }
+
+    #[test]
+    fn falls_back_for_unknown_and_third_party_games() {
+        let mut config = CompatPaletteConfig::new();
+        let fallback = BootCombo::RightA.palettes();
+
+        assert_eq!(
+            config.select(&load_rom("unknown", b"GIOBOYCOLOR", 0x01)),
+            fallback
+        );
+        assert_eq!(
+            config.select(&load_rom("third-party", b"METROID2", 0x00)),
+            fallback
+        );
+
+        config.fallback = BootCombo::LeftB;
+        assert_eq!(
+            config.select(&load_rom("grayscale", b"GIOBOYCOLOR", 0x01)),
+            BootCombo::LeftB.palettes()
+        );
+    }
+
+    #[test]
+    fn boot_combos_override_the_title() {
+        let mut config = CompatPaletteConfig::new();
+        let rom = load_rom("combo", b"METROID2", 0x01);
+
+        config.latch_buttons(&Buttons {
+            up: true,
+            b: true,
+            ..Buttons::default()
+        });
+        assert_eq!(config.select(&rom), BootCombo::UpB.palettes());
+
+        config.boot_combo = Some(BootCombo::Down);
+        assert_eq!(config.select(&rom), BootCombo::Down.palettes());
+    }
+}
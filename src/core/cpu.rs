@@ -0,0 +1,337 @@
+//! SM83 CPU
+//!
+//! The instruction set is implemented once, generic over the bus it runs on. Every bus access
+//! takes one machine cycle, the cycles of a step are counted here and compared against the
+//! timing of the instruction in debug builds.
+
+use crate::core::{
+    bus::Bus,
+    interrupts::Interrupt,
+    registers::Registers,
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
+pub struct Cpu {
+    pub registers: Registers,
+    // Interrupt master enable
+    pub ime: bool,
+    // EI enables interrupts after the following instruction
+    ime_scheduled: bool,
+    pub halted: bool,
+    // Machine cycles taken by the current step
+    step_cycles: usize,
+}
+
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu {
+            registers: Registers::new(),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            step_cycles: 0,
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.registers.save_state(writer);
+        writer.write_bool(self.ime);
+        writer.write_bool(self.ime_scheduled);
+        writer.write_bool(self.halted);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.registers.load_state(reader)?;
+        self.ime = reader.read_bool()?;
+        self.ime_scheduled = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+        Ok(())
+    }
+
+    // Reads a byte, taking one machine cycle
+    fn read<B: Bus>(&mut self, bus: &mut B, address: u16) -> u8 {
+        self.step_cycles += 1;
+        bus.read(address)
+    }
+
+    // Writes a byte, taking one machine cycle
+    fn write<B: Bus>(&mut self, bus: &mut B, address: u16, data: u8) {
+        self.step_cycles += 1;
+        bus.write(address, data);
+    }
+
+    // A machine cycle where the CPU doesn't access the bus
+    fn internal_delay<B: Bus>(&mut self, bus: &mut B) {
+        self.step_cycles += 1;
+        bus.tick();
+    }
+
+    // Whether the next step executes an instruction, rather than dispatching an interrupt or
+    // idling in HALT
+    pub fn executes_instruction<B: Bus>(&self, bus: &B) -> bool {
+        let pending = bus.pending_interrupts() != 0;
+        !(pending && self.ime) && (pending || !self.halted)
+    }
+
+    // Perform a CPU step, returns machine cycles
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> usize {
+        self.step_cycles = 0;
+
+        if self.handle_interrupts(bus) {
+            return self.step_cycles;
+        }
+
+        // Nothing to do until an interrupt is requested
+        if self.halted {
+            self.internal_delay(bus);
+            return self.step_cycles;
+        }
+
+        let enable_ime = self.ime_scheduled;
+        self.ime_scheduled = false;
+
+        // Read opcode at PC
+        let opcode = self.next_byte(bus);
+
+        // Execute instruction, every bus access has already ticked the rest of the system
+        let cycles = self.execute_instruction(bus, opcode);
+        debug_assert_eq!(
+            cycles, self.step_cycles,
+            "Cycle count mismatch for opcode {:02X}",
+            opcode
+        );
+
+        if enable_ime {
+            self.ime = true;
+        }
+
+        self.step_cycles
+    }
+
+    // Dispatches the highest priority pending interrupt, returns true if one was dispatched
+    fn handle_interrupts<B: Bus>(&mut self, bus: &mut B) -> bool {
+        let pending = bus.pending_interrupts();
+
+        if pending == 0 {
+            return false;
+        }
+
+        // Any pending interrupt wakes the CPU up, even with IME off
+        self.halted = false;
+
+        if !self.ime {
+            return false;
+        }
+
+        let Some(interrupt) = [
+            Interrupt::VBlank,
+            Interrupt::Lcd,
+            Interrupt::Timer,
+            Interrupt::Serial,
+            Interrupt::Joypad,
+        ]
+        .into_iter()
+        .find(|interrupt| pending & interrupt.bit() != 0) else {
+            return false;
+        };
+
+        // 5 machine cycles: 2 wait states, pushing PC (1 internal + 2 writes) and jumping
+        self.ime = false;
+        bus.acknowledge_interrupt(interrupt);
+        self.internal_delay(bus);
+        self.push(bus, self.registers.pc);
+        self.registers.pc = interrupt.handler_address();
+        self.internal_delay(bus);
+
+        true
+    }
+
+    fn next_byte<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let byte = self.read(bus, self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        byte
+    }
+
+    // Returns the next word (pointer, little endian)
+    fn next_pointer<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let low = self.next_byte(bus);
+        let high = self.next_byte(bus);
+        combine!(high, low)
+    }
+
+    // Pushes 16 bit data onto the stack
+    fn push<B: Bus>(&mut self, bus: &mut B, data: u16) {
+        // SP is decremented in an internal cycle before the writes
+        self.internal_delay(bus);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(bus, self.registers.sp, high!(data));
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(bus, self.registers.sp, low!(data));
+    }
+
+    // Pops highest 16 bits from stack
+    fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let low = self.read(bus, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = self.read(bus, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        combine!(high, low)
+    }
+
+    #[rustfmt::skip]
+    fn execute_instruction<B: Bus>(&mut self, bus: &mut B, opcode: u8) -> usize {
+    match opcode {
+        0x00 => { 1 },   // NOP
+        0x10 => { 1 },  // STOP
+        0x76 => { self.halted = true; 1 }, // HALT
+        0xf3 => { self.ime = false; self.ime_scheduled = false; 1 }, // DI
+        0xfb => { self.ime_scheduled = true; 1 }, // EI
+        0xd9 => { self.registers.pc = self.pop(bus); self.internal_delay(bus); self.ime = true; 4 }, // RETI
+
+        //  8-bit load instructions
+        // LD r, r'
+        0x78 => { self.registers.a = self.registers.b; 1 }, // LD A, B
+        0x79 => { self.registers.a = self.registers.c; 1 }, // LD A, C
+        0x7a => { self.registers.a = self.registers.d; 1 }, // LD A, D
+        0x7b => { self.registers.a = self.registers.e; 1 }, // LD A, E
+        0x7c => { self.registers.a = self.registers.h; 1 }, // LD A, H
+        0x7d => { self.registers.a = self.registers.l; 1 }, // LD A, L
+        0x7f => { 1 },                                    // LD A, A
+        0x47 => { self.registers.b = self.registers.a; 1 }, // LD B, A
+        0x40 => { 1 },                                    // LD B, B
+        0x41 => { self.registers.b = self.registers.c; 1 }, // LD B, C
+        0x42 => { self.registers.b = self.registers.d; 1 }, // LD B, D
+        0x43 => { self.registers.b = self.registers.e; 1 }, // LD B, E
+        0x44 => { self.registers.b = self.registers.h; 1 }, // LD B, H
+        0x45 => { self.registers.b = self.registers.l; 1 }, // LD B, L
+        0x4f => { self.registers.c = self.registers.a; 1 }, // LD C, A
+        0x48 => { self.registers.c = self.registers.b; 1 }, // LD C, B
+        0x49 => { 1 },                                    // LD C, C
+        0x4a => { self.registers.c = self.registers.d; 1 }, // LD C, D
+        0x4b => { self.registers.c = self.registers.e; 1 }, // LD C, E
+        0x4c => { self.registers.c = self.registers.h; 1 }, // LD C, H
+        0x4d => { self.registers.c = self.registers.l; 1 }, // LD C, L
+        0x57 => { self.registers.d = self.registers.a; 1 }, // LD D, A
+        0x50 => { self.registers.d = self.registers.b; 1 }, // LD D, B
+        0x51 => { self.registers.d = self.registers.c; 1 }, // LD D, C
+        0x52 => { 1 },                                    // LD D, D
+        0x53 => { self.registers.d = self.registers.e; 1 }, // LD D, E
+        0x54 => { self.registers.d = self.registers.h; 1 }, // LD D, H
+        0x55 => { self.registers.d = self.registers.l; 1 }, // LD D, L
+        0x5F => { self.registers.e = self.registers.a; 1 }, // LD E, A
+        0x58 => { self.registers.e = self.registers.b; 1 }, // LD E, B
+        0x59 => { self.registers.e = self.registers.c; 1 }, // LD E, C
+        0x5a => { self.registers.e = self.registers.d; 1 }, // LD E, D
+        0x5b => { 1 },                                    // LD E, E
+        0x5c => { self.registers.e = self.registers.h; 1 }, // LD E, H
+        0x5d => { self.registers.e = self.registers.l; 1 }, // LD E, L
+        0x67 => { self.registers.h = self.registers.a; 1 }, // LD H, A
+        0x60 => { self.registers.h = self.registers.b; 1 }, // LD H, B
+        0x61 => { self.registers.h = self.registers.c; 1 }, // LD H, C
+        0x62 => { self.registers.h = self.registers.d; 1 }, // LD H, D
+        0x63 => { self.registers.h = self.registers.e; 1 }, // LD H, E
+        0x64 => { 1 },                                    // LD H, H
+        0x65 => { self.registers.h = self.registers.l; 1 }, // LD H, E
+        0x6f => { self.registers.l = self.registers.a; 1 }, // LD L, A
+        0x68 => { self.registers.l = self.registers.b; 1 }, // LD L, B
+        0x69 => { self.registers.l = self.registers.c; 1 }, // LD L, C
+        0x6a => { self.registers.l = self.registers.d; 1 }, // LD L, D
+        0x6b => { self.registers.l = self.registers.e; 1 }, // LD L, E
+        0x6c => { self.registers.l = self.registers.h; 1 }, // LD L, H
+        0x6d => { 1 },                                    // LD L, L
+        // LD r, n
+        0x3e => { self.registers.a = self.next_byte(bus); 2 }, // LD A, n
+        0x06 => { self.registers.b = self.next_byte(bus); 2 }, // LD B, n
+        0x0e => { self.registers.c = self.next_byte(bus); 2 }, // LD C, n
+        0x16 => { self.registers.d = self.next_byte(bus); 2 }, // LD D, n
+        0x1e => { self.registers.e = self.next_byte(bus); 2 }, // LD E, n
+        0x26 => { self.registers.h = self.next_byte(bus); 2 }, // LD H, n
+        0x2e => { self.registers.l = self.next_byte(bus); 2 }, // LD L, n
+        // LD r, (HL)
+        0x7e => { self.registers.a = self.read(bus, self.registers.hl()); 2 },
+        0x46 => { self.registers.b = self.read(bus, self.registers.hl()); 2 },
+        0x4e => { self.registers.c = self.read(bus, self.registers.hl()); 2 },
+        0x56 => { self.registers.d = self.read(bus, self.registers.hl()); 2 },
+        0x5e => { self.registers.e = self.read(bus, self.registers.hl()); 2 },
+        0x66 => { self.registers.h = self.read(bus, self.registers.hl()); 2 },
+        0x6e => { self.registers.l = self.read(bus, self.registers.hl()); 2 },
+        // LD (HL), r
+        0x77 => { self.write(bus, self.registers.hl(), self.registers.a); 2 },
+        0x70 => { self.write(bus, self.registers.hl(), self.registers.b); 2 },
+        0x71 => { self.write(bus, self.registers.hl(), self.registers.c); 2 },
+        0x72 => { self.write(bus, self.registers.hl(), self.registers.d); 2 },
+        0x73 => { self.write(bus, self.registers.hl(), self.registers.e); 2 },
+        0x74 => { self.write(bus, self.registers.hl(), self.registers.h); 2 },
+        0x75 => { self.write(bus, self.registers.hl(), self.registers.l); 2 },
+        // LD (HL), n
+        0x36 => { let n = self.next_byte(bus); self.write(bus, self.registers.hl(), n); 3 },
+        // LD A, (BC)
+        0x0a => { self.registers.a = self.read(bus, self.registers.bc()); 2 },
+        // LD A, (DE)
+        0x1a => { self.registers.a = self.read(bus, self.registers.de()); 2 },
+        // LD (BC), A 
+        0x02 => { self.write(bus, self.registers.bc(), self.registers.a); 2 },
+        // LD (DE), A 
+        0x12 => { self.write(bus, self.registers.de(), self.registers.a); 2 },
+        // LD A, (nn) 
+        0xfa => { let nn = self.next_pointer(bus); self.registers.a = self.read(bus, nn); 4 },
+        // LD (nn), A 
+        0xea => { let nn = self.next_pointer(bus); self.write(bus, nn, self.registers.a); 4 },
+        // LDH A, (C)
+        0xf2 => { self.registers.a = self.read(bus, combine!(0xff, self.registers.c)); 2 },
+        // LDH (C), A
+        0xe2 => { self.write(bus, combine!(0xff, self.registers.c), self.registers.a); 2 },
+        // LDH A, n
+        0xf0 => { let n = self.next_byte(bus); self.registers.a = self.read(bus, combine!(0xff, n)); 3 },
+        // LDH (n), A
+        0xe0 => { let n = self.next_byte(bus); self.write(bus, combine!(0xff, n), self.registers.a); 3 },
+        // LD A, (HL-)
+        0x3a => { self.registers.a = self.read(bus, self.registers.hl()); self.registers.hld(); 2 },
+        // LDH (HL-), A
+        0x32 => { self.write(bus, self.registers.hl(), self.registers.a); self.registers.hld(); 2 },
+        // LD A, (HL+)
+        0x2a => { self.registers.a = self.read(bus, self.registers.hl()); self.registers.hli(); 2 },
+        // LDH (HL+), A
+        0x22 => { self.write(bus, self.registers.hl(), self.registers.a); self.registers.hli(); 2 },
+
+        //  16-bit load instructions
+        // LD rr, nn
+        0x01 => { let nn = self.next_pointer(bus); self.registers.set_bc(nn); 3 } // LD BC, nn
+        0x11 => { let nn = self.next_pointer(bus); self.registers.set_de(nn); 3 } // LD DE, nn
+        0x21 => { let nn = self.next_pointer(bus); self.registers.set_hl(nn); 3 } // LD HL, nn
+        0x31 => { let nn = self.next_pointer(bus); self.registers.sp = nn; 3 } // LD SP, nn
+        // LD (nn), SP
+        0x08 => { 
+            let nn = self.next_pointer(bus); 
+            self.write(bus, nn, low!(self.registers.sp)); 
+            self.write(bus, nn + 1, high!(self.registers.sp));  
+            5 
+        },
+        // LD SP, HL
+        0xf9 => { self.registers.sp = self.registers.hl(); self.internal_delay(bus); 2 }
+        // PUSH rr
+        0xc5 => { self.push(bus, self.registers.bc()); 4 } // PUSH BC
+        0xd5 => { self.push(bus, self.registers.de()); 4 } // PUSH DE
+        0xe5 => { self.push(bus, self.registers.hl()); 4 } // PUSH HL
+        0xf5 => { self.push(bus, self.registers.af()); 4 } // PUSH AF
+        // POP rr
+        0xc1 => { let val = self.pop(bus); self.registers.set_bc(val); 3 },
+        0xd1 => { let val = self.pop(bus); self.registers.set_de(val); 3 },
+        0xe1 => { let val = self.pop(bus); self.registers.set_hl(val); 3 },
+        0xf1 => {
+            // Lower 4 bits of register F (unused flag bits) are set to zero
+            let val = self.pop(bus) & 0xFFF0;
+            self.registers.set_af(val);
+            3
+        },
+        // The opcode was read from the byte before PC
+        _ => { panic!("Unsupported operation {:02X} at {:04X}", opcode, self.registers.pc.wrapping_sub(1)); }
+    }
+}
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
@@ -0,0 +1,568 @@
+//! CPU debugger
+//!
+//! Drives GioBoyColor one instruction at a time and stops on breakpoints and watchpoints. The
+//! frontend calls run_frame instead of GioBoyColor::run_frame, which does nothing while paused.
+//!
+//! Stop         Explanation                                                        \
+//! Breakpoint   PC reaches an address, optionally in a given ROM bank and only     \
+//!              while a register condition holds. Checked before the instruction   \
+//! Watchpoint   An address range is read, written or executed. Reads and writes    \
+//!              stop after the instruction accessing it                            \
+//! Step         step_into, step_over or step_out finished                         \
+//! VBlank       run_to_vblank reached the next frame                               \
+//!
+//! The call stack is tracked from the CALL, RST and return instructions, and the interrupts,
+//! executed while the debugger drives the CPU. Addresses are named after the loaded symbols.
+
+use std::fmt;
+
+use crate::core::{
+    bus::BusCycle,
+    disassembler::instruction_length,
+    gbc::{GioBoyColor, StepRecord},
+    gpu::DOTS_PER_FRAME,
+    memory_map::{ROM_BANK_END, ROM_BANK_START},
+    symbols::SymbolTable,
+};
+
+// Older frames are dropped, games can leave the stack unbalanced
+const MAX_CALL_STACK: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_uppercase().as_str() {
+            "A" => Register::A,
+            "F" => Register::F,
+            "B" => Register::B,
+            "C" => Register::C,
+            "D" => Register::D,
+            "E" => Register::E,
+            "H" => Register::H,
+            "L" => Register::L,
+            "AF" => Register::AF,
+            "BC" => Register::BC,
+            "DE" => Register::DE,
+            "HL" => Register::HL,
+            "SP" => Register::SP,
+            "PC" => Register::PC,
+            _ => return None,
+        };
+
+        Some(register)
+    }
+
+    pub fn read(self, gbc: &GioBoyColor) -> u16 {
+        let registers = &gbc.cpu.registers;
+
+        match self {
+            Register::A => registers.a as u16,
+            Register::F => registers.f as u16,
+            Register::B => registers.b as u16,
+            Register::C => registers.c as u16,
+            Register::D => registers.d as u16,
+            Register::E => registers.e as u16,
+            Register::H => registers.h as u16,
+            Register::L => registers.l as u16,
+            Register::AF => registers.af(),
+            Register::BC => registers.bc(),
+            Register::DE => registers.de(),
+            Register::HL => registers.hl(),
+            Register::SP => registers.sp,
+            Register::PC => registers.pc,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn from_symbol(symbol: &str) -> Option<Comparison> {
+        let comparison = match symbol {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        };
+
+        Some(comparison)
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn holds(self, left: u16, right: u16) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, gbc: &GioBoyColor) -> bool {
+        self.comparison.holds(self.register.read(gbc), self.value)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Breakpoint {
+    // Any bank when None
+    pub bank: Option<usize>,
+    pub address: u16,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+    // Inclusive
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Watchpoint {
+        index: usize,
+        access: Access,
+        address: u16,
+    },
+    Step,
+    VBlank,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(index) => write!(f, "Breakpoint {}", index),
+            StopReason::Watchpoint {
+                index,
+                access,
+                address,
+            } => write!(f, "Watchpoint {}: {:?} at {:04X}", index, access, address),
+            StopReason::Step => write!(f, "Step"),
+            StopReason::VBlank => write!(f, "VBlank"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CallFrame {
+    // Address of the CALL or RST, or of the interrupted instruction
+    pub caller: u16,
+    pub target: u16,
+    pub return_address: u16,
+    pub interrupt: bool,
+    // ROM bank mapped at 0x4000-0x7FFF, calls don't switch banks
+    pub bank: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    Running,
+    Paused,
+    // Runs until PC is back after the CALL at the same stack depth
+    StepOver { return_address: u16, sp: u16 },
+    // Runs until a return pops above the given stack pointer
+    StepOut { sp: u16 },
+    RunToVBlank,
+}
+
+// What a step did to the control flow
+enum Flow {
+    Call(CallFrame),
+    Return,
+    Other,
+}
+
+// CALL, conditional CALL and RST
+fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7
+}
+
+// RET, RETI and conditional RET
+fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8)
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    call_stack: Vec<CallFrame>,
+    mode: Mode,
+    // The instruction at PC already stopped execution, so it isn't checked again when resuming
+    resuming: bool,
+    symbols: SymbolTable,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            call_stack: Vec::new(),
+            mode: Mode::Running,
+            resuming: false,
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    // Replaced when a ROM is loaded, kept across resets
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    // The label of an address, or the closest one before it, in the bank mapped at the moment
+    pub fn describe_address(&self, gbc: &GioBoyColor, address: u16) -> Option<String> {
+        self.symbols
+            .describe(gbc.rom.rom_bank(ROM_BANK_START), address)
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        if index < self.breakpoints.len() {
+            self.breakpoints.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        if index < self.watchpoints.len() {
+            self.watchpoints.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Innermost frame last
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.call_stack
+    }
+
+    // Forgets the call stack, e.g. after loading a ROM or a save state
+    pub fn reset(&mut self) {
+        self.call_stack.clear();
+        self.resuming = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = Mode::Running;
+    }
+
+    // Executes a single instruction, or the dispatch of an interrupt
+    pub fn step_into(&mut self, gbc: &mut GioBoyColor) -> StopReason {
+        let mut cycles = 0;
+
+        // A halted CPU idles until an interrupt wakes it up, up to a frame
+        while cycles < DOTS_PER_FRAME {
+            let executes = gbc.cpu.executes_instruction(&*gbc);
+            let (record, _) = self.step(gbc);
+            cycles += record.cycles as u32 * 4;
+
+            if executes || !gbc.cpu.halted {
+                break;
+            }
+        }
+
+        self.stop(StopReason::Step)
+    }
+
+    // Like step_into, but runs over calls until they return
+    pub fn step_over(&mut self, gbc: &mut GioBoyColor) -> Option<StopReason> {
+        let pc = gbc.cpu.registers.pc;
+        let opcode = gbc.peek(pc);
+
+        if !is_call(opcode) {
+            return Some(self.step_into(gbc));
+        }
+
+        self.mode = Mode::StepOver {
+            return_address: pc.wrapping_add(instruction_length(opcode) as u16),
+            sp: gbc.cpu.registers.sp,
+        };
+        None
+    }
+
+    // Runs until the current function returns
+    pub fn step_out(&mut self, gbc: &GioBoyColor) {
+        self.mode = Mode::StepOut {
+            sp: gbc.cpu.registers.sp,
+        };
+    }
+
+    pub fn run_to_vblank(&mut self) {
+        self.mode = Mode::RunToVBlank;
+    }
+
+    // Runs until the PPU enters VBlank, a frame worth of cycles, or a stop. Does nothing while
+    // paused, and is paused after returning a stop reason
+    pub fn run_frame(&mut self, gbc: &mut GioBoyColor) -> Option<StopReason> {
+        let mut cycles = 0;
+
+        while cycles < DOTS_PER_FRAME && self.mode != Mode::Paused {
+            if !self.resuming {
+                if let Some(reason) = self.check_breakpoints(gbc) {
+                    return Some(self.stop(reason));
+                }
+            }
+            self.resuming = false;
+
+            let (record, flow) = self.step(gbc);
+            cycles += record.cycles as u32 * 4;
+
+            if let Some(reason) = self.check_watchpoints(&record.bus_cycles) {
+                return Some(self.stop(reason));
+            }
+
+            let registers = &gbc.cpu.registers;
+            let finished = match self.mode {
+                Mode::StepOver { return_address, sp } => {
+                    registers.pc == return_address && registers.sp >= sp
+                }
+                Mode::StepOut { sp } => matches!(flow, Flow::Return) && registers.sp > sp,
+                _ => false,
+            };
+            if finished {
+                return Some(self.stop(StopReason::Step));
+            }
+
+            if record.vblank {
+                if self.mode == Mode::RunToVBlank {
+                    return Some(self.stop(StopReason::VBlank));
+                }
+                break;
+            }
+        }
+
+        None
+    }
+
+    // Words on the stack, from SP upwards
+    pub fn stack(&self, gbc: &GioBoyColor, depth: usize) -> Vec<(u16, u16)> {
+        let sp = gbc.cpu.registers.sp;
+
+        (0..depth as u16)
+            .map(|index| {
+                let address = sp.wrapping_add(index * 2);
+                let value = combine!(gbc.peek(address.wrapping_add(1)), gbc.peek(address));
+                (address, value)
+            })
+            .collect()
+    }
+
+    // Steps the CPU and keeps the call stack up to date
+    fn step(&mut self, gbc: &mut GioBoyColor) -> (StepRecord, Flow) {
+        let (pc, sp) = (gbc.cpu.registers.pc, gbc.cpu.registers.sp);
+        let executes = gbc.cpu.executes_instruction(&*gbc);
+        let opcode = gbc.peek(pc);
+        let bank = gbc.rom.rom_bank(ROM_BANK_START);
+
+        let record = gbc.step_recording();
+
+        let registers = &gbc.cpu.registers;
+        let pushed = registers.sp == sp.wrapping_sub(2);
+        let flow = if !executes && pushed {
+            // Interrupt dispatch, returns to the instruction it replaced
+            Flow::Call(CallFrame {
+                caller: pc,
+                target: registers.pc,
+                return_address: pc,
+                interrupt: true,
+                bank,
+            })
+        } else if executes && pushed && is_call(opcode) {
+            Flow::Call(CallFrame {
+                caller: pc,
+                target: registers.pc,
+                return_address: pc.wrapping_add(instruction_length(opcode) as u16),
+                interrupt: false,
+                bank,
+            })
+        } else if executes && registers.sp == sp.wrapping_add(2) && is_return(opcode) {
+            Flow::Return
+        } else {
+            Flow::Other
+        };
+
+        match flow {
+            Flow::Call(frame) => {
+                if self.call_stack.len() == MAX_CALL_STACK {
+                    self.call_stack.remove(0);
+                }
+                self.call_stack.push(frame);
+            }
+            Flow::Return => {
+                self.call_stack.pop();
+            }
+            Flow::Other => (),
+        }
+
+        (record, flow)
+    }
+
+    fn stop(&mut self, reason: StopReason) -> StopReason {
+        self.mode = Mode::Paused;
+        self.resuming = true;
+        reason
+    }
+
+    fn check_breakpoints(&self, gbc: &GioBoyColor) -> Option<StopReason> {
+        let pc = gbc.cpu.registers.pc;
+
+        // Only the instructions that are about to run, not interrupt dispatches
+        if !gbc.cpu.executes_instruction(gbc) {
+            return None;
+        }
+
+        let bank = match pc {
+            ROM_BANK_START..=ROM_BANK_END => gbc.rom.rom_bank(pc),
+            _ => 0,
+        };
+
+        if let Some(index) = self.breakpoints.iter().position(|breakpoint| {
+            breakpoint.address == pc
+                && breakpoint.bank.is_none_or(|expected| expected == bank)
+                && breakpoint
+                    .condition
+                    .is_none_or(|condition| condition.holds(gbc))
+        }) {
+            return Some(StopReason::Breakpoint(index));
+        }
+
+        self.watchpoints
+            .iter()
+            .position(|watchpoint| watchpoint.execute && watchpoint.contains(pc))
+            .map(|index| StopReason::Watchpoint {
+                index,
+                access: Access::Execute,
+                address: pc,
+            })
+    }
+
+    fn check_watchpoints(&self, bus_cycles: &[BusCycle]) -> Option<StopReason> {
+        bus_cycles.iter().find_map(|cycle| {
+            let (access, address) = match *cycle {
+                BusCycle::Read { address, .. } => (Access::Read, address),
+                BusCycle::Write { address, .. } => (Access::Write, address),
+                BusCycle::Internal => return None,
+            };
+
+            self.watchpoints
+                .iter()
+                .position(|watchpoint| {
+                    watchpoint.contains(address)
+                        && match access {
+                            Access::Read => watchpoint.read,
+                            _ => watchpoint.write,
+                        }
+                })
+                .map(|index| StopReason::Watchpoint {
+                    index,
+                    access,
+                    address,
+                })
+        })
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
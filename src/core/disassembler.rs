@@ -0,0 +1,284 @@
+//! SM83 disassembler
+//!
+//! Decodes the whole instruction set, including CB-prefixed and unused opcodes, using the bit
+//! fields of the opcode:
+//!
+//! Bits    Field    Explanation                                            \
+//! 7-6     x        Instruction group                                      \
+//! 5-3     y        Destination register, condition, ALU operation or bit  \
+//! 2-0     z        Source register or instruction in the group            \
+//! 5-4     p        16 bit register                                        \
+//! 3       q        Selects between two instructions sharing p             \
+//!
+//! Registers are indexed as B, C, D, E, H, L, (HL), A. Immediates are written in hex with a
+//! `$` prefix, and addresses are replaced by their label when one is known.
+//!
+//! (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/CPU_Instruction_Set.html))
+
+use crate::core::memory_map::{ROM_BANK_END, ROM_BANK_START};
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEMORY: [&str; 4] = ["(BC)", "(DE)", "(HL+)", "(HL-)"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = [
+    "ADD A, ", "ADC A, ", "SUB ", "SBC A, ", "AND ", "XOR ", "OR ", "CP ",
+];
+const ACCUMULATOR_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+const ROTATIONS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// Names for addresses, e.g. loaded from a symbol file
+pub trait Labels {
+    // Bank is the ROM bank for 0x4000-0x7FFF and 0 for the other regions
+    fn label(&self, bank: usize, address: u16) -> Option<&str>;
+}
+
+// Every address is written as a number
+pub struct NoLabels;
+
+impl Labels for NoLabels {
+    fn label(&self, _bank: usize, _address: u16) -> Option<&str> {
+        None
+    }
+}
+
+pub struct Instruction {
+    // In bytes, including the opcode and the CB prefix
+    pub length: usize,
+    pub text: String,
+}
+
+// Length of the instruction starting with opcode, in bytes
+pub fn instruction_length(opcode: u8) -> usize {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+
+    match (x, z) {
+        (0, 0) if y == 1 => 3,
+        (0, 0) if y >= 2 => 2,
+        (0, 1) if y & 1 == 0 => 3,
+        (0, 6) => 2,
+        (3, 0) if y >= 4 => 2,
+        (3, 2) if y < 4 || y == 5 || y == 7 => 3,
+        (3, 3) if y == 0 => 3,
+        (3, 3) if y == 1 => 2,
+        (3, 4) if y < 4 => 3,
+        (3, 5) if y == 1 => 3,
+        (3, 6) => 2,
+        _ => 1,
+    }
+}
+
+// Decodes the instruction at the start of bytes, located at address. Bank is the ROM bank
+// mapped at 0x4000-0x7FFF, used to look up labels. Missing operand bytes read as 0
+pub fn disassemble(bytes: &[u8], address: u16, bank: usize, labels: &dyn Labels) -> Instruction {
+    let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+    let opcode = byte(0);
+    let length = instruction_length(opcode);
+
+    let n8 = format!("${:02X}", byte(1));
+    let n16 = format!("${:04X}", combine!(byte(2), byte(1)));
+    let a16 = address_operand(combine!(byte(2), byte(1)), bank, labels);
+    let a8 = address_operand(combine!(0xFF, byte(1)), bank, labels);
+    // Relative jumps are shown with their resolved target
+    let e8 = byte(1) as i8;
+    let relative = address_operand(
+        address.wrapping_add(2).wrapping_add(e8 as u16),
+        bank,
+        labels,
+    );
+    let sign = if e8 < 0 { '-' } else { '+' };
+    let offset = format!("{}${:02X}", sign, e8.unsigned_abs());
+
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (p, q) = ((y >> 1) as usize, y & 1);
+    let (y, z) = (y as usize, z as usize);
+
+    let text = match (x, z) {
+        (0, 0) => match y {
+            0 => "NOP".to_string(),
+            1 => format!("LD ({}), SP", a16),
+            2 => "STOP".to_string(),
+            3 => format!("JR {}", relative),
+            _ => format!("JR {}, {}", CONDITIONS[y - 4], relative),
+        },
+        (0, 1) if q == 0 => format!("LD {}, {}", R16[p], n16),
+        (0, 1) => format!("ADD HL, {}", R16[p]),
+        (0, 2) if q == 0 => format!("LD {}, A", R16_MEMORY[p]),
+        (0, 2) => format!("LD A, {}", R16_MEMORY[p]),
+        (0, 3) if q == 0 => format!("INC {}", R16[p]),
+        (0, 3) => format!("DEC {}", R16[p]),
+        (0, 4) => format!("INC {}", R8[y]),
+        (0, 5) => format!("DEC {}", R8[y]),
+        (0, 6) => format!("LD {}, {}", R8[y], n8),
+        (0, 7) => ACCUMULATOR_OPS[y].to_string(),
+        (1, 6) if y == 6 => "HALT".to_string(),
+        (1, _) => format!("LD {}, {}", R8[y], R8[z]),
+        (2, _) => format!("{}{}", ALU[y], R8[z]),
+        (3, 0) => match y {
+            0..=3 => format!("RET {}", CONDITIONS[y]),
+            4 => format!("LDH ({}), A", a8),
+            5 => format!("ADD SP, {}", offset),
+            6 => format!("LDH A, ({})", a8),
+            _ => format!("LD HL, SP{}", offset),
+        },
+        (3, 1) if q == 0 => format!("POP {}", R16_STACK[p]),
+        (3, 1) => ["RET", "RETI", "JP HL", "LD SP, HL"][p].to_string(),
+        (3, 2) => match y {
+            0..=3 => format!("JP {}, {}", CONDITIONS[y], a16),
+            4 => "LDH (C), A".to_string(),
+            5 => format!("LD ({}), A", a16),
+            6 => "LDH A, (C)".to_string(),
+            _ => format!("LD A, ({})", a16),
+        },
+        (3, 3) => match y {
+            0 => format!("JP {}", a16),
+            1 => disassemble_cb(byte(1)),
+            6 => "DI".to_string(),
+            7 => "EI".to_string(),
+            _ => unused(opcode),
+        },
+        (3, 4) if y < 4 => format!("CALL {}, {}", CONDITIONS[y], a16),
+        (3, 5) if q == 0 => format!("PUSH {}", R16_STACK[p]),
+        (3, 5) if p == 0 => format!("CALL {}", a16),
+        (3, 6) => format!("{}{}", ALU[y], n8),
+        (3, 7) => format!("RST ${:02X}", y * 8),
+        _ => unused(opcode),
+    };
+
+    Instruction { length, text }
+}
+
+fn disassemble_cb(opcode: u8) -> String {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, (opcode & 7) as usize);
+
+    match x {
+        0 => format!("{} {}", ROTATIONS[y as usize], R8[z]),
+        1 => format!("BIT {}, {}", y, R8[z]),
+        2 => format!("RES {}, {}", y, R8[z]),
+        _ => format!("SET {}, {}", y, R8[z]),
+    }
+}
+
+// Opcodes that lock up the CPU are shown as data
+fn unused(opcode: u8) -> String {
+    format!("DB ${:02X}", opcode)
+}
+
+fn address_operand(address: u16, bank: usize, labels: &dyn Labels) -> String {
+    let bank = match address {
+        ROM_BANK_START..=ROM_BANK_END => bank,
+        _ => 0,
+    };
+
+    match labels.label(bank, address) {
+        Some(label) => label.to_string(),
+        None => format!("${:04X}", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestLabels;
+
+    impl Labels for TestLabels {
+        fn label(&self, bank: usize, address: u16) -> Option<&str> {
+            match (bank, address) {
+                (2, 0x4000) => Some("Bank2Main"),
+                (0, 0xC000) => Some("wBuffer"),
+                (0, 0xFF44) => Some("rLY"),
+                _ => None,
+            }
+        }
+    }
+
+    fn text(bytes: &[u8], address: u16) -> String {
+        disassemble(bytes, address, 1, &NoLabels).text
+    }
+
+    #[test]
+    fn lengths() {
+        for (opcode, length) in [
+            (0x00, 1), // NOP
+            (0x01, 3), // LD BC, n16
+            (0x08, 3), // LD (a16), SP
+            (0x10, 2), // STOP
+            (0x18, 2), // JR e8
+            (0x20, 2), // JR NZ, e8
+            (0x3E, 2), // LD A, n8
+            (0x76, 1), // HALT
+            (0x80, 1), // ADD A, B
+            (0xC3, 3), // JP a16
+            (0xCB, 2), // Prefix
+            (0xCD, 3), // CALL a16
+            (0xD3, 1), // Unused
+            (0xE0, 2), // LDH (a8), A
+            (0xE2, 1), // LDH (C), A
+            (0xE8, 2), // ADD SP, e8
+            (0xEA, 3), // LD (a16), A
+            (0xF8, 2), // LD HL, SP+e8
+            (0xFA, 3), // LD A, (a16)
+            (0xFE, 2), // CP n8
+        ] {
+            assert_eq!(instruction_length(opcode), length, "opcode {:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn cb_prefixed() {
+        assert_eq!(text(&[0xCB, 0x00], 0), "RLC B");
+        assert_eq!(text(&[0xCB, 0x37], 0), "SWAP A");
+        assert_eq!(text(&[0xCB, 0x3E], 0), "SRL (HL)");
+        assert_eq!(text(&[0xCB, 0x7C], 0), "BIT 7, H");
+        assert_eq!(text(&[0xCB, 0x87], 0), "RES 0, A");
+        assert_eq!(text(&[0xCB, 0xFE], 0), "SET 7, (HL)");
+    }
+
+    #[test]
+    fn immediates() {
+        assert_eq!(text(&[0x3E, 0x42], 0), "LD A, $42");
+        assert_eq!(text(&[0x01, 0x34, 0x12], 0), "LD BC, $1234");
+        assert_eq!(text(&[0xFE, 0x90], 0), "CP $90");
+        assert_eq!(text(&[0xE8, 0xFE], 0), "ADD SP, -$02");
+        assert_eq!(text(&[0xF8, 0x05], 0), "LD HL, SP+$05");
+        assert_eq!(text(&[0xE0, 0x40], 0), "LDH ($FF40), A");
+        assert_eq!(text(&[0xEA, 0x00, 0xC0], 0), "LD ($C000), A");
+        // Missing operand bytes read as 0
+        assert_eq!(text(&[0xC3], 0), "JP $0000");
+    }
+
+    #[test]
+    fn relative_jumps_show_their_target() {
+        assert_eq!(text(&[0x18, 0xFE], 0x0150), "JR $0150");
+        assert_eq!(text(&[0x20, 0x10], 0x0150), "JR NZ, $0162");
+        assert_eq!(text(&[0x38, 0x80], 0x0100), "JR C, $0082");
+    }
+
+    #[test]
+    fn unused_opcodes_are_data() {
+        for opcode in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ] {
+            assert_eq!(text(&[opcode], 0), format!("DB ${:02X}", opcode));
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let jump = [0xC3, 0x00, 0x40];
+        assert_eq!(disassemble(&jump, 0, 2, &TestLabels).text, "JP Bank2Main");
+        // Same address, another bank mapped
+        assert_eq!(disassemble(&jump, 0, 3, &TestLabels).text, "JP $4000");
+
+        // Outside of the switchable bank, the mapped bank doesn't matter
+        let store = [0xEA, 0x00, 0xC0];
+        assert_eq!(
+            disassemble(&store, 0, 5, &TestLabels).text,
+            "LD (wBuffer), A"
+        );
+        let high = [0xF0, 0x44];
+        assert_eq!(disassemble(&high, 0, 5, &TestLabels).text, "LDH A, (rLY)");
+    }
+}
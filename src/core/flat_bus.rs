@@ -0,0 +1,36 @@
+use crate::core::bus::Bus;
+
+// 64 KiB of RAM replacing the whole memory map, so CPU tests can use any address
+pub struct FlatBus {
+    pub memory: Box<[u8; 0x10000]>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            memory: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> FlatBus {
+        FlatBus::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
+    }
+
+    fn tick(&mut self) {}
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
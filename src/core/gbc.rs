@@ -1,247 +1,857 @@
-use std::path::PathBuf;
+use std::{io, path::PathBuf};
 
-use crate::core::{gpu::Gpu, memory_map::*, registers::Registers, rom::Rom};
+use crate::core::{
+    apu::{sink::AudioSink, Apu},
+    bus::{Bus, BusCycle, TracingBus},
+    cheats::Cheats,
+    compat_palettes::{BootCombo, CompatPaletteConfig},
+    cpu::Cpu,
+    gpu::{Gpu, Mode, DOTS_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM_BANKS, VRAM_BANK_SIZE},
+    infrared::Infrared,
+    interrupts::{Interrupt, IF_UNUSED_BITS},
+    joypad::{Buttons, Joypad},
+    mbc::{RAM_BANK_SIZE, ROM_BANK_SIZE},
+    memory_map::*,
+    rom::Rom,
+    save_state::{SaveStateError, StateReader, StateWriter, MAGIC, VERSION},
+    serial::Serial,
+    sgb::{Sgb, SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH},
+    timer::Timer,
+    trace::Tracer,
+};
 
-const WRAM_SIZE: usize = (WRAM_END - WRAM_START + 1) as usize;
+// 0xC000-0xCFFF is always bank 0, 0xD000-0xDFFF is bank 1, or 1-7 selected by SVBK on CGB
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+pub const WRAM_BANKS: usize = 8;
 const HRAM_SIZE: usize = (HRAM_END - HRAM_START + 1) as usize;
+const OAM_DMA_LENGTH: u16 = 0xA0;
+// Machine cycles between writing DMA and the first byte being copied
+const OAM_DMA_START_DELAY: u8 = 1;
+
+struct OamDma {
+    source: u16,
+    index: u16,
+    delay: u8,
+}
+
+// Summary of the work done by run_frame/run_cycles
+pub struct FrameResult {
+    // T-cycles (dots) elapsed
+    pub cycles: u32,
+    pub instructions: usize,
+    // False when the LCD was off and the frame ended after a fixed amount of cycles
+    pub vblank: bool,
+    // Set when run_frame_until stopped early because its condition held
+    pub condition_met: bool,
+}
+
+// A single step taken by step_recording
+pub struct StepRecord {
+    // Machine cycles
+    pub cycles: usize,
+    pub bus_cycles: Vec<BusCycle>,
+    // Set when the PPU entered VBlank during the step
+    pub vblank: bool,
+}
+
+// A view of memory for debug tools, either the address space or a single bank of a memory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    // 0x0000-0xFFFF as the CPU sees it, with the banks mapped at the moment
+    AddressSpace,
+    RomBank(usize),
+    CartRamBank(usize),
+    WramBank(usize),
+    VramBank(usize),
+}
+
+impl Region {
+    // Address the first byte of the region is mapped at
+    pub fn base_address(self) -> u16 {
+        match self {
+            Region::AddressSpace | Region::RomBank(0) => ROM_START,
+            Region::RomBank(_) => ROM_BANK_START,
+            Region::CartRamBank(_) => ERAM_START,
+            Region::WramBank(0) => WRAM_START,
+            Region::WramBank(_) => WRAM_START + WRAM_BANK_SIZE as u16,
+            Region::VramBank(_) => VRAM_START,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Region::AddressSpace => "Address Space".to_string(),
+            Region::RomBank(bank) => format!("ROM Bank {:02X}", bank),
+            Region::CartRamBank(bank) => format!("Cart RAM Bank {:02X}", bank),
+            Region::WramBank(bank) => format!("WRAM Bank {}", bank),
+            Region::VramBank(bank) => format!("VRAM Bank {}", bank),
+        }
+    }
+}
 
 pub struct GioBoyColor {
+    pub cpu: Cpu,
     pub rom: Rom,
-    pub ram: [u8; WRAM_SIZE],
+    pub ram: [[u8; WRAM_BANK_SIZE]; WRAM_BANKS],
+    wram_bank: usize,
     pub hram: [u8; HRAM_SIZE],
     pub gpu: Gpu,
-    pub registers: Registers,
+    pub compat_palettes: CompatPaletteConfig,
+    pub joypad: Joypad,
+    pub interrupt_flag: u8,
+    pub interrupt_enable: u8,
+    pub apu: Apu,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub infrared: Infrared,
+    // Present for monochrome games with SGB support, as if played on a Super Game Boy
+    pub sgb: Option<Sgb>,
+    pub cheats: Cheats,
+    // Set when the PPU enters VBlank, consumed by run_frame
+    frame_completed: bool,
+    dma_register: u8,
+    oam_dma: Option<OamDma>,
+    tracer: Option<Tracer>,
 }
 
 impl GioBoyColor {
     pub fn new() -> GioBoyColor {
         GioBoyColor {
+            cpu: Cpu::new(),
             rom: Rom::new(),
-            ram: [0; WRAM_SIZE],
+            ram: [[0; WRAM_BANK_SIZE]; WRAM_BANKS],
+            wram_bank: 1,
             hram: [0; HRAM_SIZE],
             gpu: Gpu::new(),
-            registers: Registers::new(),
+            compat_palettes: CompatPaletteConfig::new(),
+            joypad: Joypad::new(),
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            apu: Apu::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            infrared: Infrared::new(),
+            sgb: None,
+            cheats: Cheats::new(),
+            frame_completed: false,
+            dma_register: 0xFF,
+            oam_dma: None,
+            tracer: None,
+        }
+    }
+    // The machine is left untouched if the ROM can't be loaded
+    pub fn load_rom(&mut self, rom_path: &PathBuf) -> io::Result<()> {
+        self.rom.load(rom_path)?;
+        self.reset();
+        self.gpu.cgb_mode = self.rom.is_cgb();
+        self.serial.cgb_mode = self.gpu.cgb_mode;
+        self.infrared.cgb_mode = self.gpu.cgb_mode;
+        self.sgb = (self.rom.supports_sgb() && !self.rom.is_cgb()).then(Sgb::new);
+        self.joypad.set_players(1);
+
+        // Buttons held while the ROM boots act like holding them during the boot logo
+        self.compat_palettes.latch_buttons(&self.joypad.buttons());
+        self.apply_compat_palettes();
+        Ok(())
+    }
+    // Removes the cartridge, nothing runs until the next ROM is loaded
+    pub fn unload_rom(&mut self) {
+        self.rom = Rom::new();
+        self.sgb = None;
+        self.cheats = Cheats::new();
+        self.set_tracer(None);
+    }
+    // Powers the console back on, devices plugged into the ports stay connected
+    fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.ram = [[0; WRAM_BANK_SIZE]; WRAM_BANKS];
+        self.wram_bank = 1;
+        self.hram = [0; HRAM_SIZE];
+        self.gpu = Gpu::new();
+        self.interrupt_flag = 0;
+        self.interrupt_enable = 0;
+        let apu = std::mem::take(&mut self.apu);
+        self.apu.output = apu.output;
+        self.timer = Timer::new();
+        let device = self.serial.disconnect();
+        self.serial = Serial::new();
+        self.serial.connect(device);
+        let device = self.infrared.disconnect();
+        self.infrared = Infrared::new();
+        self.infrared.connect(device);
+        self.frame_completed = false;
+        self.dma_register = 0xFF;
+        self.oam_dma = None;
+    }
+    // Monochrome games get colorized the same way the CGB boot ROM does it
+    pub fn apply_compat_palettes(&mut self) {
+        if self.rom.is_loaded && !self.rom.is_cgb() {
+            let palettes = self.compat_palettes.select(&self.rom);
+            self.gpu.load_compat_palettes(&palettes);
+        }
+    }
+    // Palettes used for monochrome games that have no entry in the boot ROM table
+    pub fn set_fallback_palettes(&mut self, combo: BootCombo) {
+        self.compat_palettes.fallback = combo;
+        self.apply_compat_palettes();
+    }
+    // Updates the pressed buttons, should be called by the frontend every frame
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(Interrupt::Joypad);
         }
     }
-    pub fn load_rom(&mut self, rom_path: &PathBuf) {
-        self.rom.load(rom_path);
+    // Buttons of player 2, 3 or 4, for SGB games that asked for more players
+    pub fn set_player_buttons(&mut self, player: usize, buttons: Buttons) {
+        self.joypad.set_player_buttons(player, buttons);
     }
-    fn read(&self, address: u16) -> u8 {
-        // TODO: Check if a register has been requested
+    // What to display: the frame, or in SGB mode the colored frame inside the border
+    pub fn screen(&self) -> &[u32] {
+        match &self.sgb {
+            Some(sgb) => sgb.screen(),
+            None => self.gpu.frame_buffer(),
+        }
+    }
+    // Width and height of screen
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self.sgb {
+            Some(_) => (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
+    // Sample rate of the audio returned by take_audio_samples, e.g. 44100 or 48000 Hz
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+    // Returns the interleaved stereo samples (left, right) generated since the last call
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.output.take_samples()
+    }
+    pub fn flush_audio(&mut self, sink: &mut dyn AudioSink) -> io::Result<()> {
+        let samples = self.take_audio_samples();
+        sink.write_samples(&samples)
+    }
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+    // Serializes the whole machine, see save_state for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.write_bytes(&MAGIC);
+        writer.write_u16(VERSION);
+        writer.write_u32(self.rom.checksum());
+        self.save_components(&mut writer);
+
+        writer.into_bytes()
+    }
+    // Restores a state made by save_state, the machine is left untouched if it is rejected
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if !self.rom.is_loaded {
+            return Err(SaveStateError::NoRomLoaded);
+        }
+
+        let mut reader = StateReader::new(state);
 
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        if reader.read_u32()? != self.rom.checksum() {
+            return Err(SaveStateError::RomMismatch);
+        }
+
+        // A corrupted body is only noticed halfway through, so keep a copy to roll back to
+        let mut backup = StateWriter::new();
+        self.save_components(&mut backup);
+
+        let result = self
+            .load_components(&mut reader)
+            .and_then(|_| reader.finish());
+
+        if result.is_err() {
+            let backup = backup.into_bytes();
+            self.load_components(&mut StateReader::new(&backup))
+                .expect("Unable to restore the state from before loading");
+        }
+
+        result
+    }
+    fn save_components(&self, writer: &mut StateWriter) {
+        self.cpu.save_state(writer);
+        writer.write_u8(self.interrupt_flag);
+        writer.write_u8(self.interrupt_enable);
+        for bank in &self.ram {
+            writer.write_bytes(bank);
+        }
+        writer.write_u8(self.wram_bank as u8);
+        writer.write_bytes(&self.hram);
+        writer.write_u8(self.dma_register);
+        match &self.oam_dma {
+            Some(dma) => {
+                writer.write_bool(true);
+                writer.write_u16(dma.source);
+                writer.write_u16(dma.index);
+                writer.write_u8(dma.delay);
+            }
+            None => writer.write_bool(false),
+        }
+        self.rom.save_state(writer);
+        self.gpu.save_state(writer);
+        self.timer.save_state(writer);
+        self.serial.save_state(writer);
+        self.infrared.save_state(writer);
+        self.apu.save_state(writer);
+        self.joypad.save_state(writer);
+        match &self.sgb {
+            Some(sgb) => {
+                writer.write_bool(true);
+                sgb.save_state(writer);
+            }
+            None => writer.write_bool(false),
+        }
+    }
+    fn load_components(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cpu.load_state(reader)?;
+        self.interrupt_flag = reader.read_u8()?;
+        self.interrupt_enable = reader.read_u8()?;
+        for bank in self.ram.iter_mut() {
+            reader.read_bytes(bank)?;
+        }
+        self.wram_bank = reader.read_u8()? as usize;
+        if !(1..WRAM_BANKS).contains(&self.wram_bank) {
+            return Err(SaveStateError::InvalidData);
+        }
+        reader.read_bytes(&mut self.hram)?;
+        self.dma_register = reader.read_u8()?;
+        self.oam_dma = if reader.read_bool()? {
+            let dma = OamDma {
+                source: reader.read_u16()?,
+                index: reader.read_u16()?,
+                delay: reader.read_u8()?,
+            };
+            if dma.index >= OAM_DMA_LENGTH {
+                return Err(SaveStateError::InvalidData);
+            }
+            Some(dma)
+        } else {
+            None
+        };
+        self.rom.load_state(reader)?;
+        self.gpu.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.serial.load_state(reader)?;
+        self.infrared.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        if reader.read_bool()? != self.sgb.is_some() {
+            return Err(SaveStateError::InvalidData);
+        }
+        match &mut self.sgb {
+            Some(sgb) => sgb.load_state(reader),
+            None => Ok(()),
+        }
+    }
+    // Starts logging every executed instruction, or stops with None
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        if let Some(mut previous) = std::mem::replace(&mut self.tracer, tracer) {
+            if let Err(error) = previous.flush() {
+                println!("Unable to write trace: {}", error);
+            }
+        }
+    }
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+    // Reads a byte without ticking the system, for tools inspecting memory. Reads never have
+    // side effects, and OAM can be seen during a DMA transfer
+    pub fn peek(&self, address: u16) -> u8 {
         match address {
-            ROM_START..=ROM_END => self.rom.read(address),
-            VRAM_START..=VRAM_END => self.gpu.read(address),
-            ERAM_START..=ERAM_END => self.rom.read(address),
-            WRAM_START..=WRAM_END => self.ram[(address - WRAM_START) as usize],
-            ECHO_START..=ECHO_END => self.ram[(address - ECHO_START) as usize],
-            OAM_START..=OAM_END => self.gpu.read(ECHO_START),
-            HRAM_START..=HRAM_END => self.hram[(address - WRAM_START) as usize],
-            _ => panic!("Attempted to read from an invalid address: {}", address),
+            OAM_START..=OAM_END => self.gpu.read_oam(address),
+            _ => self.try_read_bus(address).unwrap_or(0xFF),
         }
     }
 
-    fn write(&mut self, address: u16, data: u8) {
-        // TODO: Check write to registers
+    // Writes a byte without ticking the system, for tools editing memory. Writes go through
+    // the memory map, so they can switch banks or start a DMA transfer like the CPU would
+    pub fn poke(&mut self, address: u16, data: u8) {
+        self.write_bus(address, data);
+    }
+
+    // Every region of the loaded game, starting with the address space. Only CGB games have
+    // the second VRAM bank and WRAM banks 2 to 7
+    pub fn regions(&self) -> Vec<Region> {
+        let (vram_banks, wram_banks) = if self.gpu.cgb_mode {
+            (VRAM_BANKS, WRAM_BANKS)
+        } else {
+            (1, 2)
+        };
+
+        std::iter::once(Region::AddressSpace)
+            .chain((0..self.rom.rom_bank_count()).map(Region::RomBank))
+            .chain((0..self.rom.ram_bank_count()).map(Region::CartRamBank))
+            .chain((0..wram_banks).map(Region::WramBank))
+            .chain((0..vram_banks).map(Region::VramBank))
+            .collect()
+    }
+
+    pub fn region_size(&self, region: Region) -> usize {
+        match region {
+            Region::AddressSpace => 0x10000,
+            Region::RomBank(_) => ROM_BANK_SIZE,
+            Region::CartRamBank(_) => RAM_BANK_SIZE,
+            Region::WramBank(_) => WRAM_BANK_SIZE,
+            Region::VramBank(_) => VRAM_BANK_SIZE,
+        }
+    }
+
+    // Like peek, but reads banks directly instead of through the MBC, VBK and SVBK
+    pub fn peek_region(&self, region: Region, offset: usize) -> u8 {
+        match region {
+            Region::AddressSpace => self.peek(offset as u16),
+            Region::RomBank(bank) => self.rom.peek_rom(bank * ROM_BANK_SIZE + offset),
+            Region::CartRamBank(bank) => self.rom.peek_ram(bank * RAM_BANK_SIZE + offset),
+            Region::WramBank(bank) => self.ram[bank][offset],
+            Region::VramBank(bank) => self.gpu.peek_vram(bank, offset),
+        }
+    }
+
+    // Like poke, but writes banks directly, without side effects
+    pub fn poke_region(&mut self, region: Region, offset: usize, data: u8) {
+        match region {
+            Region::AddressSpace => self.poke(offset as u16, data),
+            Region::RomBank(bank) => self.rom.poke_rom(bank * ROM_BANK_SIZE + offset, data),
+            Region::CartRamBank(bank) => self.rom.poke_ram(bank * RAM_BANK_SIZE + offset, data),
+            Region::WramBank(bank) => self.ram[bank][offset] = data,
+            Region::VramBank(bank) => self.gpu.poke_vram(bank, offset, data),
+        }
+    }
+
+    fn read_bus(&self, address: u16) -> u8 {
+        self.try_read_bus(address).unwrap_or(0xFF)
+    }
+
+    // None for the unusable area after OAM
+    fn try_read_bus(&self, address: u16) -> Option<u8> {
+        let data = match address {
+            ROM_START..=ROM_BANK_END => self.cheats.patch_rom(address, self.rom.read(address)),
+            VRAM_START..=VRAM_END => self.gpu.read_vram(address),
+            ERAM_START..=ERAM_END => self.rom.read_ram(address),
+            WRAM_START..=WRAM_END | ECHO_START..=ECHO_END => {
+                let (bank, offset) = self.wram_location(address);
+                self.ram[bank][offset]
+            }
+            // OAM is not accessible while a DMA transfer is running
+            OAM_START..=OAM_END if self.oam_dma.is_some() => 0xFF,
+            OAM_START..=OAM_END => self.gpu.read_oam(address),
+            IO_REGISTERS_START..=IO_REGISTERS_END => self.read_io(address),
+            HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize],
+            IE => self.interrupt_enable,
+            _ => return None,
+        };
+
+        Some(data)
+    }
+
+    // Registers that aren't emulated, or don't exist, read as 0xFF
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            JOYP => self.joypad.read(),
+            SB | SC => self.serial.read(address),
+            RP => self.infrared.read(),
+            DIV..=TAC => self.timer.read(address),
+            IF => IF_UNUSED_BITS | self.interrupt_flag,
+            AUDIO_START..=AUDIO_END => self.apu.read(address),
+            DMA => self.dma_register,
+            LCDC..=WX | VBK | BCPS..=OCPD => self.gpu.read_register(address),
+            SVBK if self.gpu.cgb_mode => 0xF8 | self.wram_bank as u8,
+            _ => 0xFF,
+        }
+    }
+
+    // Bank and offset of a WRAM or ECHO RAM address, ECHO RAM mirrors 0xC000-0xDDFF
+    fn wram_location(&self, address: u16) -> (usize, usize) {
+        let offset = (address - WRAM_START) as usize % (WRAM_BANK_SIZE * 2);
+
+        if offset < WRAM_BANK_SIZE {
+            (0, offset)
+        } else {
+            (self.wram_bank, offset - WRAM_BANK_SIZE)
+        }
+    }
 
+    fn write_bus(&mut self, address: u16, data: u8) {
         match address {
-            ROM_START..=ROM_END => self.rom.write(address, data),
-            VRAM_START..=VRAM_END => self.gpu.write(address, data),
-            ERAM_START..=ERAM_END => self.rom.write(address, data),
-            WRAM_START..=WRAM_END => self.ram[(address - WRAM_START) as usize] = data,
-            ECHO_START..=ECHO_END => {
-                // Note: Use of the area from 0xE000 to 0xFDFF is prohibited.
-                self.ram[(address - ECHO_START) as usize] = data;
-                panic!("Attempted to write to ECHO RAM");
-            }
-            OAM_START..=OAM_END => self.gpu.write(address, data),
+            ROM_START..=ROM_BANK_END => self.rom.write(address, data),
+            VRAM_START..=VRAM_END => self.gpu.write_vram(address, data),
+            ERAM_START..=ERAM_END => self.rom.write_ram(address, data),
+            // Use of ECHO RAM is prohibited, but it works like WRAM
+            WRAM_START..=WRAM_END | ECHO_START..=ECHO_END => {
+                let (bank, offset) = self.wram_location(address);
+                self.ram[bank][offset] = data;
+            }
+            OAM_START..=OAM_END if self.oam_dma.is_some() => (),
+            OAM_START..=OAM_END => self.gpu.write_oam(address, data),
+            IO_REGISTERS_START..=IO_REGISTERS_END => self.write_io(address, data),
             HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize] = data,
-            _ => panic!("Attempted to write at an invalid address: {}", address),
+            IE => self.interrupt_enable = data,
+            // The unusable area after OAM
+            _ => (),
+        }
+    }
+
+    fn write_io(&mut self, address: u16, data: u8) {
+        match address {
+            JOYP => {
+                if self.joypad.write(data) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+                // The SGB listens to the same lines for its packets
+                if let Some(players) = self.sgb.as_mut().and_then(|sgb| sgb.write_joypad(data)) {
+                    self.joypad.set_players(players);
+                }
+            }
+            SB | SC => self.serial.write(address, data),
+            RP => self.infrared.write(data),
+            DIV..=TAC => {
+                let overflowed = self.timer.write(address, data);
+                if overflowed {
+                    self.request_interrupt(Interrupt::Timer);
+                }
+            }
+            IF => self.interrupt_flag = data & !IF_UNUSED_BITS,
+            AUDIO_START..=AUDIO_END => self.apu.write(address, data),
+            DMA => {
+                self.dma_register = data;
+                self.oam_dma = Some(OamDma {
+                    source: combine!(data, 0x00),
+                    index: 0,
+                    delay: OAM_DMA_START_DELAY,
+                });
+            }
+            LCDC..=WX | VBK | BCPS..=OCPD => self.gpu.write_register(address, data),
+            // Bank 0 can't be mapped at 0xD000, selecting it maps bank 1
+            SVBK if self.gpu.cgb_mode => self.wram_bank = ((data & 0x07) as usize).max(1),
+            // Registers that aren't emulated, or don't exist, ignore writes
+            _ => (),
         }
     }
 
-    fn next_byte(&mut self) -> u8 {
-        let byte = self.read(self.registers.pc);
-        self.registers.pc = self.registers.pc.wrapping_add(1);
-        byte
+    // Copies one byte per machine cycle from 0xXX00 to OAM, until 160 bytes are copied. Sources
+    // from 0xE000 read WRAM like ECHO RAM does, even past it
+    fn tick_oam_dma(&mut self) {
+        let Some(mut dma) = self.oam_dma.take() else {
+            return;
+        };
+
+        if dma.delay > 0 {
+            dma.delay -= 1;
+            self.oam_dma = Some(dma);
+            return;
+        }
+
+        let source = match dma.source {
+            ECHO_START.. => dma.source - (ECHO_START - WRAM_START),
+            _ => dma.source,
+        };
+        let data = self.try_read_bus(source + dma.index).unwrap_or(0xFF);
+        self.gpu.write_oam(OAM_START + dma.index, data);
+        dma.index += 1;
+
+        if dma.index < OAM_DMA_LENGTH {
+            self.oam_dma = Some(dma);
+        }
+    }
+
+    // Runs until VBlank completes, when the PPU goes back to line 0, or a frame worth of cycles
+    // if the LCD is off
+    pub fn run_frame(&mut self) -> FrameResult {
+        self.run_until(DOTS_PER_FRAME, true, |_| false)
     }
 
-    // Returns the next word (pointer, little endian)
-    fn next_pointer(&mut self) -> u16 {
-        let low = self.next_byte();
-        let high = self.next_byte();
-        combine!(high, low)
+    // Like run_frame, but also stops as soon as the condition holds after an instruction
+    pub fn run_frame_until<F>(&mut self, condition: F) -> FrameResult
+    where
+        F: FnMut(&GioBoyColor) -> bool,
+    {
+        self.run_until(DOTS_PER_FRAME, true, condition)
+    }
+
+    // Runs for at least the given amount of T-cycles
+    pub fn run_cycles(&mut self, cycles: u32) -> FrameResult {
+        self.run_until(cycles, false, |_| false)
+    }
+
+    // Like run_cycles, but also stops as soon as the condition holds after an instruction
+    pub fn run_cycles_until<F>(&mut self, cycles: u32, condition: F) -> FrameResult
+    where
+        F: FnMut(&GioBoyColor) -> bool,
+    {
+        self.run_until(cycles, false, condition)
+    }
+
+    fn run_until<F>(
+        &mut self,
+        max_cycles: u32,
+        stop_at_vblank: bool,
+        mut condition: F,
+    ) -> FrameResult
+    where
+        F: FnMut(&GioBoyColor) -> bool,
+    {
+        self.frame_completed = false;
+
+        let mut result = FrameResult {
+            cycles: 0,
+            instructions: 0,
+            vblank: false,
+            condition_met: false,
+        };
+
+        // Once VBlank has started, the frame runs to its end even past max_cycles
+        while result.cycles < max_cycles || result.vblank {
+            result.cycles += self.step() as u32 * 4;
+            result.instructions += 1;
+
+            if condition(self) {
+                result.condition_met = true;
+                break;
+            }
+
+            if stop_at_vblank && self.frame_completed {
+                result.vblank = true;
+            }
+            if result.vblank && self.gpu.mode() != Mode::VBlank {
+                break;
+            }
+        }
+
+        result
     }
 
     // Perform a CPU step, returns machine cycles
     pub fn step(&mut self) -> usize {
-        // Read opcode at PC
-        let opcode = self.next_byte();
-
-        // Execute instruction
-        self.execute_instruction(opcode)
-    }
-
-    // Pushes 16 bit data onto the stack
-	fn push(&mut self, data: u16) {
-		self.registers.sp = self.registers.sp.wrapping_sub(1);
-		self.write(self.registers.sp, high!(data));
-		self.registers.sp = self.registers.sp.wrapping_sub(1);
-		self.write(self.registers.sp, low!(data));
-	}
-
-	// Pops highest 16 bits from stack
-	fn pop(&mut self) -> u16 {
-		let low = self.read(self.registers.sp);
-		self.registers.sp = self.registers.sp.wrapping_add(1);
-		let high = self.read(self.registers.sp);
-		self.registers.sp = self.registers.sp.wrapping_add(1);
-		combine!(high, low)
-	}
-
-    #[rustfmt::skip]
-    fn execute_instruction(&mut self, opcode: u8) -> usize {
-    match opcode {
-        0x00 => { 1 },   // NOP
-        0x10 => { 1 },  // STOP
-
-        //  8-bit load instructions
-        // LD r, r'
-        0x78 => { self.registers.a = self.registers.b; 1 }, // LD A, B
-        0x79 => { self.registers.a = self.registers.c; 1 }, // LD A, C
-        0x7a => { self.registers.a = self.registers.d; 1 }, // LD A, D
-        0x7b => { self.registers.a = self.registers.e; 1 }, // LD A, E
-        0x7c => { self.registers.a = self.registers.h; 1 }, // LD A, H
-        0x7d => { self.registers.a = self.registers.l; 1 }, // LD A, L
-        0x7f => { 1 },                                    // LD A, A
-        0x47 => { self.registers.b = self.registers.a; 1 }, // LD B, A
-        0x40 => { 1 },                                    // LD B, B
-        0x41 => { self.registers.b = self.registers.c; 1 }, // LD B, C
-        0x42 => { self.registers.b = self.registers.d; 1 }, // LD B, D
-        0x43 => { self.registers.b = self.registers.e; 1 }, // LD B, E
-        0x44 => { self.registers.b = self.registers.h; 1 }, // LD B, H
-        0x45 => { self.registers.b = self.registers.l; 1 }, // LD B, L
-        0x4f => { self.registers.c = self.registers.a; 1 }, // LD C, A
-        0x48 => { self.registers.c = self.registers.b; 1 }, // LD C, B
-        0x49 => { 1 },                                    // LD C, C
-        0x4a => { self.registers.c = self.registers.d; 1 }, // LD C, D
-        0x4b => { self.registers.c = self.registers.e; 1 }, // LD C, E
-        0x4c => { self.registers.c = self.registers.h; 1 }, // LD C, H
-        0x4d => { self.registers.c = self.registers.l; 1 }, // LD C, L
-        0x57 => { self.registers.d = self.registers.a; 1 }, // LD D, A
-        0x50 => { self.registers.d = self.registers.b; 1 }, // LD D, B
-        0x51 => { self.registers.d = self.registers.c; 1 }, // LD D, C
-        0x52 => { 1 },                                    // LD D, D
-        0x53 => { self.registers.d = self.registers.e; 1 }, // LD D, E
-        0x54 => { self.registers.d = self.registers.h; 1 }, // LD D, H
-        0x55 => { self.registers.d = self.registers.l; 1 }, // LD D, L
-        0x5F => { self.registers.e = self.registers.a; 1 }, // LD E, A
-        0x58 => { self.registers.e = self.registers.b; 1 }, // LD E, B
-        0x59 => { self.registers.e = self.registers.c; 1 }, // LD E, C
-        0x5a => { self.registers.e = self.registers.d; 1 }, // LD E, D
-        0x5b => { 1 },                                    // LD E, E
-        0x5c => { self.registers.e = self.registers.h; 1 }, // LD E, H
-        0x5d => { self.registers.e = self.registers.l; 1 }, // LD E, L
-        0x67 => { self.registers.h = self.registers.a; 1 }, // LD H, A
-        0x60 => { self.registers.h = self.registers.b; 1 }, // LD H, B
-        0x61 => { self.registers.h = self.registers.c; 1 }, // LD H, C
-        0x62 => { self.registers.h = self.registers.d; 1 }, // LD H, D
-        0x63 => { self.registers.h = self.registers.e; 1 }, // LD H, E
-        0x64 => { 1 },                                    // LD H, H
-        0x65 => { self.registers.h = self.registers.l; 1 }, // LD H, E
-        0x6f => { self.registers.l = self.registers.a; 1 }, // LD L, A
-        0x68 => { self.registers.l = self.registers.b; 1 }, // LD L, B
-        0x69 => { self.registers.l = self.registers.c; 1 }, // LD L, C
-        0x6a => { self.registers.l = self.registers.d; 1 }, // LD L, D
-        0x6b => { self.registers.l = self.registers.e; 1 }, // LD L, E
-        0x6c => { self.registers.l = self.registers.h; 1 }, // LD L, H
-        0x6d => { 1 },                                    // LD L, L
-        // LD r, n
-        0x3e => { self.registers.a = self.next_byte(); 2 }, // LD A, n
-        0x06 => { self.registers.b = self.next_byte(); 2 }, // LD B, n
-        0x0e => { self.registers.c = self.next_byte(); 2 }, // LD C, n
-        0x16 => { self.registers.d = self.next_byte(); 2 }, // LD D, n
-        0x1e => { self.registers.e = self.next_byte(); 2 }, // LD E, n
-        0x26 => { self.registers.h = self.next_byte(); 2 }, // LD H, n
-        0x2e => { self.registers.l = self.next_byte(); 2 }, // LD L, n
-        // LD r, (HL)
-        0x7e => { self.registers.a = self.read(self.registers.hl()); 2 },
-        0x46 => { self.registers.b = self.read(self.registers.hl()); 2 },
-        0x4e => { self.registers.c = self.read(self.registers.hl()); 2 },
-        0x56 => { self.registers.d = self.read(self.registers.hl()); 2 },
-        0x5e => { self.registers.e = self.read(self.registers.hl()); 2 },
-        0x66 => { self.registers.h = self.read(self.registers.hl()); 2 },
-        0x6e => { self.registers.l = self.read(self.registers.hl()); 2 },
-        // LD (HL), r
-        0x77 => { self.write(self.registers.hl(), self.registers.a); 2 },
-        0x70 => { self.write(self.registers.hl(), self.registers.b); 2 },
-        0x71 => { self.write(self.registers.hl(), self.registers.c); 2 },
-        0x72 => { self.write(self.registers.hl(), self.registers.d); 2 },
-        0x73 => { self.write(self.registers.hl(), self.registers.e); 2 },
-        0x74 => { self.write(self.registers.hl(), self.registers.h); 2 },
-        0x75 => { self.write(self.registers.hl(), self.registers.l); 2 },
-        // LD (HL), n
-        0x36 => { let n = self.next_byte(); self.write(self.registers.hl(), n); 3 },
-        // LD A, (BC)
-        0x0a => { self.registers.a = self.read(self.registers.bc()); 2 },
-        // LD A, (DE)
-        0x1a => { self.registers.a = self.read(self.registers.de()); 2 },
-        // LD (BC), A 
-        0x02 => { self.write(self.registers.bc(), self.registers.a); 2 },
-        // LD (DE), A 
-        0x12 => { self.write(self.registers.de(), self.registers.a); 2 },
-        // LD A, (nn) 
-        0xfa => { let nn = self.next_pointer(); self.registers.a = self.read(nn); 4 },
-        // LD (nn), A 
-        0xea => { let nn = self.next_pointer(); self.write(nn, self.registers.a); 4 },
-        // LDH A, (C)
-        0xf2 => { self.registers.a = self.read(combine!(0xff, self.registers.c)); 2 },
-        // LDH (C), A
-        0xe2 => { self.write(combine!(0xff, self.registers.c), self.registers.a); 2 },
-        // LDH A, n
-        0xf0 => { let n = self.next_byte(); self.registers.a = self.read(combine!(0xff, n)); 3 },
-        // LDH (n), A
-        0xe0 => { let n = self.next_byte(); self.write(combine!(0xff, n), self.registers.a); 3 },
-        // LD A, (HL-)
-        0x3a => { self.registers.a = self.read(self.registers.hl()); self.registers.hld(); 2 },
-        // LDH (HL-), A
-        0x32 => { self.write(self.registers.hl(), self.registers.a); self.registers.hld(); 2 },
-        // LD A, (HL+)
-        0x2a => { self.registers.a = self.read(self.registers.hl()); self.registers.hli(); 2 },
-        // LDH (HL+), A
-        0x22 => { self.write(self.registers.hl(), self.registers.a); self.registers.hli(); 2 },
-
-        //  16-bit load instructions
-        // LD rr, nn
-        0x01 => { let nn = self.next_pointer(); self.registers.set_bc(nn); 3 } // LD BC, nn
-        0x11 => { let nn = self.next_pointer(); self.registers.set_de(nn); 3 } // LD DE, nn
-        0x21 => { let nn = self.next_pointer(); self.registers.set_hl(nn); 3 } // LD HL, nn
-        0x31 => { let nn = self.next_pointer(); self.registers.sp = nn; 3 } // LD SP, nn
-        // LD (nn), SP
-        0x08 => { 
-            let nn = self.next_pointer(); 
-            self.write(nn, low!(self.registers.sp)); 
-            self.write(nn + 1, high!(self.registers.sp));  
-            5 
-        },
-        // LD SP, HL
-        0xf9 => { self.registers.sp = self.registers.hl() ; 2 } 
-        // PUSH rr
-        0xc5 => { self.push(self.registers.bc()); 4 } // PUSH BC
-        0xd5 => { self.push(self.registers.de()); 4 } // PUSH DE
-        0xe5 => { self.push(self.registers.hl()); 4 } // PUSH HL
-        0xf5 => { self.push(self.registers.af()); 4 } // PUSH AF
-        // POP rr
-        0xc1 => { let val = self.pop(); self.registers.set_bc(val); 3 },
-        0xd1 => { let val = self.pop(); self.registers.set_de(val); 3 },
-        0xe1 => { let val = self.pop(); self.registers.set_hl(val); 3 },
-        0xf1 => {
-            // Lower 4 bits of register F (unused flag bits) are set to zero
-            let val = self.pop() & 0xFFF0;
-            self.registers.set_af(val);
-            3
-        },
-        _ => { panic!("Unsupported operation {:02X}", opcode); }
+        self.step_cpu(false).0
+    }
+
+    // Like step, but also records the bus activity of every machine cycle, for debugging
+    pub fn step_recording(&mut self) -> StepRecord {
+        self.frame_completed = false;
+        let (cycles, bus_cycles) = self.step_cpu(true);
+
+        StepRecord {
+            cycles,
+            bus_cycles,
+            vblank: self.frame_completed,
+        }
+    }
+
+    fn step_cpu(&mut self, recording: bool) -> (usize, Vec<BusCycle>) {
+        // The tracer is moved out while logging, since it reads the rest of the machine
+        let mut tracer = self.tracer.take();
+        if let Some(active) = &mut tracer {
+            if self.cpu.executes_instruction(self) {
+                if let Err(error) = active.log(self) {
+                    println!("Unable to write trace, tracing stopped: {}", error);
+                    tracer = None;
+                }
+            }
+        }
+
+        // The CPU is moved out while it runs, so the rest of the machine can act as its bus
+        let mut cpu = std::mem::take(&mut self.cpu);
+        let (cycles, bus_cycles) = if recording {
+            let mut bus = TracingBus::new(&mut *self);
+            let cycles = cpu.step(&mut bus);
+            (cycles, bus.take_cycles())
+        } else {
+            (cpu.step(self), Vec::new())
+        };
+        self.cpu = cpu;
+
+        if let Some(active) = &mut tracer {
+            active.add_cycles(cycles);
+        }
+        self.tracer = tracer;
+
+        (cycles, bus_cycles)
+    }
+
+    // Advances the rest of the system by the given machine cycles
+    fn tick_components(&mut self, cycles: usize) {
+        // Machine cycles are 4 T-cycles long
+        let t_cycles = cycles as u32 * 4;
+
+        for _ in 0..cycles {
+            self.tick_oam_dma();
+        }
+
+        let gpu_interrupts = self.gpu.tick(t_cycles);
+        if gpu_interrupts & Interrupt::VBlank.bit() != 0 {
+            self.frame_completed = true;
+            if let Some(sgb) = &mut self.sgb {
+                sgb.end_frame(self.gpu.shade_buffer());
+            }
+            self.apply_ram_cheats();
+        }
+        self.interrupt_flag |= gpu_interrupts;
+
+        if self.timer.tick(t_cycles) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+
+        if self.serial.tick(t_cycles) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+
+        self.infrared.tick(t_cycles);
+
+        self.apu.tick(t_cycles);
+    }
+
+    // GameShark codes, written at the start of every VBlank
+    fn apply_ram_cheats(&mut self) {
+        let switchable_bank = WRAM_START + WRAM_BANK_SIZE as u16;
+
+        for (bank, address, value) in self.cheats.ram_writes() {
+            match bank {
+                // Only CGB games have WRAM banks to choose from
+                Some(bank)
+                    if self.gpu.cgb_mode && (switchable_bank..=WRAM_END).contains(&address) =>
+                {
+                    // Like SVBK, bank 0 selects bank 1
+                    let region = Region::WramBank(bank.max(1));
+                    self.poke_region(region, (address - switchable_bank) as usize, value);
+                }
+                _ => self.poke(address, value),
+            }
+        }
+    }
+}
+
+impl Default for GioBoyColor {
+    fn default() -> GioBoyColor {
+        GioBoyColor::new()
+    }
+}
+
+// The rest of the machine as seen by the CPU, every access advances it by one machine cycle
+impl Bus for GioBoyColor {
+    fn read(&mut self, address: u16) -> u8 {
+        self.tick_components(1);
+        self.read_bus(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.tick_components(1);
+        self.write_bus(address, data);
+    }
+
+    fn tick(&mut self) {
+        self.tick_components(1);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        GioBoyColor::peek(self, address)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & !IF_UNUSED_BITS
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY1: u16 = 0xFF4D;
+    const BOOT: u16 = 0xFF50;
+
+    #[test]
+    fn unmapped_io_reads_ff_and_ignores_writes() {
+        let mut gbc = GioBoyColor::new();
+
+        for address in [0xFF03, KEY1, BOOT, 0xFF51, 0xFF55, 0xFF7F] {
+            Bus::write(&mut gbc, address, 0x12);
+            assert_eq!(Bus::read(&mut gbc, address), 0xFF, "{:04X}", address);
+        }
+        // The unusable area after OAM
+        Bus::write(&mut gbc, UNUSED_START, 0x12);
+        assert_eq!(Bus::read(&mut gbc, UNUSED_END), 0xFF);
+    }
+
+    #[test]
+    fn bus_peek_matches_peek() {
+        let mut gbc = GioBoyColor::new();
+        gbc.poke(OAM_START, 0x12);
+        Bus::write(&mut gbc, DMA, 0xC0);
+
+        // OAM during a DMA transfer, ECHO RAM, unmapped IO and the unusable area
+        for address in [OAM_START, ECHO_START, KEY1, UNUSED_START] {
+            assert_eq!(
+                Bus::peek(&gbc, address),
+                gbc.peek(address),
+                "{:04X}",
+                address
+            );
+        }
+        assert_eq!(Bus::peek(&gbc, OAM_START), 0x12);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut gbc = GioBoyColor::new();
+
+        Bus::write(&mut gbc, ECHO_START + 0x10, 0x12);
+        assert_eq!(Bus::read(&mut gbc, WRAM_START + 0x10), 0x12);
+        Bus::write(&mut gbc, WRAM_START + 0x20, 0x34);
+        assert_eq!(Bus::read(&mut gbc, ECHO_START + 0x20), 0x34);
+    }
+
+    #[test]
+    fn oam_dma_from_high_pages_reads_wram() {
+        for page in [0xE0, 0xFE, 0xFF] {
+            let mut gbc = GioBoyColor::new();
+            let source = combine!(page, 0x00) - (ECHO_START - WRAM_START);
+            for index in 0..OAM_DMA_LENGTH {
+                gbc.poke(source + index, index as u8);
+            }
+
+            Bus::write(&mut gbc, DMA, page);
+            for _ in 0..=OAM_DMA_LENGTH {
+                Bus::tick(&mut gbc);
+            }
+
+            assert!(gbc.oam_dma.is_none());
+            for index in 0..OAM_DMA_LENGTH {
+                assert_eq!(gbc.peek(OAM_START + index), index as u8, "{:02X}", page);
+            }
+        }
+    }
+
+    #[test]
+    fn serial_transfers_request_an_interrupt() {
+        let mut gbc = GioBoyColor::new();
+        Bus::write(&mut gbc, SC, 0x81);
+
+        // 8 bits at 8192 Hz, 4 T-cycles per tick
+        for _ in 0..8 * 512 / 4 - 1 {
+            Bus::tick(&mut gbc);
+        }
+        assert_eq!(gbc.interrupt_flag & 0x08, 0);
+        Bus::tick(&mut gbc);
+        assert_eq!(gbc.interrupt_flag & 0x08, 0x08);
+        assert_eq!(Bus::read(&mut gbc, SC) & 0x80, 0);
+    }
 }
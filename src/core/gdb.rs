@@ -0,0 +1,803 @@
+//! GDB remote serial protocol stub
+//!
+//! Lets a GDB or LLDB compatible client attach over TCP on localhost. The stub is polled by the
+//! frontend every frame and never blocks, execution goes through the Debugger so breakpoints
+//! and watchpoints are shared with the console. The target stops as soon as a client attaches.
+//!
+//! Packet                 Explanation                                                  \
+//! ?                      Why the target stopped                                       \
+//! g, G                   Read or write all registers                                  \
+//! p n, P n=v             Read or write a single register                              \
+//! m addr,len             Read up to 0x800 bytes of memory through the memory map      \
+//! M addr,len:data        Write memory through the memory map                          \
+//! c, s, vCont            Continue or single-step, optionally from an address          \
+//! Z0/z0, Z1/z1           Insert or remove a breakpoint, software and hardware alike   \
+//! Z2/z2, Z3/z3, Z4/z4    Insert or remove a write, read or access watchpoint          \
+//! qXfer:features:read    Target description, see TARGET_XML                           \
+//! qRegisterInfo n        Register description for LLDB                                \
+//! D, k                   Detach, removing the client's breakpoints and resuming       \
+//!
+//! Registers are numbered a, f, b, c, d, e, h, l, sp, pc, and sent little endian.
+//!
+//! (Protocol described in the [GDB manual](https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html))
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+use crate::core::{
+    debugger::{Access, Breakpoint, Debugger, StopReason, Watchpoint},
+    gbc::GioBoyColor,
+};
+
+pub const DEFAULT_PORT: u16 = 2345;
+
+// Largest packet accepted from the client, in bytes
+const PACKET_SIZE: usize = 0x1000;
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+// Sent by the client to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gioboycolor.sm83">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="f" bitsize="8" type="uint8"/>
+    <reg name="b" bitsize="8" type="uint8"/>
+    <reg name="c" bitsize="8" type="uint8"/>
+    <reg name="d" bitsize="8" type="uint8"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+    <reg name="h" bitsize="8" type="uint8"/>
+    <reg name="l" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Name, size in bytes and LLDB generic name of every register, in protocol order
+const REGISTERS: [(&str, usize, Option<&str>); 10] = [
+    ("a", 1, None),
+    ("f", 1, Some("flags")),
+    ("b", 1, None),
+    ("c", 1, None),
+    ("d", 1, None),
+    ("e", 1, None),
+    ("h", 1, None),
+    ("l", 1, None),
+    ("sp", 2, Some("sp")),
+    ("pc", 2, Some("pc")),
+];
+
+struct Client {
+    stream: TcpStream,
+    // Received bytes not forming a complete packet yet
+    input: Vec<u8>,
+    no_ack: bool,
+    // Continued, the stop reply is sent once the debugger stops
+    running: bool,
+    // Added by this client, removed again when it detaches
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+impl GdbServer {
+    // Listens on localhost only, the protocol has no authentication
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbServer {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener
+            .local_addr()
+            .map_or(0, |address| address.port())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Accepts a client and handles the packets received since the last call
+    pub fn poll(&mut self, debugger: &mut Debugger, gbc: &mut GioBoyColor) {
+        if self.client.is_none() {
+            self.accept(debugger);
+        }
+
+        let Some(client) = &mut self.client else {
+            return;
+        };
+
+        let mut buffer = [0; PACKET_SIZE];
+        let connected = loop {
+            match client.stream.read(&mut buffer) {
+                Ok(0) => break false,
+                Ok(length) => client.input.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break true,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(_) => break false,
+            }
+        };
+
+        let connected = connected && client.handle_input(debugger, gbc).is_ok();
+
+        if !connected {
+            self.disconnect(debugger);
+        }
+    }
+
+    // Sends the stop reply the client is waiting for after continuing
+    pub fn report_stop(&mut self, reason: StopReason, debugger: &mut Debugger) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+
+        if client.running {
+            client.running = false;
+            if client.send(&stop_reply(reason)).is_err() {
+                self.disconnect(debugger);
+            }
+        }
+    }
+
+    fn accept(&mut self, debugger: &mut Debugger) {
+        let Ok((stream, address)) = self.listener.accept() else {
+            return;
+        };
+
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        // Replies are small and the client waits for each of them
+        let _ = stream.set_nodelay(true);
+
+        println!("GDB client connected from {}", address);
+        debugger.pause();
+
+        self.client = Some(Client {
+            stream,
+            input: Vec::new(),
+            no_ack: false,
+            running: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        });
+    }
+
+    fn disconnect(&mut self, debugger: &mut Debugger) {
+        if let Some(client) = self.client.take() {
+            client.detach(debugger);
+            println!("GDB client disconnected");
+        }
+    }
+}
+
+impl Client {
+    fn handle_input(&mut self, debugger: &mut Debugger, gbc: &mut GioBoyColor) -> io::Result<()> {
+        loop {
+            // Acknowledgements and noise between packets
+            let skipped = self
+                .input
+                .iter()
+                .position(|&byte| byte == b'$' || byte == INTERRUPT)
+                .unwrap_or(self.input.len());
+            self.input.drain(..skipped);
+
+            match self.input.first() {
+                Some(&INTERRUPT) => {
+                    self.input.remove(0);
+                    debugger.pause();
+                    if self.running {
+                        self.running = false;
+                        self.send(&format!("S{:02x}", SIGINT))?;
+                    }
+                }
+                Some(_) => {
+                    // $packet-data#checksum
+                    let Some(end) = self.input.iter().position(|&byte| byte == b'#') else {
+                        return Ok(());
+                    };
+                    if self.input.len() < end + 3 {
+                        return Ok(());
+                    }
+
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+                    if !self.no_ack {
+                        if checksum != Some(checksum_of(data)) {
+                            self.stream.write_all(b"-")?;
+                            continue;
+                        }
+                        self.stream.write_all(b"+")?;
+                    }
+
+                    let data = String::from_utf8_lossy(data).into_owned();
+                    if let Some(reply) = self.handle_packet(&data, debugger, gbc) {
+                        self.send(&reply)?;
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // The reply to a packet, None when the reply comes later or the client is gone
+    fn handle_packet(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        gbc: &mut GioBoyColor,
+    ) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => REGISTERS
+                .iter()
+                .enumerate()
+                .map(|(number, _)| read_register(gbc, number).unwrap_or_default())
+                .collect(),
+            "G" => {
+                let mut offset = 0;
+                for (number, (_, size, _)) in REGISTERS.iter().enumerate() {
+                    let Some(value) = args.get(offset..offset + size * 2) else {
+                        return Some("E01".to_string());
+                    };
+                    write_register(gbc, number, value);
+                    offset += size * 2;
+                }
+                "OK".to_string()
+            }
+            "p" => usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|number| read_register(gbc, number))
+                .unwrap_or("E01".to_string()),
+            "P" => match args.split_once('=') {
+                Some((number, value)) => match usize::from_str_radix(number, 16) {
+                    Ok(number) if write_register(gbc, number, value) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            "m" => match parse_range(args) {
+                // The reply must fit in a packet, with two hex digits per byte
+                Some((address, length)) if length <= PACKET_SIZE / 2 => {
+                    read_memory(gbc, address, length)
+                }
+                _ => "E01".to_string(),
+            },
+            "M" => match args.split_once(':') {
+                Some((range, data)) => match (parse_range(range), decode_hex(data)) {
+                    (Some((address, _)), Some(bytes)) => write_memory(gbc, address, &bytes),
+                    _ => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            "c" | "s" | "C" | "S" => {
+                // C and S carry a signal to deliver first, there are none on the Game Boy
+                let address = match command {
+                    "c" | "s" => args,
+                    _ => args.split_once(';').map_or("", |(_, address)| address),
+                };
+                if let Ok(address) = u16::from_str_radix(address, 16) {
+                    gbc.cpu.registers.pc = address;
+                }
+                return self.resume(command.eq_ignore_ascii_case("s"), debugger, gbc);
+            }
+            "v" if packet == "vCont?" => "vCont;c;C;s;S".to_string(),
+            "v" if packet.starts_with("vCont;") => {
+                // There is a single thread, so the first action applies to it
+                let action = packet["vCont;".len()..].chars().next();
+                match action {
+                    Some('c' | 'C') => return self.resume(false, debugger, gbc),
+                    Some('s' | 'S') => return self.resume(true, debugger, gbc),
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.update_point(command == "Z", args, debugger),
+            "D" => {
+                self.detach(debugger);
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                "OK".to_string()
+            }
+            "k" => {
+                self.detach(debugger);
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                return None;
+            }
+            "H" | "T" => "OK".to_string(),
+            "q" | "Q" => query(packet, self),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn resume(
+        &mut self,
+        step: bool,
+        debugger: &mut Debugger,
+        gbc: &mut GioBoyColor,
+    ) -> Option<String> {
+        if step {
+            Some(stop_reply(debugger.step_into(gbc)))
+        } else {
+            debugger.resume();
+            self.running = true;
+            None
+        }
+    }
+
+    // type,addr,kind where kind is the length of watchpoints
+    fn update_point(&mut self, insert: bool, args: &str, debugger: &mut Debugger) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(address), Some(length)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return "E01".to_string();
+        };
+        let (Ok(address), Ok(length)) = (
+            u16::from_str_radix(address, 16),
+            u16::from_str_radix(length, 16),
+        ) else {
+            return "E01".to_string();
+        };
+
+        match kind {
+            "0" | "1" => {
+                let breakpoint = Breakpoint {
+                    bank: None,
+                    address,
+                    condition: None,
+                };
+                if insert {
+                    debugger.add_breakpoint(breakpoint);
+                    self.breakpoints.push(breakpoint);
+                } else {
+                    remove_breakpoint(debugger, &breakpoint);
+                    remove_first(&mut self.breakpoints, &breakpoint);
+                }
+            }
+            "2" | "3" | "4" => {
+                let watchpoint = Watchpoint {
+                    start: address,
+                    end: address.saturating_add(length.max(1) - 1),
+                    read: kind != "2",
+                    write: kind != "3",
+                    execute: false,
+                };
+                if insert {
+                    debugger.add_watchpoint(watchpoint);
+                    self.watchpoints.push(watchpoint);
+                } else {
+                    remove_watchpoint(debugger, &watchpoint);
+                    remove_first(&mut self.watchpoints, &watchpoint);
+                }
+            }
+            // Unsupported point type
+            _ => return String::new(),
+        }
+
+        "OK".to_string()
+    }
+
+    // Removes the client's breakpoints and watchpoints and lets the game run
+    fn detach(&self, debugger: &mut Debugger) {
+        for breakpoint in &self.breakpoints {
+            remove_breakpoint(debugger, breakpoint);
+        }
+        for watchpoint in &self.watchpoints {
+            remove_watchpoint(debugger, watchpoint);
+        }
+        debugger.resume();
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+fn query(packet: &str, client: &mut Client) -> String {
+    let (name, args) = packet.split_once(':').unwrap_or((packet, ""));
+
+    match name {
+        "qSupported" => format!(
+            "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+            PACKET_SIZE
+        ),
+        "QStartNoAckMode" => {
+            client.no_ack = true;
+            "OK".to_string()
+        }
+        "qAttached" => "1".to_string(),
+        "qC" => "QC1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        "qXfer" => match args.split_once(':') {
+            Some(("features", args)) => read_target_xml(args),
+            _ => String::new(),
+        },
+        _ if name.starts_with("qRegisterInfo") => {
+            usize::from_str_radix(&name["qRegisterInfo".len()..], 16)
+                .ok()
+                .and_then(register_info)
+                .unwrap_or("E45".to_string())
+        }
+        _ => String::new(),
+    }
+}
+
+// read:target.xml:offset,length
+fn read_target_xml(args: &str) -> String {
+    let Some(range) = args.strip_prefix("read:target.xml:") else {
+        return "E00".to_string();
+    };
+    let Some((offset, length)) = range.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Ok(offset), Ok(length)) = (
+        usize::from_str_radix(offset, 16),
+        usize::from_str_radix(length, 16),
+    ) else {
+        return "E01".to_string();
+    };
+
+    // m when there is more to read, l for the last chunk
+    let start = offset.min(TARGET_XML.len());
+    let end = offset.saturating_add(length).min(TARGET_XML.len());
+    let marker = if end < TARGET_XML.len() { 'm' } else { 'l' };
+
+    format!("{}{}", marker, &TARGET_XML[start..end])
+}
+
+fn register_info(number: usize) -> Option<String> {
+    let (name, size, generic) = REGISTERS.get(number)?;
+    let offset: usize = REGISTERS[..number].iter().map(|(_, size, _)| size).sum();
+
+    let mut info = format!(
+        "name:{};bitsize:{};offset:{};encoding:uint;format:hex;set:General Purpose Registers;",
+        name,
+        size * 8,
+        offset
+    );
+    if let Some(generic) = generic {
+        info += &format!("generic:{};", generic);
+    }
+
+    Some(info)
+}
+
+fn read_register(gbc: &GioBoyColor, number: usize) -> Option<String> {
+    let registers = &gbc.cpu.registers;
+
+    let hex = match number {
+        0 => format!("{:02x}", registers.a),
+        1 => format!("{:02x}", registers.f),
+        2 => format!("{:02x}", registers.b),
+        3 => format!("{:02x}", registers.c),
+        4 => format!("{:02x}", registers.d),
+        5 => format!("{:02x}", registers.e),
+        6 => format!("{:02x}", registers.h),
+        7 => format!("{:02x}", registers.l),
+        8 => encode_hex(&registers.sp.to_le_bytes()),
+        9 => encode_hex(&registers.pc.to_le_bytes()),
+        _ => return None,
+    };
+
+    Some(hex)
+}
+
+fn write_register(gbc: &mut GioBoyColor, number: usize, hex: &str) -> bool {
+    let Some(bytes) = decode_hex(hex) else {
+        return false;
+    };
+    let registers = &mut gbc.cpu.registers;
+
+    match (number, bytes.as_slice()) {
+        (0, &[value]) => registers.a = value,
+        // The low nibble of F always reads as zero
+        (1, &[value]) => registers.f = value & 0xF0,
+        (2, &[value]) => registers.b = value,
+        (3, &[value]) => registers.c = value,
+        (4, &[value]) => registers.d = value,
+        (5, &[value]) => registers.e = value,
+        (6, &[value]) => registers.h = value,
+        (7, &[value]) => registers.l = value,
+        (8, &[low, high]) => registers.sp = combine!(high, low),
+        (9, &[low, high]) => registers.pc = combine!(high, low),
+        _ => return false,
+    }
+
+    true
+}
+
+// Unmapped addresses read as 0xFF
+fn read_memory(gbc: &GioBoyColor, address: u16, length: usize) -> String {
+    let bytes: Vec<u8> = (0..length)
+        .map(|offset| gbc.peek(address.wrapping_add(offset as u16)))
+        .collect();
+
+    encode_hex(&bytes)
+}
+
+// Writes to unmapped addresses are ignored
+fn write_memory(gbc: &mut GioBoyColor, address: u16, bytes: &[u8]) -> String {
+    for (offset, &byte) in bytes.iter().enumerate() {
+        gbc.poke(address.wrapping_add(offset as u16), byte);
+    }
+
+    "OK".to_string()
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint {
+            access: access @ (Access::Read | Access::Write),
+            address,
+            ..
+        } => {
+            let kind = if access == Access::Read {
+                "rwatch"
+            } else {
+                "watch"
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, address)
+        }
+        _ => format!("T{:02x}", SIGTRAP),
+    }
+}
+
+fn remove_breakpoint(debugger: &mut Debugger, breakpoint: &Breakpoint) {
+    if let Some(index) = debugger
+        .breakpoints()
+        .iter()
+        .position(|other| other == breakpoint)
+    {
+        debugger.remove_breakpoint(index);
+    }
+}
+
+fn remove_watchpoint(debugger: &mut Debugger, watchpoint: &Watchpoint) {
+    if let Some(index) = debugger
+        .watchpoints()
+        .iter()
+        .position(|other| other == watchpoint)
+    {
+        debugger.remove_watchpoint(index);
+    }
+}
+
+fn remove_first<T: PartialEq>(items: &mut Vec<T>, item: &T) {
+    if let Some(index) = items.iter().position(|other| other == item) {
+        items.remove(index);
+    }
+}
+
+// addr,length
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // A server with a client connected to it over loopback
+    struct Session {
+        server: GdbServer,
+        stream: TcpStream,
+        debugger: Debugger,
+        gbc: GioBoyColor,
+    }
+
+    impl Session {
+        fn connect() -> Session {
+            let mut server = GdbServer::bind(0).unwrap();
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, server.port())).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut debugger = Debugger::new();
+            let mut gbc = GioBoyColor::new();
+
+            while !server.is_connected() {
+                server.poll(&mut debugger, &mut gbc);
+            }
+
+            Session {
+                server,
+                stream,
+                debugger,
+                gbc,
+            }
+        }
+
+        // Polls the server until it answers
+        fn send_raw(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).unwrap();
+
+            let mut byte = [0];
+            for _ in 0..5000 {
+                self.server.poll(&mut self.debugger, &mut self.gbc);
+
+                self.stream.set_nonblocking(true).unwrap();
+                let answered = self.stream.peek(&mut byte).is_ok();
+                self.stream.set_nonblocking(false).unwrap();
+                if answered {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            panic!("No answer from the server");
+        }
+
+        // Reads until the end of a packet, including the acknowledgement before it
+        fn receive(&mut self) -> String {
+            let mut received = Vec::new();
+            let mut byte = [0];
+            while received.len() < 3 || received[received.len() - 3] != b'#' {
+                self.stream.read_exact(&mut byte).unwrap();
+                received.push(byte[0]);
+            }
+            String::from_utf8(received).unwrap()
+        }
+
+        // Sends a packet and returns the data of the reply
+        fn request(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.send_raw(packet.as_bytes());
+
+            let reply = self.receive();
+            let (ack, reply) = reply.split_at(1);
+            assert_eq!(ack, "+");
+            let (data, checksum) = reply[1..].split_once('#').unwrap();
+            assert_eq!(
+                u8::from_str_radix(checksum, 16).unwrap(),
+                checksum_of(data.as_bytes())
+            );
+            data.to_string()
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(checksum_of(b""), 0);
+        assert_eq!(checksum_of(b"OK"), 0x9A);
+        // Wraps around
+        assert_eq!(checksum_of(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(encode_hex(&[0x00, 0x0F, 0xA5]), "000fa5");
+        assert_eq!(decode_hex("000fa5"), Some(vec![0x00, 0x0F, 0xA5]));
+        assert_eq!(decode_hex("000FA5"), Some(vec![0x00, 0x0F, 0xA5]));
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("c000,10"), Some((0xC000, 0x10)));
+        assert_eq!(parse_range("10000,1"), None);
+        assert_eq!(parse_range("c000"), None);
+        assert_eq!(parse_range("c000,"), None);
+    }
+
+    #[test]
+    fn target_xml_is_read_in_chunks() {
+        let first = read_target_xml("read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+        let last = read_target_xml(&format!("read:target.xml:10,{:x}", TARGET_XML.len()));
+        assert_eq!(last, format!("l{}", &TARGET_XML[0x10..]));
+        assert_eq!(read_target_xml("read:other.xml:0,10"), "E00");
+    }
+
+    #[test]
+    fn registers() {
+        let mut session = Session::connect();
+        session.gbc.cpu.registers.a = 0x12;
+        session.gbc.cpu.registers.sp = 0xFFFE;
+        session.gbc.cpu.registers.pc = 0x0150;
+
+        let registers = session.request("g");
+        assert_eq!(registers.len(), 2 * (8 + 2 + 2));
+        assert!(registers.starts_with("12"));
+        assert!(registers.ends_with("feff5001"));
+
+        assert_eq!(session.request("P9=0002"), "OK");
+        assert_eq!(session.gbc.cpu.registers.pc, 0x0200);
+        assert_eq!(session.request("p9"), "0002");
+        assert_eq!(session.request("pa"), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut session = Session::connect();
+
+        assert_eq!(session.request("Mc000,3:0102ff"), "OK");
+        assert_eq!(session.request("mc000,4"), "0102ff00");
+        // Reads wrap around the address space, no ROM is loaded
+        assert_eq!(session.request("mffff,2"), "00ff");
+        // The reply has to fit in a packet
+        assert_eq!(
+            session.request(&format!("m0,{:x}", PACKET_SIZE / 2)).len(),
+            PACKET_SIZE
+        );
+        assert_eq!(
+            session.request(&format!("m0,{:x}", PACKET_SIZE / 2 + 1)),
+            "E01"
+        );
+        assert_eq!(session.request("mc000"), "E01");
+    }
+
+    #[test]
+    fn bad_checksums_are_rejected() {
+        let mut session = Session::connect();
+
+        session.send_raw(b"$g#00");
+        let mut nack = [0];
+        session.stream.read_exact(&mut nack).unwrap();
+        assert_eq!(&nack, b"-");
+
+        // Unsupported packets get an empty reply
+        assert_eq!(session.request("X"), "");
+    }
+
+    #[test]
+    fn no_ack_mode() {
+        let mut session = Session::connect();
+        assert_eq!(session.request("QStartNoAckMode"), "OK");
+
+        let data = "qAttached";
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        session.send_raw(packet.as_bytes());
+        assert_eq!(session.receive(), "$1#31");
+    }
+}
@@ -1,24 +1,799 @@
-use super::memory_map::*;
+use super::{
+    compat_palettes::{Palette, PaletteSet},
+    interrupts::Interrupt,
+    memory_map::*,
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
 
-const VRAM_SIZE: usize = (VRAM_END - VRAM_START + 1) as usize;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
+pub const VRAM_BANK_SIZE: usize = (VRAM_END - VRAM_START + 1) as usize;
+pub const VRAM_BANKS: usize = 2;
+// Tile data fills 0x8000-0x97FF of each bank, 16 bytes per tile
+pub const TILES_PER_BANK: usize = 384;
+// A tile map is 32x32 tiles
+pub const TILE_MAP_SIZE: usize = 256;
+pub const BG_TILE_MAPS: [u16; 2] = [0x9800, 0x9C00];
+pub const SPRITE_COUNT: usize = 40;
+pub const PALETTE_COUNT: usize = 8;
+const OAM_SIZE: usize = (OAM_END - OAM_START + 1) as usize;
+// 8 palettes of 4 colors, 2 bytes per color
+const PALETTE_RAM_SIZE: usize = 64;
+
+// Dots (T-cycles) per scanline and per frame
+pub const DOTS_PER_LINE: u32 = 456;
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const VBLANK_START_LINE: u8 = 144;
+const LAST_LINE: u8 = 153;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// An OAM entry, with the position as stored (offset by 16 and 8 pixels)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// LCD Control (LCDC)
+///
+/// Bit    Explanation                                                    \
+/// 7      LCD enable                                                     \
+/// 6      Window tile map (0 = 0x9800, 1 = 0x9C00)                       \
+/// 5      Window enable                                                  \
+/// 4      BG & window tile data (0 = 0x8800 signed, 1 = 0x8000 unsigned) \
+/// 3      BG tile map (0 = 0x9800, 1 = 0x9C00)                           \
+/// 2      OBJ size (0 = 8x8, 1 = 8x16)                                   \
+/// 1      OBJ enable                                                     \
+/// 0      BG & window enable (DMG), BG & window priority (CGB)           \
+///
+/// LCD Status (STAT)
+///
+/// Bit    Explanation                                  \
+/// 6      LYC = LY interrupt source                    \
+/// 5      Mode 2 interrupt source                      \
+/// 4      Mode 1 interrupt source                      \
+/// 3      Mode 0 interrupt source                      \
+/// 2      LYC = LY (read-only)                         \
+/// 1-0    Current mode (read-only)                     \
+///
+/// Every scanline takes 456 dots: 80 dots scanning OAM (mode 2), 172 dots drawing (mode 3) and
+/// the rest in HBlank (mode 0). Lines 144 to 153 are spent in VBlank (mode 1).
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Rendering.html))
 pub struct Gpu {
-    vram: [u8; VRAM_SIZE],
+    vram: [[u8; VRAM_BANK_SIZE]; VRAM_BANKS],
+    vram_bank: usize,
+    oam: [u8; OAM_SIZE],
+    bg_palette_ram: [u8; PALETTE_RAM_SIZE],
+    obj_palette_ram: [u8; PALETTE_RAM_SIZE],
+    bg_palette_index: u8,
+    obj_palette_index: u8,
+    // Set for CGB games, monochrome games run in compatibility mode
+    pub cgb_mode: bool,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    dots: u32,
+    window_line: u8,
+    stat_line: bool,
+    frame_buffer: Vec<u32>,
+    // Shades (0-3) the DMG palettes gave to every pixel, what the Super Game Boy sees
+    shade_buffer: Vec<u8>,
+    // Per pixel of the current line: BG color index and whether the BG has priority
+    line_bg: [(u8, bool); SCREEN_WIDTH],
 }
 
 impl Gpu {
     pub fn new() -> Gpu {
         Gpu {
-            vram: [0; VRAM_SIZE],
+            vram: [[0; VRAM_BANK_SIZE]; VRAM_BANKS],
+            vram_bank: 0,
+            oam: [0; OAM_SIZE],
+            bg_palette_ram: [0; PALETTE_RAM_SIZE],
+            obj_palette_ram: [0; PALETTE_RAM_SIZE],
+            bg_palette_index: 0,
+            obj_palette_index: 0,
+            cgb_mode: false,
+            // Values left by the boot ROM
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::OamScan,
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+            frame_buffer: vec![0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shade_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            line_bg: [(0, false); SCREEN_WIDTH],
         }
     }
 
-    pub fn read(&self, address: u16) -> u8 {
-        // TODO: Check for mode, and handle all that (maybe add an unsafe_read? not sure)
-        self.vram[address as usize]
+    // Writes the palettes the CGB boot ROM sets up for monochrome games
+    pub fn load_compat_palettes(&mut self, palettes: &PaletteSet) {
+        write_palette(&mut self.bg_palette_ram, 0, &palettes.bg);
+        write_palette(&mut self.obj_palette_ram, 0, &palettes.obj0);
+        write_palette(&mut self.obj_palette_ram, 1, &palettes.obj1);
+    }
+
+    // Current frame as 0RGB pixels, row by row
+    pub fn frame_buffer(&self) -> &[u32] {
+        &self.frame_buffer
+    }
+
+    // Current frame as shades of the DMG palettes, row by row, only meaningful for DMG games
+    pub fn shade_buffer(&self) -> &[u8] {
+        &self.shade_buffer
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[self.vram_bank][(address - VRAM_START) as usize]
+    }
+
+    pub fn write_vram(&mut self, address: u16, data: u8) {
+        self.vram[self.vram_bank][(address - VRAM_START) as usize] = data;
+    }
+
+    // Reads a byte of either VRAM bank, regardless of VBK, for debug tools
+    pub fn peek_vram(&self, bank: usize, offset: usize) -> u8 {
+        self.vram[bank][offset]
+    }
+
+    pub fn poke_vram(&mut self, bank: usize, offset: usize, data: u8) {
+        self.vram[bank][offset] = data;
     }
 
-    pub fn write(&self, address: u16, data: u8) {
-        todo!()
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[(address - OAM_START) as usize]
     }
-}
\ No newline at end of file
+
+    pub fn write_oam(&mut self, address: u16, data: u8) {
+        self.oam[(address - OAM_START) as usize] = data;
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => 0x80 | self.stat | ((self.ly == self.lyc) as u8) << 2 | self.mode as u8,
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            VBK => 0xFE | self.vram_bank as u8,
+            BCPS => 0x40 | self.bg_palette_index,
+            BCPD => self.bg_palette_ram[(self.bg_palette_index & 0x3F) as usize],
+            OCPS => 0x40 | self.obj_palette_index,
+            OCPD => self.obj_palette_ram[(self.obj_palette_index & 0x3F) as usize],
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            LCDC => self.write_lcdc(data),
+            STAT => self.stat = data & 0x78,
+            SCY => self.scy = data,
+            SCX => self.scx = data,
+            LY => (), // Read-only
+            LYC => self.lyc = data,
+            BGP => self.bgp = data,
+            OBP0 => self.obp0 = data,
+            OBP1 => self.obp1 = data,
+            WY => self.wy = data,
+            WX => self.wx = data,
+            VBK => {
+                if self.cgb_mode {
+                    self.vram_bank = (data & 0x01) as usize;
+                }
+            }
+            BCPS => self.bg_palette_index = data & 0xBF,
+            BCPD => {
+                self.bg_palette_ram[(self.bg_palette_index & 0x3F) as usize] = data;
+                self.bg_palette_index = auto_increment(self.bg_palette_index);
+            }
+            OCPS => self.obj_palette_index = data & 0xBF,
+            OCPD => {
+                self.obj_palette_ram[(self.obj_palette_index & 0x3F) as usize] = data;
+                self.obj_palette_index = auto_increment(self.obj_palette_index);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_lcdc(&mut self, data: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = data;
+
+        if was_enabled && !self.lcd_enabled() {
+            // Turning the LCD off resets the PPU, the screen goes blank
+            self.ly = 0;
+            self.dots = 0;
+            self.mode = Mode::HBlank;
+            self.window_line = 0;
+            self.frame_buffer
+                .iter_mut()
+                .for_each(|pixel| *pixel = 0xFFFFFF);
+            self.shade_buffer.fill(0);
+        } else if !was_enabled && self.lcd_enabled() {
+            self.mode = Mode::OamScan;
+        }
+    }
+
+    // Advances the PPU by the given amount of dots, returns the requested interrupts (IF bits)
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+
+        let mut interrupts = 0;
+
+        for _ in 0..cycles {
+            self.dots += 1;
+
+            match self.mode {
+                Mode::OamScan if self.dots == OAM_SCAN_DOTS => self.mode = Mode::Drawing,
+                Mode::Drawing if self.dots == OAM_SCAN_DOTS + DRAWING_DOTS => {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                }
+                _ => (),
+            }
+
+            if self.dots == DOTS_PER_LINE {
+                self.dots = 0;
+                self.ly = if self.ly == LAST_LINE { 0 } else { self.ly + 1 };
+
+                if self.ly == VBLANK_START_LINE {
+                    self.mode = Mode::VBlank;
+                    interrupts |= Interrupt::VBlank.bit();
+                } else if self.ly < VBLANK_START_LINE {
+                    if self.ly == 0 {
+                        self.window_line = 0;
+                    }
+                    self.mode = Mode::OamScan;
+                }
+            }
+
+            if self.update_stat_line() {
+                interrupts |= Interrupt::Lcd.bit();
+            }
+        }
+
+        interrupts
+    }
+
+    // The STAT interrupt is requested on the rising edge of the OR of all enabled sources
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & 0x40 != 0 && self.ly == self.lyc)
+            || (self.stat & 0x20 != 0 && self.mode == Mode::OamScan)
+            || (self.stat & 0x10 != 0 && self.mode == Mode::VBlank)
+            || (self.stat & 0x08 != 0 && self.mode == Mode::HBlank);
+
+        let rising_edge = line && !self.stat_line;
+        self.stat_line = line;
+        rising_edge
+    }
+
+    fn render_line(&mut self) {
+        self.render_background();
+        self.render_window();
+
+        if self.lcdc & 0x02 != 0 {
+            self.render_sprites();
+        }
+    }
+
+    fn render_background(&mut self) {
+        let y = self.ly.wrapping_add(self.scy);
+        let map = if self.lcdc & 0x08 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+
+        // On DMG games, clearing LCDC bit 0 blanks the background
+        let enabled = self.cgb_mode || self.lcdc & 0x01 != 0;
+
+        for x in 0..SCREEN_WIDTH {
+            if !enabled {
+                self.line_bg[x] = (0, false);
+                let color = self.bg_color(0, 0);
+                self.set_pixel(x, color, self.bgp & 0x03);
+                continue;
+            }
+
+            let map_x = (x as u8).wrapping_add(self.scx);
+            self.draw_tile_pixel(map, map_x, y, x);
+        }
+    }
+
+    fn render_window(&mut self) {
+        // WX is offset by 7 pixels
+        let window_x = self.wx as i16 - 7;
+
+        if self.lcdc & 0x20 == 0 || self.ly < self.wy || window_x >= SCREEN_WIDTH as i16 {
+            return;
+        }
+
+        if !self.cgb_mode && self.lcdc & 0x01 == 0 {
+            return;
+        }
+
+        let map = if self.lcdc & 0x40 != 0 {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let y = self.window_line;
+
+        for x in window_x.max(0) as usize..SCREEN_WIDTH {
+            let map_x = (x as i16 - window_x) as u8;
+            self.draw_tile_pixel(map, map_x, y, x);
+        }
+
+        self.window_line += 1;
+    }
+
+    // Draws one BG or window pixel at screen x, from the given tile map coordinates
+    fn draw_tile_pixel(&mut self, map: u16, map_x: u8, map_y: u8, x: usize) {
+        let map_offset =
+            (map - VRAM_START) as usize + (map_y as usize / 8) * 32 + (map_x as usize / 8);
+        let tile_index = self.vram[0][map_offset];
+        // In CGB mode, bank 1 of the tile map holds the tile attributes
+        let attributes = if self.cgb_mode {
+            self.vram[1][map_offset]
+        } else {
+            0
+        };
+
+        let mut row = map_y % 8;
+        let mut column = map_x % 8;
+
+        if attributes & 0x40 != 0 {
+            row = 7 - row;
+        }
+        if attributes & 0x20 != 0 {
+            column = 7 - column;
+        }
+
+        let bank = ((attributes >> 3) & 0x01) as usize;
+        let color_index =
+            self.tile_color_index(bank, self.tile_data_offset(tile_index), row, column);
+        let palette = attributes & 0x07;
+
+        self.line_bg[x] = (color_index, attributes & 0x80 != 0);
+        let color = self.bg_color(palette, color_index);
+        let shade = (self.bgp >> (color_index * 2)) & 0x03;
+        self.set_pixel(x, color, shade);
+    }
+
+    fn render_sprites(&mut self) {
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        let ly = self.ly as i16;
+
+        // OAM scan: the first 10 sprites overlapping the line are selected
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|&index| {
+                let y = self.oam[index * 4] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+
+        // Lower priority sprites are drawn first, so they get covered by higher priority ones.
+        // In CGB mode only OAM order matters, otherwise sprites with a lower X win.
+        if self.cgb_mode {
+            sprites.reverse();
+        } else {
+            sprites.sort_by(|&a, &b| {
+                self.oam[b * 4 + 1]
+                    .cmp(&self.oam[a * 4 + 1])
+                    .then(b.cmp(&a))
+            });
+        }
+
+        for index in sprites {
+            let y = self.oam[index * 4] as i16 - 16;
+            let x = self.oam[index * 4 + 1] as i16 - 8;
+            let mut tile_index = self.oam[index * 4 + 2];
+            let attributes = self.oam[index * 4 + 3];
+
+            let mut row = (ly - y) as u8;
+            if attributes & 0x40 != 0 {
+                row = height as u8 - 1 - row;
+            }
+
+            // 8x16 sprites ignore bit 0 of the tile index
+            if height == 16 {
+                tile_index &= 0xFE;
+            }
+
+            let bank = if self.cgb_mode {
+                ((attributes >> 3) & 0x01) as usize
+            } else {
+                0
+            };
+            let tile_offset = tile_index as usize * 16;
+
+            for column in 0..8u8 {
+                let screen_x = x + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&screen_x) {
+                    continue;
+                }
+                let screen_x = screen_x as usize;
+
+                let tile_column = if attributes & 0x20 != 0 {
+                    7 - column
+                } else {
+                    column
+                };
+                let color_index = self.tile_color_index(bank, tile_offset, row, tile_column);
+
+                // Color 0 is transparent for sprites
+                if color_index == 0 || self.bg_has_priority(screen_x, attributes) {
+                    continue;
+                }
+
+                let color = self.obj_color(attributes, color_index);
+                let obp = if attributes & 0x10 != 0 {
+                    self.obp1
+                } else {
+                    self.obp0
+                };
+                let shade = (obp >> (color_index * 2)) & 0x03;
+                self.set_pixel(screen_x, color, shade);
+            }
+        }
+    }
+
+    fn bg_has_priority(&self, x: usize, attributes: u8) -> bool {
+        let (bg_color_index, bg_attribute_priority) = self.line_bg[x];
+
+        if bg_color_index == 0 {
+            return false;
+        }
+
+        // In CGB mode, clearing LCDC bit 0 gives sprites priority over everything
+        if self.cgb_mode && self.lcdc & 0x01 == 0 {
+            return false;
+        }
+
+        attributes & 0x80 != 0 || bg_attribute_priority
+    }
+
+    // Offset of a BG/window tile in VRAM, depending on the addressing mode in LCDC bit 4
+    fn tile_data_offset(&self, tile_index: u8) -> usize {
+        if self.lcdc & 0x10 != 0 {
+            tile_index as usize * 16
+        } else {
+            (0x1000 + (tile_index as i8 as i32) * 16) as usize
+        }
+    }
+
+    // Tiles are 16 bytes, 2 bytes per row: the first holds the low bits, the second the high bits
+    fn tile_color_index(&self, bank: usize, tile_offset: usize, row: u8, column: u8) -> u8 {
+        let low = self.vram[bank][tile_offset + row as usize * 2];
+        let high = self.vram[bank][tile_offset + row as usize * 2 + 1];
+        let bit = 7 - column;
+
+        ((high >> bit) & 1) << 1 | ((low >> bit) & 1)
+    }
+
+    fn bg_color(&self, palette: u8, color_index: u8) -> u32 {
+        if self.cgb_mode {
+            palette_color(&self.bg_palette_ram, palette, color_index)
+        } else {
+            // BGP maps the color to a shade of the compatibility palette
+            let shade = (self.bgp >> (color_index * 2)) & 0x03;
+            palette_color(&self.bg_palette_ram, 0, shade)
+        }
+    }
+
+    fn obj_color(&self, attributes: u8, color_index: u8) -> u32 {
+        if self.cgb_mode {
+            palette_color(&self.obj_palette_ram, attributes & 0x07, color_index)
+        } else {
+            let (obp, palette) = if attributes & 0x10 != 0 {
+                (self.obp1, 1)
+            } else {
+                (self.obp0, 0)
+            };
+            let shade = (obp >> (color_index * 2)) & 0x03;
+            palette_color(&self.obj_palette_ram, palette, shade)
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, color: u32, shade: u8) {
+        let index = self.ly as usize * SCREEN_WIDTH + x;
+        self.frame_buffer[index] = color;
+        self.shade_buffer[index] = shade;
+    }
+
+    // SCX and SCY, the top left corner of the screen in the BG tile map
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    // Tile map used by the background, from LCDC bit 3
+    pub fn bg_tile_map(&self) -> u16 {
+        BG_TILE_MAPS[((self.lcdc >> 3) & 0x01) as usize]
+    }
+
+    pub fn sprite(&self, index: usize) -> Sprite {
+        let entry = &self.oam[index * 4..index * 4 + 4];
+
+        Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+        }
+    }
+
+    // 8 or 16, from LCDC bit 2
+    pub fn sprite_height(&self) -> usize {
+        if self.lcdc & 0x04 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // Color indices of a row of one of the 384 tiles of a bank, for debug views
+    pub fn tile_row(&self, bank: usize, tile: usize, row: u8) -> [u8; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|column| self.tile_color_index(bank, tile * 16, row, column))
+    }
+
+    // The four colors of a BG palette, in DMG games palette 0 shaded by BGP
+    pub fn bg_palette(&self, palette: u8) -> [u32; 4] {
+        [0, 1, 2, 3].map(|color_index| self.bg_color(palette, color_index))
+    }
+
+    // The four colors of an OBJ palette, in DMG games palettes 0 and 1 shaded by OBP0 and OBP1
+    pub fn obj_palette(&self, palette: u8) -> [u32; 4] {
+        let attributes = if self.cgb_mode {
+            palette & 0x07
+        } else {
+            (palette & 0x01) << 4
+        };
+
+        [0, 1, 2, 3].map(|color_index| self.obj_color(attributes, color_index))
+    }
+
+    // Draws a whole 256x256 tile map the way the background would show it, for debug views
+    pub fn render_tile_map(&self, map: u16) -> Vec<u32> {
+        let mut pixels = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            let (x, y) = (index % TILE_MAP_SIZE, index / TILE_MAP_SIZE);
+            let map_offset = (map - VRAM_START) as usize + (y / 8) * 32 + (x / 8);
+            let tile_index = self.vram[0][map_offset];
+            let attributes = if self.cgb_mode {
+                self.vram[1][map_offset]
+            } else {
+                0
+            };
+
+            let row = if attributes & 0x40 != 0 {
+                7 - y % 8
+            } else {
+                y % 8
+            };
+            let column = if attributes & 0x20 != 0 {
+                7 - x % 8
+            } else {
+                x % 8
+            };
+            let bank = ((attributes >> 3) & 0x01) as usize;
+            let color_index = self.tile_color_index(
+                bank,
+                self.tile_data_offset(tile_index),
+                row as u8,
+                column as u8,
+            );
+
+            *pixel = self.bg_color(attributes & 0x07, color_index);
+        }
+
+        pixels
+    }
+
+    // The frame buffer isn't stored, it is redrawn during the next frame
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for bank in &self.vram {
+            writer.write_bytes(bank);
+        }
+        writer.write_u8(self.vram_bank as u8);
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.bg_palette_ram);
+        writer.write_bytes(&self.obj_palette_ram);
+        writer.write_u8(self.bg_palette_index);
+        writer.write_u8(self.obj_palette_index);
+        writer.write_bool(self.cgb_mode);
+        writer.write_bytes(&[
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ]);
+        writer.write_u8(self.mode as u8);
+        writer.write_u32(self.dots);
+        writer.write_u8(self.window_line);
+        writer.write_bool(self.stat_line);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        for bank in self.vram.iter_mut() {
+            reader.read_bytes(bank)?;
+        }
+        self.vram_bank = reader.read_u8()? as usize;
+        if self.vram_bank >= VRAM_BANKS {
+            return Err(SaveStateError::InvalidData);
+        }
+        reader.read_bytes(&mut self.oam)?;
+        reader.read_bytes(&mut self.bg_palette_ram)?;
+        reader.read_bytes(&mut self.obj_palette_ram)?;
+        self.bg_palette_index = reader.read_u8()?;
+        self.obj_palette_index = reader.read_u8()?;
+        // Bit 7 is auto increment and bits 5-0 the index, as masked by BCPS/OCPS writes
+        if (self.bg_palette_index | self.obj_palette_index) & !0xBF != 0 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.cgb_mode = reader.read_bool()?;
+
+        let mut registers = [0; 11];
+        reader.read_bytes(&mut registers)?;
+        [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ] = registers;
+
+        self.mode = match reader.read_u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(SaveStateError::InvalidData),
+        };
+        self.dots = reader.read_u32()?;
+        // Lines 144 to 153 are always VBlank, and the other lines never are
+        if self.dots >= DOTS_PER_LINE
+            || self.ly > LAST_LINE
+            || (self.ly >= VBLANK_START_LINE) != (self.mode == Mode::VBlank)
+        {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.window_line = reader.read_u8()?;
+        if self.window_line as usize > SCREEN_HEIGHT {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.stat_line = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Gpu {
+    fn default() -> Gpu {
+        Gpu::new()
+    }
+}
+
+// Bit 7 of BCPS/OCPS enables incrementing the index after each write to BCPD/OCPD
+fn auto_increment(index: u8) -> u8 {
+    if index & 0x80 != 0 {
+        0x80 | ((index + 1) & 0x3F)
+    } else {
+        index
+    }
+}
+
+// Converts a color from palette RAM (RGB555) to 0RGB
+fn palette_color(palette_ram: &[u8; PALETTE_RAM_SIZE], palette: u8, color_index: u8) -> u32 {
+    let offset = palette as usize * 8 + color_index as usize * 2;
+    rgb555(combine!(palette_ram[offset + 1], palette_ram[offset]))
+}
+
+// Converts an RGB555 color, as used by CGB palettes and the Super Game Boy, to 0RGB
+pub fn rgb555(color: u16) -> u32 {
+    let red = (color & 0x1F) as u32;
+    let green = ((color >> 5) & 0x1F) as u32;
+    let blue = ((color >> 10) & 0x1F) as u32;
+
+    // Expand 5-bit channels to 8 bits
+    let expand = |channel: u32| (channel << 3) | (channel >> 2);
+
+    expand(red) << 16 | expand(green) << 8 | expand(blue)
+}
+
+fn write_palette(palette_ram: &mut [u8; PALETTE_RAM_SIZE], index: usize, palette: &Palette) {
+    for (color_index, color) in palette.iter().enumerate() {
+        let offset = index * 8 + color_index * 2;
+        palette_ram[offset] = low!(color);
+        palette_ram[offset + 1] = high!(color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(gpu: &Gpu) -> Result<(), SaveStateError> {
+        let mut writer = StateWriter::new();
+        gpu.save_state(&mut writer);
+        Gpu::new().load_state(&mut StateReader::new(&writer.into_bytes()))
+    }
+
+    #[test]
+    fn palette_indices_auto_increment() {
+        let mut gpu = Gpu::new();
+
+        gpu.write_register(BCPS, 0xBF);
+        gpu.write_register(BCPD, 0x12);
+        assert_eq!(gpu.read_register(BCPS), 0xC0);
+        assert_eq!(gpu.bg_palette_ram[0x3F], 0x12);
+
+        // Without bit 7 the index stays
+        gpu.write_register(OCPS, 0x05);
+        gpu.write_register(OCPD, 0x34);
+        assert_eq!(gpu.read_register(OCPS), 0x45);
+    }
+
+    #[test]
+    fn rejects_invalid_states() {
+        let mut gpu = Gpu::new();
+        gpu.bg_palette_index = 0xBF;
+        gpu.window_line = SCREEN_HEIGHT as u8;
+        assert!(reload(&gpu).is_ok());
+
+        gpu.bg_palette_index = 0xFF;
+        assert!(reload(&gpu).is_err());
+        gpu.bg_palette_index = 0;
+        gpu.obj_palette_index = 0x40;
+        assert!(reload(&gpu).is_err());
+        gpu.obj_palette_index = 0;
+
+        gpu.window_line = SCREEN_HEIGHT as u8 + 1;
+        assert!(reload(&gpu).is_err());
+    }
+}
@@ -1,2 +1,3 @@
 #[macro_use]
-pub mod bitwise;
\ No newline at end of file
+pub mod bitwise;
+pub mod checksum;
@@ -0,0 +1,300 @@
+use std::{cell::RefCell, collections::VecDeque, fs, io, path::Path, rc::Rc};
+
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+
+const READ_ENABLE: u8 = 0b1100_0000;
+const RECEIVING: u8 = 0b0000_0010;
+const LED_ON: u8 = 0b0000_0001;
+const RP_UNUSED_BITS: u8 = 0b0011_1100;
+
+// Whatever faces the infrared port. Devices keep time by adding up the T-cycles they are
+// ticked with, the LED changes between two ticks
+pub trait InfraredDevice {
+    fn set_led(&mut self, on: bool);
+
+    fn tick(&mut self, _cycles: u32) {}
+
+    // Whether light reaches the receiver of the Game Boy
+    fn light(&self) -> bool;
+}
+
+// Nothing in front of the port
+pub struct Darkness;
+
+impl InfraredDevice for Darkness {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn light(&self) -> bool {
+        false
+    }
+}
+
+/// Infrared communications port (CGB only)
+///
+/// Register    Explanation                                               \
+/// RP          Bits 7-6: data read enable (3 = enable), bit 1: read      \
+///             data (0 = receiving light), bit 0: LED (1 = on)           \
+///
+/// Bit 1 reads 1 while reading is disabled. Games send data by switching the LED on and off
+/// at precise times and timing what they receive, so the device sees every change at the
+/// T-cycle it happens.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/CGB_Registers.html#ff56--rp-cgb-mode-only-infrared-communications-port))
+pub struct Infrared {
+    control: u8,
+    device: Box<dyn InfraredDevice>,
+    pub cgb_mode: bool,
+}
+
+impl Infrared {
+    pub fn new() -> Infrared {
+        Infrared {
+            control: 0,
+            device: Box::new(Darkness),
+            cgb_mode: false,
+        }
+    }
+
+    // Puts a device in front of the port, returning the previous one
+    pub fn connect(&mut self, device: Box<dyn InfraredDevice>) -> Box<dyn InfraredDevice> {
+        let mut previous = std::mem::replace(&mut self.device, device);
+        previous.set_led(false);
+        self.device.set_led(self.control & LED_ON != 0);
+        previous
+    }
+
+    pub fn disconnect(&mut self) -> Box<dyn InfraredDevice> {
+        self.connect(Box::new(Darkness))
+    }
+
+    pub fn read(&self) -> u8 {
+        // Not present on DMG games
+        if !self.cgb_mode {
+            return 0xFF;
+        }
+
+        let receiving = self.control & READ_ENABLE == READ_ENABLE && self.device.light();
+        let data = if receiving { 0 } else { RECEIVING };
+
+        RP_UNUSED_BITS | self.control | data
+    }
+
+    pub fn write(&mut self, data: u8) {
+        if !self.cgb_mode {
+            return;
+        }
+
+        let led_was_on = self.control & LED_ON != 0;
+        self.control = data & (READ_ENABLE | LED_ON);
+
+        let led_on = self.control & LED_ON != 0;
+        if led_on != led_was_on {
+            self.device.set_led(led_on);
+        }
+    }
+
+    // Whether the game is reading or sending, and needs the other side's clock kept close
+    pub fn is_active(&self) -> bool {
+        self.control & READ_ENABLE == READ_ENABLE || self.control & LED_ON != 0
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.device.tick(cycles);
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.control);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let led_was_on = self.control & LED_ON != 0;
+        self.control = reader.read_u8()? & (READ_ENABLE | LED_ON);
+
+        let led_on = self.control & LED_ON != 0;
+        if led_on != led_was_on {
+            self.device.set_led(led_on);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Infrared {
+    fn default() -> Infrared {
+        Infrared::new()
+    }
+}
+
+// Changes of one side's LED, with the T-cycle they happened at, until the other side's clock
+// gets there
+type Beam = Rc<RefCell<VecDeque<(u64, bool)>>>;
+
+// One of two instances in this process facing each other, run side by side with
+// link::Lockstep so their clocks stay close. A side whose clock is ahead of the other's sees
+// changes late, so Lockstep takes turns of about one instruction while either port is in use
+pub struct InfraredLink {
+    // T-cycles since the pair was made
+    cycles: u64,
+    outgoing: Beam,
+    incoming: Beam,
+    light: bool,
+}
+
+impl InfraredLink {
+    fn new(outgoing: Beam, incoming: Beam) -> InfraredLink {
+        InfraredLink {
+            cycles: 0,
+            outgoing,
+            incoming,
+            light: false,
+        }
+    }
+
+    // Both sides, for two instances in this process
+    pub fn pair() -> (InfraredLink, InfraredLink) {
+        let first: Beam = Rc::new(RefCell::new(VecDeque::new()));
+        let second: Beam = Rc::new(RefCell::new(VecDeque::new()));
+
+        (
+            InfraredLink::new(first.clone(), second.clone()),
+            InfraredLink::new(second, first),
+        )
+    }
+}
+
+impl InfraredDevice for InfraredLink {
+    fn set_led(&mut self, on: bool) {
+        self.outgoing.borrow_mut().push_back((self.cycles, on));
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+
+        // Changes from ahead of this side's clock stay in the beam until it gets there
+        let mut incoming = self.incoming.borrow_mut();
+        while let Some(&(time, on)) = incoming.front() {
+            if time > self.cycles {
+                break;
+            }
+            self.light = on;
+            incoming.pop_front();
+        }
+    }
+
+    fn light(&self) -> bool {
+        self.light
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScriptStep {
+    // Switches the LED of the peer
+    Led(bool),
+    // Waits for that many T-cycles
+    Wait(u32),
+    // Waits until the LED of the Game Boy is on, or off
+    WaitForLed(bool),
+}
+
+// A peer following a script, for exchanges that don't need a second game, e.g. answering a
+// handshake. Each line of a script file is a step, anything after a ; is ignored:
+//
+// Line           Explanation                                            \
+// on, off        Switch the peer's LED                                  \
+// wait <n>       Wait for n T-cycles (4194304 per second)               \
+// wait on        Wait until the Game Boy's LED is on                    \
+// wait off       Wait until the Game Boy's LED is off                   \
+//
+// Steps with no wait between them happen at the same time, and the LED stays as the last step
+// left it once the script is over
+pub struct ScriptedPeer {
+    steps: VecDeque<ScriptStep>,
+    // T-cycles since the current wait started
+    waited: u32,
+    led: bool,
+    game_led: bool,
+}
+
+impl ScriptedPeer {
+    pub fn new(steps: Vec<ScriptStep>) -> ScriptedPeer {
+        let mut peer = ScriptedPeer {
+            steps: steps.into(),
+            waited: 0,
+            led: false,
+            game_led: false,
+        };
+        peer.run(0);
+        peer
+    }
+
+    pub fn parse(text: &str) -> Result<ScriptedPeer, String> {
+        let mut steps = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let step =
+                match fields.as_slice() {
+                    [] => continue,
+                    ["on"] => ScriptStep::Led(true),
+                    ["off"] => ScriptStep::Led(false),
+                    ["wait", "on"] => ScriptStep::WaitForLed(true),
+                    ["wait", "off"] => ScriptStep::WaitForLed(false),
+                    ["wait", cycles] => ScriptStep::Wait(cycles.parse().map_err(|_| {
+                        format!("Line {}: invalid cycle count: {}", index + 1, cycles)
+                    })?),
+                    _ => return Err(format!("Line {}: unknown step: {}", index + 1, line.trim())),
+                };
+            steps.push(step);
+        }
+
+        Ok(ScriptedPeer::new(steps))
+    }
+
+    pub fn load(path: &Path) -> io::Result<ScriptedPeer> {
+        ScriptedPeer::parse(&fs::read_to_string(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    // Goes through the steps the given T-cycles allow
+    fn run(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+
+        while let Some(&step) = self.steps.front() {
+            match step {
+                ScriptStep::Led(on) => self.led = on,
+                ScriptStep::Wait(length) => {
+                    let left = length - self.waited;
+                    if remaining < left {
+                        self.waited += remaining;
+                        return;
+                    }
+                    remaining -= left;
+                    self.waited = 0;
+                }
+                ScriptStep::WaitForLed(on) => {
+                    if self.game_led != on {
+                        return;
+                    }
+                }
+            }
+            self.steps.pop_front();
+        }
+    }
+}
+
+impl InfraredDevice for ScriptedPeer {
+    fn set_led(&mut self, on: bool) {
+        self.game_led = on;
+        // Answers waiting for this happen right away
+        self.run(0);
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.run(cycles);
+    }
+
+    fn light(&self) -> bool {
+        self.led
+    }
+}
@@ -0,0 +1,40 @@
+/// Interrupts
+///
+/// Bit    Name      Handler \
+/// 0      VBlank    0x40    \
+/// 1      LCD       0x48    \
+/// 2      Timer     0x50    \
+/// 3      Serial    0x58    \
+/// 4      Joypad    0x60    \
+///
+/// An interrupt is requested by setting its bit in IF (0xFF0F), and will only be handled if
+/// its bit is also set in IE (0xFFFF). Bit 0 has the highest priority.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Interrupts.html))
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interrupt {
+    VBlank = 0b00001,
+    Lcd = 0b00010,
+    Timer = 0b00100,
+    Serial = 0b01000,
+    Joypad = 0b10000,
+}
+
+impl Interrupt {
+    pub fn bit(self) -> u8 {
+        self as u8
+    }
+
+    pub fn handler_address(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Lcd => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+// Only the lower 5 bits of IF are used, the rest read as 1
+pub const IF_UNUSED_BITS: u8 = 0b1110_0000;
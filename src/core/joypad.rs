@@ -0,0 +1,238 @@
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+
+const SELECT_BUTTONS: u8 = 0b0010_0000;
+const SELECT_DPAD: u8 = 0b0001_0000;
+const SELECT_MASK: u8 = SELECT_BUTTONS | SELECT_DPAD;
+const LINES_MASK: u8 = 0b0000_1111;
+// Bits 6 and 7 are unused and always read as 1
+const UNUSED_BITS: u8 = 0b1100_0000;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+impl Buttons {
+    // Active-low lines of the d-pad row
+    fn dpad_lines(&self) -> u8 {
+        !(self.right as u8 | (self.left as u8) << 1 | (self.up as u8) << 2 | (self.down as u8) << 3)
+            & LINES_MASK
+    }
+
+    // Active-low lines of the buttons row
+    fn button_lines(&self) -> u8 {
+        !(self.a as u8 | (self.b as u8) << 1 | (self.select as u8) << 2 | (self.start as u8) << 3)
+            & LINES_MASK
+    }
+}
+
+/// Joypad (P1/JOYP, 0xFF00)
+///
+/// Bit    Explanation                           \
+/// 5      Select buttons (0 = selected)         \
+/// 4      Select d-pad (0 = selected)           \
+/// 3      Start / Down (0 = pressed, read-only) \
+/// 2      Select / Up (0 = pressed, read-only)  \
+/// 1      B / Left (0 = pressed, read-only)     \
+/// 0      A / Right (0 = pressed, read-only)    \
+///
+/// The buttons are arranged in a 2x4 matrix, the game selects a row by writing bits 4 and 5
+/// and reads the state of the selected row in the lower nibble. When both rows are selected
+/// the lines are ANDed together. The joypad interrupt is requested whenever one of the lower
+/// 4 bits goes from high to low.
+///
+/// After a Super Game Boy MLT_REQ, the joypads of 2 or 4 players are read in turn. With no row
+/// selected the lower nibble reads 0x0F minus the current player, and deselecting both rows
+/// moves on to the next player.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Joypad_Input.html))
+pub struct Joypad {
+    select: u8,
+    buttons: Buttons,
+    // Players 2 to 4, only read by SGB games
+    other_buttons: [Buttons; 3],
+    players: u8,
+    // Counted from 0
+    player: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_MASK,
+            buttons: Buttons::default(),
+            other_buttons: [Buttons::default(); 3],
+            players: 1,
+            player: 0,
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    // 1, 2 or 4, set by the Super Game Boy
+    pub fn set_players(&mut self, players: u8) {
+        self.players = players;
+        self.player = 0;
+    }
+
+    // Buttons of player 2, 3 or 4
+    pub fn set_player_buttons(&mut self, player: usize, buttons: Buttons) {
+        if let Some(player_buttons) = self.other_buttons.get_mut(player.wrapping_sub(2)) {
+            *player_buttons = buttons;
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        UNUSED_BITS | self.select | self.lines()
+    }
+
+    // Returns true if the write caused a joypad interrupt
+    pub fn write(&mut self, data: u8) -> bool {
+        let old_lines = self.lines();
+        let old_select = self.select;
+        self.select = data & SELECT_MASK;
+
+        if self.select == SELECT_MASK && old_select != SELECT_MASK {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        falling_edge(old_lines, self.lines())
+    }
+
+    // Returns true if the new button state caused a joypad interrupt
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let old_lines = self.lines();
+        self.buttons = buttons;
+        falling_edge(old_lines, self.lines())
+    }
+
+    fn lines(&self) -> u8 {
+        if self.players > 1 && self.select == SELECT_MASK {
+            return LINES_MASK - self.player;
+        }
+
+        let buttons = match self.player {
+            0 => &self.buttons,
+            player => &self.other_buttons[player as usize - 1],
+        };
+        let mut lines = LINES_MASK;
+
+        if self.select & SELECT_DPAD == 0 {
+            lines &= buttons.dpad_lines();
+        }
+
+        if self.select & SELECT_BUTTONS == 0 {
+            lines &= buttons.button_lines();
+        }
+
+        lines
+    }
+
+    // Buttons come from the frontend, only the selected rows are part of the state
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.select);
+        writer.write_u8(self.players);
+        writer.write_u8(self.player);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select = reader.read_u8()? & SELECT_MASK;
+        self.players = reader.read_u8()?;
+        self.player = reader.read_u8()?;
+        if !matches!(self.players, 1 | 2 | 4) || self.player >= self.players {
+            return Err(SaveStateError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+fn falling_edge(old_lines: u8, new_lines: u8) -> bool {
+    old_lines & !new_lines & LINES_MASK != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DPAD: u8 = SELECT_BUTTONS;
+    const BUTTONS: u8 = SELECT_DPAD;
+    const BOTH: u8 = 0;
+    const NONE: u8 = SELECT_MASK;
+
+    fn pressed(right: bool, a: bool) -> Buttons {
+        Buttons {
+            right,
+            a,
+            ..Buttons::default()
+        }
+    }
+
+    #[test]
+    fn rows_are_selected_by_bits_4_and_5() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons {
+            down: true,
+            a: true,
+            ..Buttons::default()
+        });
+
+        joypad.write(DPAD);
+        assert_eq!(joypad.read(), 0xE7);
+        joypad.write(BUTTONS);
+        assert_eq!(joypad.read(), 0xDE);
+        joypad.write(NONE);
+        assert_eq!(joypad.read(), 0xFF);
+        // The lower nibble is read-only
+        joypad.write(DPAD | LINES_MASK);
+        assert_eq!(joypad.read(), 0xE7);
+    }
+
+    #[test]
+    fn both_rows_are_anded() {
+        let mut joypad = Joypad::new();
+        joypad.write(BOTH);
+
+        joypad.set_buttons(pressed(true, false));
+        assert_eq!(joypad.read(), 0xCE);
+        joypad.set_buttons(Buttons {
+            right: true,
+            b: true,
+            ..Buttons::default()
+        });
+        assert_eq!(joypad.read(), 0xCC);
+    }
+
+    #[test]
+    fn interrupts_on_falling_edges_of_selected_lines() {
+        let mut joypad = Joypad::new();
+        joypad.write(DPAD);
+
+        // Buttons of the other row don't drive any line
+        assert!(!joypad.set_buttons(pressed(false, true)));
+        assert!(joypad.set_buttons(pressed(true, true)));
+        // Held, then released: low to high
+        assert!(!joypad.set_buttons(pressed(true, true)));
+        assert!(!joypad.set_buttons(pressed(false, true)));
+
+        // Selecting the row of a pressed button pulls its line low
+        assert!(joypad.write(BOTH));
+        assert!(!joypad.write(DPAD));
+        assert!(!joypad.write(NONE));
+        assert!(joypad.write(BUTTONS));
+    }
+}
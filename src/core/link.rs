@@ -0,0 +1,408 @@
+//! Link cable
+//!
+//! Connects the serial ports of two emulators, either two GioBoyColor instances in the same
+//! process run side by side with Lockstep, or two processes over a TCP or Unix socket.
+//!
+//! Both ends exchange a message every SYNC_CYCLES T-cycles of emulated time, and each end only
+//! goes past a sync point once it has the message the other end sent at the previous one. Ends
+//! are never more than one sync period apart, and what they see of each other only changes at
+//! sync points, so transfers happen at the same emulated time however fast the hosts run.
+//!
+//! Field       Explanation                                                          \
+//! Flags       Bit 0: waiting for an external clock, bit 1: sent a byte             \
+//! Waiting     SB of the end waiting for an external clock                          \
+//! Sent        Byte sent with the internal clock since the previous sync point      \
+//! Received    Count of the bytes received so far, wrapping                         \
+//!
+//! The end with the internal clock only sends when the other end was waiting at the last sync
+//! point and had received everything sent before, and gets back the SB it reported, otherwise
+//! it gets 0xFF like with nothing connected. The byte is delivered at the next sync point, so
+//! transfers are late by up to SYNC_CYCLES, less than what one takes at 8192 Hz.
+//!
+//! Sockets start with HANDSHAKE, then the messages follow as 4 bytes each.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs},
+    rc::Rc,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+};
+
+use crate::core::{gbc::GioBoyColor, serial::SerialDevice};
+
+pub const DEFAULT_PORT: u16 = 2346;
+pub const SYNC_CYCLES: u32 = 1024;
+// Turns taken by Lockstep while either infrared port is in use, about one instruction
+pub const INFRARED_SYNC_CYCLES: u32 = 4;
+
+const HANDSHAKE: &[u8; 5] = b"GBLK\x01";
+// A peer silent for this long is considered gone
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const WAITING: u8 = 0b01;
+const SENT: u8 = 0b10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Message {
+    waiting: Option<u8>,
+    sent: Option<u8>,
+    received: u8,
+}
+
+impl Message {
+    // What an end says before the first sync point
+    const START: Message = Message {
+        waiting: None,
+        sent: None,
+        received: 0,
+    };
+
+    fn encode(self) -> [u8; 4] {
+        let flags = if self.waiting.is_some() { WAITING } else { 0 }
+            | if self.sent.is_some() { SENT } else { 0 };
+
+        [
+            flags,
+            self.waiting.unwrap_or(0xFF),
+            self.sent.unwrap_or(0xFF),
+            self.received,
+        ]
+    }
+
+    fn decode(bytes: [u8; 4]) -> Message {
+        Message {
+            waiting: (bytes[0] & WAITING != 0).then_some(bytes[1]),
+            sent: (bytes[0] & SENT != 0).then_some(bytes[2]),
+            received: bytes[3],
+        }
+    }
+}
+
+// How messages get to the other end
+trait Channel {
+    fn send(&mut self, message: Message) -> io::Result<()>;
+    // Blocks until the other end's next message arrives
+    fn receive(&mut self) -> io::Result<Message>;
+}
+
+// Both ends in the same process, which Lockstep keeps close enough that messages are
+// always there when needed
+struct Mailbox {
+    inbox: Rc<RefCell<VecDeque<Message>>>,
+    outbox: Rc<RefCell<VecDeque<Message>>>,
+}
+
+impl Channel for Mailbox {
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        self.outbox.borrow_mut().push_back(message);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Message> {
+        self.inbox.borrow_mut().pop_front().ok_or(io::Error::new(
+            ErrorKind::WouldBlock,
+            "the other end fell behind",
+        ))
+    }
+}
+
+struct Stream<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> Stream<S> {
+    // Exchanges the handshakes, the start messages are sent by LinkCable
+    fn open(mut stream: S) -> io::Result<Stream<S>> {
+        stream.write_all(HANDSHAKE)?;
+
+        let mut handshake = [0; HANDSHAKE.len()];
+        stream.read_exact(&mut handshake)?;
+        if &handshake != HANDSHAKE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the other end isn't a GioBoyColor link cable",
+            ));
+        }
+
+        Ok(Stream { stream })
+    }
+}
+
+impl<S: Read + Write> Channel for Stream<S> {
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        self.stream.write_all(&message.encode())
+    }
+
+    fn receive(&mut self) -> io::Result<Message> {
+        let mut bytes = [0; 4];
+        self.stream.read_exact(&mut bytes)?;
+        Ok(Message::decode(bytes))
+    }
+}
+
+// One end of the cable, plugged into a serial port with Serial::connect
+pub struct LinkCable {
+    // None once the other end is gone, the port then acts like nothing is connected
+    channel: Option<Box<dyn Channel>>,
+    // T-cycles since the last sync point
+    cycles: u32,
+    // SB while waiting for an external clock, since the last sync point
+    waiting: Option<u8>,
+    // Byte sent with the internal clock, since the last sync point
+    sent: Option<u8>,
+    sent_count: u8,
+    // SB of the other end, if it's ready for the next byte
+    peer_waiting: Option<u8>,
+    // Byte from the other end, until the serial port takes it
+    incoming: Option<u8>,
+    received_count: u8,
+}
+
+impl LinkCable {
+    fn new(channel: Box<dyn Channel>) -> LinkCable {
+        LinkCable {
+            channel: Some(channel),
+            cycles: 0,
+            waiting: None,
+            sent: None,
+            sent_count: 0,
+            peer_waiting: None,
+            incoming: None,
+            received_count: 0,
+        }
+    }
+
+    // Both ends of a cable, for two instances in this process
+    pub fn pair() -> (LinkCable, LinkCable) {
+        // The start messages are already in the mailboxes
+        let first = Rc::new(RefCell::new(VecDeque::from([Message::START])));
+        let second = Rc::new(RefCell::new(VecDeque::from([Message::START])));
+
+        (
+            LinkCable::new(Box::new(Mailbox {
+                inbox: first.clone(),
+                outbox: second.clone(),
+            })),
+            LinkCable::new(Box::new(Mailbox {
+                inbox: second,
+                outbox: first,
+            })),
+        )
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<LinkCable> {
+        LinkCable::from_tcp(TcpStream::connect(address)?)
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<LinkCable> {
+        LinkCable::from_unix(UnixStream::connect(path)?)
+    }
+
+    fn from_tcp(stream: TcpStream) -> io::Result<LinkCable> {
+        stream.set_nonblocking(false)?;
+        // Messages are tiny and every one of them is waited for
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        LinkCable::from_channel(Box::new(Stream::open(stream)?))
+    }
+
+    #[cfg(unix)]
+    fn from_unix(stream: UnixStream) -> io::Result<LinkCable> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        LinkCable::from_channel(Box::new(Stream::open(stream)?))
+    }
+
+    fn from_channel(mut channel: Box<dyn Channel>) -> io::Result<LinkCable> {
+        channel.send(Message::START)?;
+        Ok(LinkCable::new(channel))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.channel.is_some()
+    }
+
+    fn sync(&mut self) {
+        let Some(channel) = &mut self.channel else {
+            return;
+        };
+
+        let message = Message {
+            waiting: self.waiting.take(),
+            sent: self.sent.take(),
+            received: self.received_count,
+        };
+        let result = channel.send(message).and_then(|_| channel.receive());
+
+        let peer = match result {
+            Ok(peer) => peer,
+            Err(error) => {
+                println!("Link cable disconnected: {}", error);
+                self.channel = None;
+                self.peer_waiting = None;
+                self.incoming = None;
+                return;
+            }
+        };
+
+        // Until the other end got everything sent, its SB is from before
+        self.peer_waiting = peer.waiting.filter(|_| peer.received == self.sent_count);
+
+        // A byte the serial port didn't take in time is lost
+        self.incoming = peer.sent;
+        if peer.sent.is_some() {
+            self.received_count = self.received_count.wrapping_add(1);
+        }
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn transfer(&mut self, data: u8) -> u8 {
+        match self.peer_waiting.take() {
+            Some(peer_data) if self.sent.is_none() => {
+                self.sent = Some(data);
+                self.sent_count = self.sent_count.wrapping_add(1);
+                peer_data
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn external_clock(&mut self, data: u8) -> Option<u8> {
+        if self.incoming.is_some() {
+            return self.incoming.take();
+        }
+
+        self.waiting = Some(data);
+        None
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= SYNC_CYCLES {
+            self.cycles -= SYNC_CYCLES;
+            self.sync();
+        }
+    }
+}
+
+// Waits for the other end on a TCP port or a Unix socket, polled without blocking
+pub struct LinkListener {
+    listener: Listener,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl LinkListener {
+    // Only reachable from this machine
+    pub fn bind(port: u16) -> io::Result<LinkListener> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(LinkListener {
+            listener: Listener::Tcp(listener),
+        })
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<LinkListener> {
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(LinkListener {
+            listener: Listener::Unix(listener),
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(|address| address.port()),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    // The cable once the other end connected
+    pub fn accept(&self) -> io::Result<Option<LinkCable>> {
+        let result = match &self.listener {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .and_then(|(stream, _)| LinkCable::from_tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .accept()
+                .and_then(|(stream, _)| LinkCable::from_unix(stream)),
+        };
+
+        match result {
+            Ok(cable) => Ok(Some(cable)),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+// Runs two instances linked with LinkCable::pair or InfraredLink::pair side by side, taking
+// turns so that neither gets a sync period ahead of the other. While either infrared port is
+// reading or sending, turns are INFRARED_SYNC_CYCLES long instead, so that pulses keep their
+// width on the other side
+pub struct Lockstep {
+    // T-cycles run by each instance
+    first_cycles: u64,
+    second_cycles: u64,
+}
+
+impl Lockstep {
+    pub fn new() -> Lockstep {
+        Lockstep {
+            first_cycles: 0,
+            second_cycles: 0,
+        }
+    }
+
+    // Runs both instances for at least the given amount of T-cycles
+    pub fn run(&mut self, first: &mut GioBoyColor, second: &mut GioBoyColor, cycles: u32) {
+        let end = self.first_cycles.max(self.second_cycles) + cycles as u64;
+
+        // Turns start from the instance behind, which may have ended the previous one early
+        while self.first_cycles.min(self.second_cycles) < end {
+            let period = if first.infrared.is_active() || second.infrared.is_active() {
+                INFRARED_SYNC_CYCLES
+            } else {
+                SYNC_CYCLES
+            };
+            let target = (self.first_cycles.min(self.second_cycles) + period as u64).min(end);
+
+            // A turn ends early once an infrared port is put to use, so the next ones are short
+            if self.first_cycles < target {
+                let cycles = (target - self.first_cycles) as u32;
+                let result = first.run_cycles_until(cycles, |gbc| gbc.infrared.is_active());
+                self.first_cycles += result.cycles as u64;
+            }
+            if self.second_cycles < target {
+                let cycles = (target - self.second_cycles) as u32;
+                let result = second.run_cycles_until(cycles, |gbc| gbc.infrared.is_active());
+                self.second_cycles += result.cycles as u64;
+            }
+        }
+    }
+}
+
+impl Default for Lockstep {
+    fn default() -> Lockstep {
+        Lockstep::new()
+    }
+}
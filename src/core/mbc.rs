@@ -0,0 +1,285 @@
+use crate::core::save_state::{SaveStateError, StateReader, StateWriter};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+const RTC_REGISTERS: usize = 5;
+
+/// Memory Bank Controllers
+///
+/// Writes to the ROM area don't modify the ROM, they set the registers of the cartridge's MBC
+/// instead, which select the banks mapped at 0x4000-0x7FFF and 0xA000-0xBFFF.
+///
+/// MBC    Address        Explanation                                               \
+/// MBC1   0x0000-0x1FFF  RAM enable (0x0A in the lower 4 bits enables)             \
+///        0x2000-0x3FFF  ROM bank number, lower 5 bits (0 is treated as 1)         \
+///        0x4000-0x5FFF  RAM bank number, or upper 2 bits of the ROM bank number   \
+///        0x6000-0x7FFF  Banking mode select                                       \
+/// MBC3   0x0000-0x1FFF  RAM and RTC enable                                        \
+///        0x2000-0x3FFF  ROM bank number, 7 bits (0 is treated as 1)               \
+///        0x4000-0x5FFF  RAM bank number (0x00-0x03) or RTC register (0x08-0x0C)   \
+///        0x6000-0x7FFF  Latch clock data                                          \
+/// MBC5   0x0000-0x1FFF  RAM enable                                                \
+///        0x2000-0x2FFF  ROM bank number, lower 8 bits                             \
+///        0x3000-0x3FFF  ROM bank number, 9th bit                                  \
+///        0x4000-0x5FFF  RAM bank number (0x00-0x0F)                               \
+///
+/// The MBC3 real time clock registers can be read and written, but the clock doesn't run.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/MBCs.html))
+pub enum Mbc {
+    None,
+    Mbc1 {
+        ram_enabled: bool,
+        rom_bank: u8,
+        bank2: u8,
+        advanced_mode: bool,
+    },
+    Mbc3 {
+        ram_enabled: bool,
+        rom_bank: u8,
+        // 0x00-0x03 selects a RAM bank, 0x08-0x0C an RTC register
+        ram_bank: u8,
+        rtc: [u8; RTC_REGISTERS],
+    },
+    Mbc5 {
+        ram_enabled: bool,
+        rom_bank: u16,
+        ram_bank: u8,
+    },
+}
+
+impl Mbc {
+    // Cartridge types with an unsupported MBC are treated as ROM only
+    pub fn from_cartridge_type(cartridge_type: u8) -> Mbc {
+        match cartridge_type {
+            0x01..=0x03 => Mbc::Mbc1 {
+                ram_enabled: false,
+                rom_bank: 1,
+                bank2: 0,
+                advanced_mode: false,
+            },
+            0x0F..=0x13 => Mbc::Mbc3 {
+                ram_enabled: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                rtc: [0; RTC_REGISTERS],
+            },
+            0x19..=0x1E => Mbc::Mbc5 {
+                ram_enabled: false,
+                rom_bank: 1,
+                ram_bank: 0,
+            },
+            _ => Mbc::None,
+        }
+    }
+
+    // Writes to 0x0000-0x7FFF
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match self {
+            Mbc::None => (),
+            Mbc::Mbc1 {
+                ram_enabled,
+                rom_bank,
+                bank2,
+                advanced_mode,
+            } => match address {
+                0x0000..=0x1FFF => *ram_enabled = data & 0x0F == 0x0A,
+                0x2000..=0x3FFF => *rom_bank = (data & 0x1F).max(1),
+                0x4000..=0x5FFF => *bank2 = data & 0x03,
+                _ => *advanced_mode = data & 0x01 != 0,
+            },
+            Mbc::Mbc3 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+                ..
+            } => match address {
+                0x0000..=0x1FFF => *ram_enabled = data & 0x0F == 0x0A,
+                0x2000..=0x3FFF => *rom_bank = (data & 0x7F).max(1),
+                0x4000..=0x5FFF => *ram_bank = data,
+                _ => (),
+            },
+            Mbc::Mbc5 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+            } => match address {
+                0x0000..=0x1FFF => *ram_enabled = data & 0x0F == 0x0A,
+                0x2000..=0x2FFF => *rom_bank = (*rom_bank & 0x100) | data as u16,
+                0x3000..=0x3FFF => *rom_bank = (*rom_bank & 0xFF) | ((data as u16 & 0x01) << 8),
+                0x4000..=0x5FFF => *ram_bank = data & 0x0F,
+                _ => (),
+            },
+        }
+    }
+
+    // Offset in the ROM of an address in 0x0000-0x7FFF
+    pub fn rom_offset(&self, address: u16) -> usize {
+        let bank = match (self, address) {
+            (Mbc::None, _) => return address as usize,
+            // Bank 0 can only be remapped by MBC1 in advanced banking mode
+            (
+                Mbc::Mbc1 {
+                    bank2,
+                    advanced_mode: true,
+                    ..
+                },
+                0x0000..=0x3FFF,
+            ) => (*bank2 as usize) << 5,
+            (_, 0x0000..=0x3FFF) => 0,
+            (
+                Mbc::Mbc1 {
+                    rom_bank, bank2, ..
+                },
+                _,
+            ) => ((*bank2 as usize) << 5) | *rom_bank as usize,
+            (Mbc::Mbc3 { rom_bank, .. }, _) => *rom_bank as usize,
+            (Mbc::Mbc5 { rom_bank, .. }, _) => *rom_bank as usize,
+        };
+
+        bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))
+    }
+
+    // Offset in cartridge RAM of an address in 0xA000-0xBFFF, None while RAM is disabled
+    pub fn ram_offset(&self, address: u16) -> Option<usize> {
+        let bank = match self {
+            Mbc::None => 0,
+            Mbc::Mbc1 {
+                ram_enabled: false, ..
+            }
+            | Mbc::Mbc3 {
+                ram_enabled: false, ..
+            }
+            | Mbc::Mbc5 {
+                ram_enabled: false, ..
+            } => return None,
+            Mbc::Mbc1 {
+                bank2,
+                advanced_mode,
+                ..
+            } => {
+                if *advanced_mode {
+                    *bank2 as usize
+                } else {
+                    0
+                }
+            }
+            Mbc::Mbc3 { ram_bank, .. } if *ram_bank <= 0x03 => *ram_bank as usize,
+            // An RTC register is mapped instead
+            Mbc::Mbc3 { .. } => return None,
+            Mbc::Mbc5 { ram_bank, .. } => *ram_bank as usize,
+        };
+
+        Some(bank * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1)))
+    }
+
+    // Index of the MBC3 RTC register mapped at 0xA000-0xBFFF, if any
+    fn rtc_index(&self) -> Option<usize> {
+        match self {
+            Mbc::Mbc3 {
+                ram_enabled: true,
+                ram_bank: bank @ 0x08..=0x0C,
+                ..
+            } => Some((*bank - 0x08) as usize),
+            _ => None,
+        }
+    }
+
+    pub fn read_rtc(&self) -> Option<u8> {
+        match (self, self.rtc_index()) {
+            (Mbc::Mbc3 { rtc, .. }, Some(index)) => Some(rtc[index]),
+            _ => None,
+        }
+    }
+
+    // Returns false if no RTC register is mapped
+    pub fn write_rtc(&mut self, data: u8) -> bool {
+        let index = self.rtc_index();
+
+        match (self, index) {
+            (Mbc::Mbc3 { rtc, .. }, Some(index)) => {
+                rtc[index] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        match self {
+            Mbc::None => (),
+            Mbc::Mbc1 {
+                ram_enabled,
+                rom_bank,
+                bank2,
+                advanced_mode,
+            } => {
+                writer.write_bool(*ram_enabled);
+                writer.write_u8(*rom_bank);
+                writer.write_u8(*bank2);
+                writer.write_bool(*advanced_mode);
+            }
+            Mbc::Mbc3 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+                rtc,
+            } => {
+                writer.write_bool(*ram_enabled);
+                writer.write_u8(*rom_bank);
+                writer.write_u8(*ram_bank);
+                writer.write_bytes(rtc);
+            }
+            Mbc::Mbc5 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+            } => {
+                writer.write_bool(*ram_enabled);
+                writer.write_u16(*rom_bank);
+                writer.write_u8(*ram_bank);
+            }
+        }
+    }
+
+    // The MBC type is given by the ROM, so only its registers are stored
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        match self {
+            Mbc::None => (),
+            Mbc::Mbc1 {
+                ram_enabled,
+                rom_bank,
+                bank2,
+                advanced_mode,
+            } => {
+                *ram_enabled = reader.read_bool()?;
+                *rom_bank = reader.read_u8()?;
+                *bank2 = reader.read_u8()?;
+                *advanced_mode = reader.read_bool()?;
+            }
+            Mbc::Mbc3 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+                rtc,
+            } => {
+                *ram_enabled = reader.read_bool()?;
+                *rom_bank = reader.read_u8()?;
+                *ram_bank = reader.read_u8()?;
+                reader.read_bytes(rtc)?;
+            }
+            Mbc::Mbc5 {
+                ram_enabled,
+                rom_bank,
+                ram_bank,
+            } => {
+                *ram_enabled = reader.read_bool()?;
+                *rom_bank = reader.read_u16()?;
+                *ram_bank = reader.read_u8()?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -20,3 +20,62 @@ pub const HRAM_START: u16 = 0xFF80;
 pub const HRAM_END: u16 = 0xFFFE;
 pub const IE_START: u16 = 0xFFFF;
 pub const IE_END: u16 = 0xFFFF;
+
+// IO registers
+pub const JOYP: u16 = 0xFF00;
+pub const IF: u16 = 0xFF0F;
+pub const IE: u16 = IE_START;
+
+// Audio registers
+pub const AUDIO_START: u16 = 0xFF10;
+pub const NR10: u16 = 0xFF10;
+pub const NR14: u16 = 0xFF14;
+pub const NR20: u16 = 0xFF15;
+pub const NR21: u16 = 0xFF16;
+pub const NR24: u16 = 0xFF19;
+pub const NR30: u16 = 0xFF1A;
+pub const NR34: u16 = 0xFF1E;
+pub const NR40: u16 = 0xFF1F;
+pub const NR41: u16 = 0xFF20;
+pub const NR44: u16 = 0xFF23;
+pub const NR50: u16 = 0xFF24;
+pub const NR51: u16 = 0xFF25;
+pub const NR52: u16 = 0xFF26;
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+pub const AUDIO_END: u16 = 0xFF3F;
+
+// Serial registers
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+// Timer registers
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+
+// LCD registers
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const DMA: u16 = 0xFF46;
+pub const BGP: u16 = 0xFF47;
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+pub const VBK: u16 = 0xFF4F;
+pub const BCPS: u16 = 0xFF68;
+pub const BCPD: u16 = 0xFF69;
+pub const OCPS: u16 = 0xFF6A;
+pub const OCPD: u16 = 0xFF6B;
+
+// CGB infrared port
+pub const RP: u16 = 0xFF56;
+
+// CGB WRAM bank
+pub const SVBK: u16 = 0xFF70;
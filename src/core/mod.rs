@@ -1,8 +1,30 @@
 #[macro_use]
 mod helpers;
 
+pub mod apu;
+pub mod bus;
+pub mod cheats;
+pub mod compat_palettes;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod flat_bus;
 pub mod gbc;
-mod gpu;
+pub mod gdb;
+pub mod gpu;
+pub mod infrared;
+pub mod interrupts;
+pub mod joypad;
+pub mod link;
+mod mbc;
 mod memory_map;
+pub mod printer;
 mod registers;
+pub mod rewind;
 mod rom;
+pub mod save_state;
+pub mod serial;
+pub mod sgb;
+pub mod symbols;
+pub mod timer;
+pub mod trace;
@@ -0,0 +1,486 @@
+//! Game Boy Printer
+//!
+//! A SerialDevice receiving packets from the Game Boy, which sends every byte with its internal
+//! clock. The printer answers 0x00 to every byte but the last two of a packet.
+//!
+//! Byte       Explanation                                                              \
+//! 0x88 0x33  Magic bytes                                                              \
+//! Command    0x01: init, 0x02: print, 0x04: data, 0x0F: status                        \
+//! Flags      Bit 0: data is RLE compressed                                            \
+//! Length     Length of the data, little endian                                        \
+//! Data       Depends on the command                                                   \
+//! Checksum   Sum of the command, flags, length and data bytes, little endian          \
+//! 0x00       The printer answers 0x81                                                 \
+//! 0x00       The printer answers with its status                                      \
+//!
+//! Data packets carry bands of 2x20 tiles in the 2bpp format of VRAM, an empty one ends the
+//! image. Print packets carry 4 bytes: the number of sheets, the margins in line feeds before
+//! (upper nibble) and after (lower nibble), the palette like BGP and the exposure, which is
+//! ignored. Compressed data alternates a control byte with either (control + 1) bytes copied
+//! as is, or if bit 7 is set a byte repeated ((control & 0x7F) + 2) times.
+//!
+//! Status bit  Explanation                                                             \
+//! 3           Unprocessed data                                                        \
+//! 2           Image data full                                                         \
+//! 1           Currently printing                                                      \
+//! 0           Checksum error                                                          \
+//!
+//! Printed bands add up on the same piece of paper until a print ends with a margin after,
+//! which tears it off as a PNG in the output directory, printout_1.png, printout_2.png and so
+//! on. Whatever is left is written when the printer is disconnected.
+//!
+//! (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Gameboy_Printer.html))
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::core::serial::SerialDevice;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const ALIVE: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_UNPROCESSED: u8 = 0b1000;
+const STATUS_FULL: u8 = 0b0100;
+const STATUS_PRINTING: u8 = 0b0010;
+const STATUS_CHECKSUM_ERROR: u8 = 0b0001;
+
+pub const PAPER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PAPER_WIDTH / 8;
+// 2 rows of tiles, 16 bytes each
+const BAND_SIZE: usize = TILES_PER_ROW * 2 * 16;
+const BAND_HEIGHT: usize = 16;
+const MAX_BANDS: usize = 9;
+// Blank rows fed for every line feed of the margins
+const LINE_FEED_ROWS: usize = BAND_HEIGHT;
+// Some games leave the palette at 0, which prints like the usual one
+const DEFAULT_PALETTE: u8 = 0xE4;
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+// How long printing takes, in T-cycles, about half a second
+const PRINT_CYCLES: u32 = 1 << 21;
+
+// Where the next byte of a packet goes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Magic(usize),
+    Command,
+    Flags,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    directory: PathBuf,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    // Decompressed image data waiting to be printed
+    image: Vec<u8>,
+    // Shades of the printed rows not torn off yet
+    paper: Vec<u8>,
+    printing_cycles: u32,
+}
+
+impl Printer {
+    // Printouts are written to the directory, created when needed
+    pub fn new(directory: &Path) -> Printer {
+        Printer {
+            directory: directory.to_path_buf(),
+            state: State::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            image: Vec::new(),
+            paper: Vec::new(),
+            printing_cycles: 0,
+        }
+    }
+
+    // Returns the answer to the byte
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut answer = 0x00;
+
+        self.state = match self.state {
+            State::Magic(index) if byte == MAGIC[index] => {
+                if index + 1 < MAGIC.len() {
+                    State::Magic(index + 1)
+                } else {
+                    State::Command
+                }
+            }
+            State::Magic(_) if byte == MAGIC[0] => State::Magic(1),
+            // Anything else waits for the start of a packet
+            State::Magic(_) => State::Magic(0),
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Flags
+            }
+            State::Flags => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() < self.length as usize {
+                    State::Data
+                } else {
+                    State::ChecksumLow
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.handle_packet();
+                State::Alive
+            }
+            State::Alive => {
+                answer = ALIVE;
+                State::Status
+            }
+            State::Status => {
+                answer = self.status();
+                State::Magic(0)
+            }
+        };
+
+        answer
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.status;
+        if !self.image.is_empty() {
+            status |= STATUS_UNPROCESSED;
+        }
+        if self.image.len() >= BAND_SIZE * MAX_BANDS {
+            status |= STATUS_FULL;
+        }
+        if self.printing_cycles > 0 {
+            status |= STATUS_PRINTING;
+        }
+        status
+    }
+
+    fn handle_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+            }
+            COMMAND_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+                let space = BAND_SIZE * MAX_BANDS - self.image.len();
+                self.image.extend(data.into_iter().take(space));
+            }
+            COMMAND_PRINT if self.data.len() >= 4 => {
+                self.print(self.data[0], self.data[1], self.data[2]);
+            }
+            // Only asks for the status
+            COMMAND_STATUS => (),
+            _ => (),
+        }
+    }
+
+    // No sheets only feeds the paper
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        let palette = if palette == 0 {
+            DEFAULT_PALETTE
+        } else {
+            palette
+        };
+        let (before, after) = ((margins >> 4) as usize, (margins & 0x0F) as usize);
+
+        self.feed(before);
+
+        if sheets == 0 {
+            self.feed(after);
+            return;
+        }
+
+        for band in self.image.chunks(BAND_SIZE) {
+            // A partial band is printed as blank where data is missing
+            let mut rows = vec![SHADES[0]; PAPER_WIDTH * BAND_HEIGHT];
+
+            for (tile, bytes) in band.chunks_exact(16).enumerate() {
+                let tile_x = (tile % TILES_PER_ROW) * 8;
+                let tile_y = (tile / TILES_PER_ROW) * 8;
+
+                for row in 0..8 {
+                    let (low, high) = (bytes[row * 2], bytes[row * 2 + 1]);
+                    for column in 0..8 {
+                        let bit = 7 - column;
+                        let color = ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01);
+                        let shade = (palette >> (color * 2)) & 0x03;
+                        rows[(tile_y + row) * PAPER_WIDTH + tile_x + column] =
+                            SHADES[shade as usize];
+                    }
+                }
+            }
+
+            self.paper.extend(rows);
+        }
+
+        self.image.clear();
+        self.printing_cycles = PRINT_CYCLES;
+
+        if after > 0 {
+            self.feed(after);
+            self.tear_off();
+        }
+    }
+
+    fn feed(&mut self, line_feeds: usize) {
+        let rows = line_feeds * LINE_FEED_ROWS;
+        self.paper
+            .resize(self.paper.len() + rows * PAPER_WIDTH, SHADES[0]);
+    }
+
+    // Writes the paper printed so far
+    fn tear_off(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+
+        let paper = std::mem::take(&mut self.paper);
+        match self.save_png(&paper) {
+            Ok(path) => println!("Printed {}", path.display()),
+            Err(error) => println!("Unable to save the printout: {}", error),
+        }
+    }
+
+    fn save_png(&self, paper: &[u8]) -> Result<PathBuf, png::EncodingError> {
+        fs::create_dir_all(&self.directory)?;
+
+        let path = (1..)
+            .map(|number| self.directory.join(format!("printout_{}.png", number)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        let file = File::create(&path)?;
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            PAPER_WIDTH as u32,
+            (paper.len() / PAPER_WIDTH) as u32,
+        );
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(paper)?;
+
+        Ok(path)
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.printing_cycles = self.printing_cycles.saturating_sub(cycles);
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.tear_off();
+    }
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+
+    while let Some(&control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(&byte) = bytes.next() else {
+                break;
+            };
+            output.resize(output.len() + (control & 0x7F) as usize + 2, byte);
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    // Sends a packet, returns the printer's answers to the last two bytes
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let length = data.len() as u16;
+        let mut packet = vec![command, compressed as u8, length as u8, (length >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend([checksum as u8, (checksum >> 8) as u8]);
+
+        for byte in MAGIC.into_iter().chain(packet) {
+            assert_eq!(printer.receive(byte), 0x00);
+        }
+        (printer.receive(0x00), printer.receive(0x00))
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gioboycolor-printer-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn decompresses_runs_and_literals() {
+        // 3 literals, 0xAA repeated 2 times, 1 literal, 0x00 repeated 5 times
+        let data = [0x02, 1, 2, 3, 0x80, 0xAA, 0x00, 4, 0x83, 0x00];
+        assert_eq!(decompress(&data), [1, 2, 3, 0xAA, 0xAA, 4, 0, 0, 0, 0, 0]);
+        // Missing bytes at the end are ignored
+        assert_eq!(decompress(&[0x03, 1, 2]), [1, 2]);
+        assert_eq!(decompress(&[0x81]), []);
+    }
+
+    #[test]
+    fn answers_status() {
+        let mut printer = Printer::new(&directory("status"));
+
+        assert_eq!(send(&mut printer, COMMAND_STATUS, false, &[]), (ALIVE, 0));
+        assert_eq!(send(&mut printer, COMMAND_INIT, false, &[]), (ALIVE, 0));
+    }
+
+    #[test]
+    fn resynchronizes_on_magic_bytes() {
+        let mut printer = Printer::new(&directory("magic"));
+
+        // Noise, then a packet starting after a repeated first magic byte
+        for byte in [0x00, 0x88, 0x88] {
+            printer.receive(byte);
+        }
+        assert_eq!(printer.state, State::Magic(1));
+        printer.receive(0x33);
+        assert_eq!(printer.state, State::Command);
+    }
+
+    #[test]
+    fn reports_checksum_errors() {
+        let mut printer = Printer::new(&directory("checksum"));
+
+        for byte in [0x88, 0x33, COMMAND_STATUS, 0, 0, 0, 0x00, 0x00] {
+            printer.receive(byte);
+        }
+        assert_eq!(printer.receive(0x00), ALIVE);
+        assert_eq!(printer.receive(0x00), STATUS_CHECKSUM_ERROR);
+
+        // Cleared by the next valid packet
+        assert_eq!(send(&mut printer, COMMAND_STATUS, false, &[]), (ALIVE, 0));
+    }
+
+    #[test]
+    fn buffers_data_until_full() {
+        let mut printer = Printer::new(&directory("full"));
+
+        let band = vec![0x00; BAND_SIZE];
+        assert_eq!(
+            send(&mut printer, COMMAND_DATA, false, &band),
+            (ALIVE, STATUS_UNPROCESSED)
+        );
+        // A compressed band of 640 zeros, 5 runs of 128
+        let compressed = [0xFE, 0x00].repeat(5);
+        for _ in 1..MAX_BANDS {
+            send(&mut printer, COMMAND_DATA, true, &compressed);
+        }
+        assert_eq!(printer.image.len(), BAND_SIZE * MAX_BANDS);
+        assert_eq!(
+            send(&mut printer, COMMAND_STATUS, false, &[]),
+            (ALIVE, STATUS_UNPROCESSED | STATUS_FULL)
+        );
+
+        // Extra data is dropped
+        send(&mut printer, COMMAND_DATA, false, &band);
+        assert_eq!(printer.image.len(), BAND_SIZE * MAX_BANDS);
+    }
+
+    #[test]
+    fn prints_and_tears_off() {
+        let directory = directory("print");
+        let mut printer = Printer::new(&directory);
+
+        // One band, the first tile black (color 3) and the rest white
+        let mut band = vec![0x00; BAND_SIZE];
+        band[..16].fill(0xFF);
+        send(&mut printer, COMMAND_DATA, false, &band);
+        send(&mut printer, COMMAND_DATA, false, &[]);
+        // No margin before, one line feed after
+        assert_eq!(
+            send(&mut printer, COMMAND_PRINT, false, &[1, 0x01, 0xE4, 0x40]),
+            (ALIVE, STATUS_PRINTING)
+        );
+        printer.tick(PRINT_CYCLES);
+        assert_eq!(send(&mut printer, COMMAND_STATUS, false, &[]), (ALIVE, 0));
+
+        let path = directory.join("printout_1.png");
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut image).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let info = reader.info();
+        assert_eq!(info.width as usize, PAPER_WIDTH);
+        assert_eq!(info.height as usize, BAND_HEIGHT + LINE_FEED_ROWS);
+        assert_eq!(image[0], SHADES[3]);
+        assert_eq!(image[7 * PAPER_WIDTH + 7], SHADES[3]);
+        assert_eq!(image[8], SHADES[0]);
+        assert_eq!(image[8 * PAPER_WIDTH], SHADES[0]);
+    }
+}
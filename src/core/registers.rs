@@ -1,3 +1,5 @@
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+
 /// Registers
 ///
 /// 16-bit	Hi	Lo	Name/Function           \
@@ -120,4 +122,23 @@ impl Registers {
         let new = self.hl().wrapping_sub(1);
         self.set_hl(new);
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&[
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ]);
+        writer.write_u16(self.sp);
+        writer.write_u16(self.pc);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut bytes = [0; 8];
+        reader.read_bytes(&mut bytes)?;
+        [
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ] = bytes;
+        self.sp = reader.read_u16()?;
+        self.pc = reader.read_u16()?;
+        Ok(())
+    }
 }
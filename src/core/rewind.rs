@@ -0,0 +1,283 @@
+//! Rewind buffer
+//!
+//! Snapshots of the machine are taken every few frames and kept for a configurable amount of
+//! seconds. To keep memory usage bounded, most snapshots are stored as a delta against the
+//! last keyframe: the two states are XORed, so unchanged bytes become 0, and the runs of zeros
+//! are then run-length encoded.
+//!
+//! Delta encoding, repeated until the end of the state:
+//!
+//! Field       Explanation                                \
+//! zeros       Varint, bytes equal to the keyframe        \
+//! literals    Varint, count of XORed bytes that follow   \
+//! bytes       The XORed bytes                            \
+
+use std::collections::VecDeque;
+
+use crate::core::gbc::GioBoyColor;
+
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+// Frames emulated between snapshots, rewinding plays one snapshot per frame
+const FRAMES_PER_SNAPSHOT: u32 = 2;
+const FRAMES_PER_SECOND: u32 = 60;
+// Snapshots stored as a delta of each keyframe
+const DELTAS_PER_KEYFRAME: usize = 30;
+
+// A keyframe and the snapshots taken after it, which can't outlive it
+struct SnapshotGroup {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl SnapshotGroup {
+    fn len(&self) -> usize {
+        1 + self.deltas.len()
+    }
+}
+
+pub struct RewindBuffer {
+    groups: VecDeque<SnapshotGroup>,
+    // Maximum amount of snapshots, including keyframes
+    capacity: usize,
+    frame_counter: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(seconds: u32) -> RewindBuffer {
+        RewindBuffer {
+            groups: VecDeque::new(),
+            capacity: snapshots_for(seconds),
+            frame_counter: 0,
+        }
+    }
+
+    pub fn set_seconds(&mut self, seconds: u32) {
+        self.capacity = snapshots_for(seconds);
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.frame_counter = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    // Amount of stored snapshots
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(SnapshotGroup::len).sum()
+    }
+
+    // Memory used by the stored snapshots, in bytes
+    pub fn size(&self) -> usize {
+        self.groups
+            .iter()
+            .map(|group| group.keyframe.len() + group.deltas.iter().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+
+    // Should be called after every emulated frame, snapshots are taken every few frames
+    pub fn record_frame(&mut self, gbc: &GioBoyColor) {
+        self.frame_counter += 1;
+
+        if self.frame_counter >= FRAMES_PER_SNAPSHOT {
+            self.frame_counter = 0;
+            self.push(gbc.save_state());
+        }
+    }
+
+    // Restores the most recent snapshot and removes it, returns false once history runs out
+    pub fn rewind_frame(&mut self, gbc: &mut GioBoyColor) -> bool {
+        self.frame_counter = 0;
+
+        match self.pop() {
+            Some(state) => gbc.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        match self.groups.back_mut() {
+            Some(group)
+                if group.deltas.len() < DELTAS_PER_KEYFRAME
+                    && group.keyframe.len() == state.len() =>
+            {
+                group.deltas.push(encode_delta(&group.keyframe, &state));
+            }
+            _ => self.groups.push_back(SnapshotGroup {
+                keyframe: state,
+                deltas: Vec::new(),
+            }),
+        }
+
+        self.trim();
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+
+        match group.deltas.pop() {
+            Some(delta) => Some(decode_delta(&group.keyframe, &delta)),
+            None => self.groups.pop_back().map(|group| group.keyframe),
+        }
+    }
+
+    // Drops the oldest groups while the rest still cover the whole history. A group is only
+    // dropped as a whole since its deltas need the keyframe, so up to one extra group is kept
+    fn trim(&mut self) {
+        while let Some(oldest) = self.groups.front() {
+            if self.len() - oldest.len() < self.capacity {
+                break;
+            }
+            self.groups.pop_front();
+        }
+    }
+}
+
+fn snapshots_for(seconds: u32) -> usize {
+    (seconds * FRAMES_PER_SECOND / FRAMES_PER_SNAPSHOT) as usize
+}
+
+fn encode_delta(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+
+    while position < state.len() {
+        let zeros_start = position;
+        while position < state.len() && state[position] == keyframe[position] {
+            position += 1;
+        }
+
+        let literals_start = position;
+        while position < state.len() && state[position] != keyframe[position] {
+            position += 1;
+        }
+
+        write_varint(&mut delta, literals_start - zeros_start);
+        write_varint(&mut delta, position - literals_start);
+        delta.extend((literals_start..position).map(|index| state[index] ^ keyframe[index]));
+    }
+
+    delta
+}
+
+fn decode_delta(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut state = keyframe.to_vec();
+    let mut position = 0;
+    let mut index = 0;
+
+    while index < delta.len() {
+        position += read_varint(delta, &mut index);
+        let literals = read_varint(delta, &mut index);
+
+        for byte in &delta[index..index + literals] {
+            state[position] ^= byte;
+            position += 1;
+        }
+        index += literals;
+    }
+
+    state
+}
+
+// LEB128, 7 bits per byte with the high bit set on every byte but the last
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], index: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = input[*index];
+        *index += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        let mut output = Vec::new();
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 123_456_789] {
+            write_varint(&mut output, value);
+        }
+        assert_eq!(&output[..4], &[0x00, 0x01, 0x7F, 0x80]);
+
+        let mut index = 0;
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 123_456_789] {
+            assert_eq!(read_varint(&output, &mut index), value);
+        }
+        assert_eq!(index, output.len());
+    }
+
+    #[test]
+    fn delta_of_identical_states_is_one_run() {
+        let keyframe = vec![0x12; 1000];
+        let delta = encode_delta(&keyframe, &keyframe);
+
+        // 1000 zeros as a 2 byte varint, then no literals
+        assert_eq!(delta, [0xE8, 0x07, 0x00]);
+        assert_eq!(decode_delta(&keyframe, &delta), keyframe);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let keyframe: Vec<u8> = (0..5000).map(|index| (index * 7) as u8).collect();
+        let mut state = keyframe.clone();
+        state[0] ^= 0xFF;
+        state[1] = 0x00;
+        state[300..310].fill(0xAA);
+        state[4999] = state[4999].wrapping_add(1);
+
+        let delta = encode_delta(&keyframe, &state);
+        assert!(delta.len() < 40);
+        assert_eq!(decode_delta(&keyframe, &delta), state);
+    }
+
+    #[test]
+    fn buffer_pops_snapshots_in_reverse() {
+        let mut buffer = RewindBuffer::new(DEFAULT_REWIND_SECONDS);
+        let states: Vec<Vec<u8>> = (0..DELTAS_PER_KEYFRAME + 5)
+            .map(|index| vec![index as u8; 64])
+            .collect();
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), states.len());
+
+        for state in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn buffer_keeps_whole_groups_within_capacity() {
+        let mut buffer = RewindBuffer::new(1);
+        let capacity = snapshots_for(1);
+        for index in 0..capacity * 3 {
+            buffer.push(vec![index as u8; 16]);
+        }
+
+        // Up to one extra group is kept so that its deltas can still be decoded
+        assert!(buffer.len() >= capacity);
+        assert!(buffer.len() < capacity + DELTAS_PER_KEYFRAME + 1);
+        assert_eq!(buffer.pop(), Some(vec![(capacity * 3 - 1) as u8; 16]));
+    }
+}
@@ -1,38 +1,160 @@
-use std::{vec::Vec, fs::File, io::Read, path::PathBuf, fmt};
+use std::{fmt, fs, io, path::PathBuf};
+
+use crate::core::{
+    helpers::checksum::crc32,
+    mbc::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE},
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
+// Every ROM starts with the entry point and the header, up to 0x014F
+const MIN_SIZE: usize = 0x150;
 
 pub struct Rom {
     bytes: Vec<u8>,
-    pub is_loaded: bool
+    pub is_loaded: bool,
+    mbc: Mbc,
+    // Cartridge (external) RAM, mapped at 0xA000-0xBFFF
+    ram: Vec<u8>,
+    checksum: u32,
 }
 
 impl Rom {
     pub fn new() -> Rom {
         Rom {
             bytes: Vec::new(),
-            is_loaded: false
+            is_loaded: false,
+            mbc: Mbc::None,
+            ram: Vec::new(),
+            checksum: 0,
         }
     }
 
-    pub fn load(&mut self, rom_path: &PathBuf) {
-        let mut file = File::open(rom_path).expect("Invalid ROM path");
-		file.read_to_end(&mut self.bytes).expect("Unable to read ROM");
+    // The loaded ROM is left untouched if the file can't be read or is too small
+    pub fn load(&mut self, rom_path: &PathBuf) -> io::Result<()> {
+        let bytes = fs::read(rom_path)?;
+        if bytes.len() < MIN_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the file is too small to be a ROM",
+            ));
+        }
+
+        self.bytes = bytes;
+        self.mbc = Mbc::from_cartridge_type(self.cartridge_type());
+        self.ram = vec![0; self.ram_banks() * RAM_BANK_SIZE];
+        self.checksum = crc32(&self.bytes);
         println!("Successfully loaded ROM:\n{}", self);
         self.is_loaded = true;
+        Ok(())
     }
 
+    // Reads from 0x0000-0x7FFF, through the MBC
     pub fn read(&self, address: u16) -> u8 {
-        self.bytes[address as usize]
+        if self.bytes.is_empty() {
+            return 0xFF;
+        }
+
+        // Bank numbers past the end of the ROM wrap around
+        self.bytes[self.mbc.rom_offset(address) % self.bytes.len()]
+    }
+
+    // ROM bank mapped at an address in 0x0000-0x7FFF
+    pub fn rom_bank(&self, address: u16) -> usize {
+        if self.bytes.is_empty() {
+            return 0;
+        }
+
+        (self.mbc.rom_offset(address) % self.bytes.len()) / ROM_BANK_SIZE
+    }
+
+    // Writes to 0x0000-0x7FFF set the MBC registers
+    pub fn write(&mut self, address: u16, data: u8) {
+        self.mbc.write_register(address, data);
+    }
+
+    // Reads from 0xA000-0xBFFF
+    pub fn read_ram(&self, address: u16) -> u8 {
+        if let Some(register) = self.mbc.read_rtc() {
+            return register;
+        }
+
+        match self.mbc.ram_offset(address) {
+            Some(offset) if !self.ram.is_empty() => self.ram[offset % self.ram.len()],
+            _ => 0xFF,
+        }
+    }
+
+    // Writes to 0xA000-0xBFFF
+    pub fn write_ram(&mut self, address: u16, data: u8) {
+        if self.mbc.write_rtc(data) {
+            return;
+        }
+
+        if let Some(offset) = self.mbc.ram_offset(address) {
+            if !self.ram.is_empty() {
+                let length = self.ram.len();
+                self.ram[offset % length] = data;
+            }
+        }
+    }
+
+    pub fn rom_bank_count(&self) -> usize {
+        self.bytes.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    pub fn ram_bank_count(&self) -> usize {
+        self.ram.len().div_ceil(RAM_BANK_SIZE)
     }
 
-    pub fn write(&self, address: u16, data: u8) {
-        todo!()
+    // Reads a byte of the ROM image, regardless of the MBC, for debug tools
+    pub fn peek_rom(&self, offset: usize) -> u8 {
+        self.bytes.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    // Patches the ROM image in memory, the file isn't modified
+    pub fn poke_rom(&mut self, offset: usize, data: u8) {
+        if let Some(byte) = self.bytes.get_mut(offset) {
+            *byte = data;
+        }
+    }
+
+    // Reads a byte of cartridge RAM, regardless of the MBC, for debug tools
+    pub fn peek_ram(&self, offset: usize) -> u8 {
+        self.ram.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    pub fn poke_ram(&mut self, offset: usize, data: u8) {
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = data;
+        }
+    }
+
+    // CRC-32 of the whole ROM, identifies the game in save states
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.mbc.save_state(writer);
+        writer.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mbc.load_state(reader)?;
+        reader.read_bytes(&mut self.ram)
+    }
+
+    // Header bytes are read directly, regardless of the banks mapped by the MBC. Without a ROM
+    // they read as 0
+    fn header(&self, address: u16) -> u8 {
+        self.bytes.get(address as usize).copied().unwrap_or(0)
     }
 
     fn title(&self) -> String {
         let mut name = String::new();
         
         for index in 0x134..0x144 {
-            let code = self.read(index);
+            let code = self.header(index);
 
             match code {
                 0 => break,
@@ -44,23 +166,65 @@ impl Rom {
     }
 
     fn cgb_flag(&self) -> u8 {
-        self.read(0x143)
+        self.header(0x143)
+    }
+
+    // Bit 7 of the CGB flag marks games that use CGB features
+    pub fn is_cgb(&self) -> bool {
+        self.cgb_flag() & 0x80 != 0
+    }
+
+    // Sum of all bytes of the title area, used by the CGB boot ROM to pick compatibility palettes
+    pub fn title_checksum(&self) -> u8 {
+        (0x134..=0x143).fold(0u8, |sum, index| sum.wrapping_add(self.header(index)))
+    }
+
+    fn old_licensee_code(&self) -> u8 {
+        self.header(0x14B)
+    }
+
+    fn new_licensee_code(&self) -> [u8; 2] {
+        [self.header(0x144), self.header(0x145)]
+    }
+
+    // Old licensee code 0x33 means the new licensee code should be used instead
+    pub fn has_nintendo_licensee(&self) -> bool {
+        match self.old_licensee_code() {
+            0x01 => true,
+            0x33 => self.new_licensee_code() == *b"01",
+            _ => false,
+        }
     }
 
     fn sgb_flag(&self) -> u8 {
-        self.read(0x146)
+        self.header(0x146)
+    }
+
+    // The SGB only listens to games with the SGB flag and old licensee code 0x33
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag() == 0x03 && self.old_licensee_code() == 0x33
     }
 
     fn cartridge_type(&self) -> u8 {
-        self.read(0x147)
+        self.header(0x147)
     }
     
     fn rom_size(&self) -> u8 {
-        self.read(0x148)
+        self.header(0x148)
     }
 
     fn ram_size(&self) -> u8 {
-        self.read(0x149)
+        self.header(0x149)
+    }
+
+    fn ram_banks(&self) -> usize {
+        match self.ram_size() {
+            0x02 => 1,
+            0x03 => 4,
+            0x04 => 16,
+            0x05 => 8,
+            _ => 0,
+        }
     }
 }
 
@@ -142,4 +306,49 @@ RAM Size: {}
             }
         )
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn write_rom(name: &str, size: usize) -> PathBuf {
+        let path = env::temp_dir().join(format!("gioboycolor-rom-{}-{}.gb", name, process::id()));
+        fs::write(&path, vec![0; size]).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_roms() {
+        let path = write_rom("valid", 0x8000);
+        let mut rom = Rom::new();
+        let result = rom.load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+        assert!(rom.is_loaded);
+        assert_eq!(rom.rom_bank_count(), 2);
+    }
+
+    #[test]
+    fn rejects_missing_and_undersized_files() {
+        let mut rom = Rom::new();
+
+        let missing = env::temp_dir().join("gioboycolor-rom-missing.gb");
+        assert_eq!(
+            rom.load(&missing).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let path = write_rom("small", MIN_SIZE - 1);
+        let result = rom.load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // Nothing was loaded, and the header reads as 0
+        assert!(!rom.is_loaded);
+        assert_eq!(rom.rom_bank_count(), 0);
+        assert!(!rom.is_cgb());
+        assert_eq!(rom.title_checksum(), 0);
+    }
+}
@@ -0,0 +1,148 @@
+//! Binary save state format
+//!
+//! A save state starts with a header, followed by the state of every component in a fixed
+//! order. All values are little endian.
+//!
+//! Offset    Size    Explanation                   \
+//! 0x00      4       Magic ("GBCS")                \
+//! 0x04      2       Format version                \
+//! 0x06      4       CRC-32 of the loaded ROM      \
+//! 0x0A      -       Component states              \
+//!
+//! States with a different version or made with a different ROM are rejected before anything
+//! is modified.
+
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"GBCS";
+// Must be incremented whenever the layout of any component state changes
+pub const VERSION: u16 = 6;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    NoRomLoaded,
+    // The state ended before every component was read
+    Truncated,
+    // The state has the right layout but contains impossible values
+    InvalidData,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidMagic => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported save state version {} (expected {})",
+                version, VERSION
+            ),
+            SaveStateError::RomMismatch => write!(f, "Save state was made with a different ROM"),
+            SaveStateError::NoRomLoaded => write!(f, "No ROM is loaded"),
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::InvalidData => write!(f, "Save state is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { bytes: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.position + length;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(SaveStateError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidData),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Fills the whole buffer
+    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+        buffer.copy_from_slice(self.take(buffer.len())?);
+        Ok(())
+    }
+
+    // Leftover bytes mean the state doesn't match the expected layout
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(SaveStateError::InvalidData)
+        }
+    }
+}
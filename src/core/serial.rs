@@ -0,0 +1,284 @@
+use super::{
+    memory_map::*,
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
+const TRANSFER_START: u8 = 0b1000_0000;
+const FAST_CLOCK: u8 = 0b0000_0010;
+const INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+// 8192 Hz and, on the CGB, 262144 Hz
+const BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+// Whatever is plugged into the link port. The Game Boy exchanges whole bytes with it, the bits
+// are shifted in as the transfer goes on
+pub trait SerialDevice {
+    // The Game Boy started a transfer with its internal clock, sending data. Returns the byte
+    // the device sends back
+    fn transfer(&mut self, data: u8) -> u8;
+
+    // The Game Boy is waiting for the device to clock a transfer, with data in SB. Called every
+    // step while waiting, returns the byte sent by the device once it clocked a whole transfer
+    fn external_clock(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+
+    // T-cycles passed, for devices that keep time
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+// An empty port: the data line is pulled up and nothing ever drives the clock
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _data: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// Serial transfer
+///
+/// Register    Explanation                                               \
+/// SB          Serial transfer data, shifted out MSB first while the     \
+///             received bits are shifted in                              \
+/// SC          Bit 7: transfer enable, bit 1: clock speed (CGB only,     \
+///             0 = 8192 Hz, 1 = 262144 Hz), bit 0: clock select          \
+///             (0 = external clock, 1 = internal clock)                  \
+///
+/// With the internal clock the Game Boy shifts the 8 bits at the selected speed, with the
+/// external clock it waits for the device on the other end, possibly forever. When all 8 bits
+/// have been shifted, bit 7 of SC is cleared and a serial interrupt is requested.
+///
+/// The bytes sent are captured so test ROMs that report their results over serial can be
+/// checked.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html))
+pub struct Serial {
+    data: u8,
+    control: u8,
+    // Byte being shifted in, bits left to shift and T-cycles until the next one
+    incoming: u8,
+    bits_left: u8,
+    bit_cycles: u32,
+    output: Vec<u8>,
+    device: Box<dyn SerialDevice>,
+    pub cgb_mode: bool,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            incoming: 0xFF,
+            bits_left: 0,
+            bit_cycles: 0,
+            output: Vec::new(),
+            device: Box::new(Disconnected),
+            cgb_mode: false,
+        }
+    }
+
+    // Plugs a device into the port, returning the previous one
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) -> Box<dyn SerialDevice> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn disconnect(&mut self) -> Box<dyn SerialDevice> {
+        self.connect(Box::new(Disconnected))
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB => self.data,
+            SC => !self.used_bits() | self.control,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            SB => self.data = data,
+            SC => {
+                self.control = data & self.used_bits();
+                // Writing SC restarts the transfer
+                self.bits_left = 0;
+
+                if self.control & (TRANSFER_START | INTERNAL_CLOCK)
+                    == TRANSFER_START | INTERNAL_CLOCK
+                {
+                    self.output.push(self.data);
+                    self.incoming = self.device.transfer(self.data);
+                    self.bits_left = 8;
+                    self.bit_cycles = self.bit_period();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns true if a transfer completed, which requests a serial interrupt
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        self.device.tick(cycles);
+
+        if self.control & TRANSFER_START == 0 {
+            return false;
+        }
+
+        if self.control & INTERNAL_CLOCK == 0 {
+            let Some(incoming) = self.device.external_clock(self.data) else {
+                return false;
+            };
+            self.output.push(self.data);
+            self.data = incoming;
+            self.control &= !TRANSFER_START;
+            return true;
+        }
+
+        let mut remaining = cycles;
+        while self.bits_left > 0 && remaining >= self.bit_cycles {
+            remaining -= self.bit_cycles;
+            self.bit_cycles = self.bit_period();
+            self.shift();
+        }
+        if self.bits_left > 0 {
+            self.bit_cycles -= remaining;
+            return false;
+        }
+
+        self.control &= !TRANSFER_START;
+        true
+    }
+
+    fn shift(&mut self) {
+        self.data = self.data << 1 | self.incoming >> 7;
+        self.incoming <<= 1;
+        self.bits_left -= 1;
+    }
+
+    fn bit_period(&self) -> u32 {
+        if self.control & FAST_CLOCK != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            BIT_CYCLES
+        }
+    }
+
+    // The clock speed bit only exists on the CGB
+    fn used_bits(&self) -> u8 {
+        if self.cgb_mode {
+            TRANSFER_START | FAST_CLOCK | INTERNAL_CLOCK
+        } else {
+            TRANSFER_START | INTERNAL_CLOCK
+        }
+    }
+
+    // Every byte sent since the ROM was loaded
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_u8(self.control);
+        writer.write_u8(self.incoming);
+        writer.write_u8(self.bits_left);
+        writer.write_u32(self.bit_cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.data = reader.read_u8()?;
+        self.control = reader.read_u8()?;
+        self.incoming = reader.read_u8()?;
+        self.bits_left = reader.read_u8()?.min(8);
+        self.bit_cycles = reader.read_u32()?;
+        Ok(())
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers every transfer with the same byte
+    struct Echo(u8);
+
+    impl SerialDevice for Echo {
+        fn transfer(&mut self, _data: u8) -> u8 {
+            self.0
+        }
+    }
+
+    // Ticks until the transfer completes, returning the T-cycles it took
+    fn transfer_cycles(serial: &mut Serial) -> u32 {
+        let mut cycles = 0;
+        while cycles < 2 * 8 * BIT_CYCLES {
+            cycles += 4;
+            if serial.tick(4) {
+                return cycles;
+            }
+        }
+        panic!("the transfer didn't complete");
+    }
+
+    #[test]
+    fn internal_clock_transfers_take_8_bits() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Echo(0x5A)));
+        serial.write(SB, 0x81);
+        serial.write(SC, TRANSFER_START | INTERNAL_CLOCK);
+
+        assert_eq!(transfer_cycles(&mut serial), 8 * BIT_CYCLES);
+        assert_eq!(serial.read(SB), 0x5A);
+        assert_eq!(serial.read(SC) & TRANSFER_START, 0);
+        assert_eq!(serial.output(), [0x81]);
+        // Nothing left to complete
+        assert!(!serial.tick(8 * BIT_CYCLES));
+    }
+
+    #[test]
+    fn fast_clock_only_on_cgb() {
+        let mut serial = Serial::new();
+        serial.cgb_mode = true;
+        serial.write(SC, TRANSFER_START | FAST_CLOCK | INTERNAL_CLOCK);
+        assert_eq!(transfer_cycles(&mut serial), 8 * FAST_BIT_CYCLES);
+
+        let mut serial = Serial::new();
+        serial.write(SC, TRANSFER_START | FAST_CLOCK | INTERNAL_CLOCK);
+        assert_eq!(transfer_cycles(&mut serial), 8 * BIT_CYCLES);
+    }
+
+    #[test]
+    fn bits_shift_in_msb_first() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Echo(0x00)));
+        serial.write(SB, 0xFF);
+        serial.write(SC, TRANSFER_START | INTERNAL_CLOCK);
+
+        assert!(!serial.tick(3 * BIT_CYCLES));
+        assert_eq!(serial.read(SB), 0xF8);
+        assert!(serial.tick(5 * BIT_CYCLES));
+        assert_eq!(serial.read(SB), 0x00);
+    }
+
+    #[test]
+    fn external_clock_without_peer_never_completes() {
+        let mut serial = Serial::new();
+        serial.write(SB, 0x42);
+        serial.write(SC, TRANSFER_START);
+
+        for _ in 0..1000 {
+            assert!(!serial.tick(8 * BIT_CYCLES));
+        }
+        assert_eq!(serial.read(SB), 0x42);
+        assert_eq!(serial.read(SC) & TRANSFER_START, TRANSFER_START);
+        assert!(serial.output().is_empty());
+    }
+}
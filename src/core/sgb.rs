@@ -0,0 +1,851 @@
+//! Super Game Boy
+//!
+//! SGB enhanced games send commands to the SNES side through P1, in packets of 16 bytes. A
+//! packet starts with both P14 and P15 low, then every bit (LSB first) is a pulse on one of
+//! them, P14 low for 0 and P15 low for 1, each followed by both lines high. The first byte of
+//! a command is its number times 8 plus how many packets it takes.
+//!
+//! Command     Explanation                                                             \
+//! 0x00-0x03   PAL01, PAL23, PAL03, PAL12: set two palettes, and color 0 of all 4      \
+//! 0x04        ATTR_BLK: color the inside, border or outside of rectangles             \
+//! 0x05        ATTR_LIN: color rows or columns                                         \
+//! 0x06        ATTR_DIV: color both sides of a row or column, and the line itself      \
+//! 0x07        ATTR_CHR: color cells one by one                                        \
+//! 0x0A        PAL_SET: copy 4 system palettes, optionally apply an attribute file     \
+//! 0x0B        PAL_TRN: transfer the 512 system palettes                               \
+//! 0x11        MLT_REQ: read the joypads of 1, 2 or 4 players                          \
+//! 0x13        CHR_TRN: transfer 128 of the 256 border tiles                           \
+//! 0x14        PCT_TRN: transfer the border tile map and its palettes                  \
+//! 0x15        ATTR_TRN: transfer the 45 attribute files                               \
+//! 0x16        ATTR_SET: apply an attribute file                                       \
+//! 0x17        MASK_EN: freeze the screen, or blank it to black or color 0             \
+//!
+//! Other commands, for sound or running SNES code, are ignored. The screen is split in 20x18
+//! cells of 8x8 pixels, each using one of the 4 palettes. Transfers (_TRN) send 4 KiB through
+//! the screen: the SGB reads the tiles of the next frame as 2bpp data, 20 per row.
+//!
+//! The output is 256x224 pixels, the game in the middle of the border. The border is made of
+//! 4bpp SNES tiles, color 0 shows the backdrop (color 0 of the palettes) or the game.
+//!
+//! (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/SGB_Functions.html))
+
+use crate::core::{
+    gpu::{rgb555, SCREEN_HEIGHT, SCREEN_WIDTH},
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+// Top left corner of the game inside the border
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+const CELLS: usize = CELLS_X * CELLS_Y;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+const TRANSFER_SIZE: usize = 0x1000;
+
+const SYSTEM_PALETTES: usize = 512;
+const ATTRIBUTE_FILES: usize = 45;
+// 2 bits per cell
+const ATTRIBUTE_FILE_SIZE: usize = CELLS / 4;
+const BORDER_TILES: usize = 256;
+// 8 rows of 4 bitplanes
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_SIZE: usize = BORDER_MAP_WIDTH * BORDER_MAP_WIDTH;
+// The border uses SNES palettes 4 to 7
+const BORDER_PALETTES: usize = 4;
+const BORDER_PALETTE_OFFSET: usize = 0x800;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+const MASK_NONE: u8 = 0;
+const MASK_FREEZE: u8 = 1;
+const MASK_BLACK: u8 = 2;
+const MASK_COLOR_0: u8 = 3;
+
+// Shades of gray until the game sets its palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x5294, 0x294A, 0x0000];
+
+// What the next frame is read as
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Transfer {
+    Palettes,
+    // Starting at tile 0 or 128
+    BorderTiles(usize),
+    BorderMap,
+    AttributeFiles,
+}
+
+pub struct Sgb {
+    // The packet being received over P1
+    receiving: bool,
+    bit_index: usize,
+    pending_bit: Option<bool>,
+    packet: [u8; PACKET_SIZE],
+    // Packets of the command received so far
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    // Palette of every cell
+    attributes: [u8; CELLS],
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; BORDER_PALETTES],
+    players: u8,
+    mask: u8,
+    transfer: Option<Transfer>,
+    screen: Vec<u32>,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        let mut sgb = Sgb {
+            receiving: false,
+            bit_index: 0,
+            pending_bit: None,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; SYSTEM_PALETTES],
+            attributes: [0; CELLS],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_MAP_SIZE],
+            border_palettes: [[0; 16]; BORDER_PALETTES],
+            players: 1,
+            mask: MASK_NONE,
+            transfer: None,
+            screen: vec![0; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT],
+        };
+        sgb.render(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        sgb
+    }
+
+    // The last frame inside the border, as 0RGB pixels row by row
+    pub fn screen(&self) -> &[u32] {
+        &self.screen
+    }
+
+    // Receives packets written to P1, returns the number of players after a MLT_REQ
+    pub fn write_joypad(&mut self, data: u8) -> Option<u8> {
+        match data & 0x30 {
+            // Reset pulse, a packet starts
+            0x00 => {
+                self.receiving = true;
+                self.bit_index = 0;
+                self.pending_bit = None;
+                self.packet = [0; PACKET_SIZE];
+            }
+            0x10 => self.pending_bit = Some(true),
+            0x20 => self.pending_bit = Some(false),
+            // The bit is taken when both lines go back high
+            _ => {
+                let bit = self.pending_bit.take()?;
+                if !self.receiving {
+                    return None;
+                }
+
+                if bit {
+                    self.packet[self.bit_index / 8] |= 1 << (self.bit_index % 8);
+                }
+                self.bit_index += 1;
+
+                // The stop bit that follows is ignored
+                if self.bit_index == PACKET_BITS {
+                    self.receiving = false;
+                    return self.receive_packet();
+                }
+            }
+        }
+
+        None
+    }
+
+    fn receive_packet(&mut self) -> Option<u8> {
+        self.command.extend_from_slice(&self.packet);
+
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() < packets * PACKET_SIZE {
+            return None;
+        }
+
+        let command = std::mem::take(&mut self.command);
+        self.execute(&command)
+    }
+
+    fn execute(&mut self, data: &[u8]) -> Option<u8> {
+        let byte = |index: usize| data.get(index).copied().unwrap_or(0);
+
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attribute_blocks(data),
+            ATTR_LIN => self.attribute_lines(data),
+            ATTR_DIV => self.attribute_division(byte(1), byte(2)),
+            ATTR_CHR => self.attribute_cells(data),
+            PAL_SET => {
+                for palette in 0..4 {
+                    let number = combine!(byte(palette * 2 + 2), byte(palette * 2 + 1));
+                    self.palettes[palette] = self.system_palettes[(number & 0x1FF) as usize];
+                }
+                self.share_color_0(self.palettes[0][0]);
+                if byte(9) & 0x80 != 0 {
+                    self.apply_attribute_file(byte(9) & 0x3F);
+                }
+                if byte(9) & 0x40 != 0 {
+                    self.mask = MASK_NONE;
+                }
+            }
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match byte(1) & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                return Some(self.players);
+            }
+            // Bit 1 selects OBJ tiles for SNES code, which isn't run
+            CHR_TRN if byte(1) & 0x02 == 0 => {
+                let first = if byte(1) & 0x01 != 0 { 128 } else { 0 };
+                self.transfer = Some(Transfer::BorderTiles(first));
+            }
+            PCT_TRN => self.transfer = Some(Transfer::BorderMap),
+            ATTR_TRN => self.transfer = Some(Transfer::AttributeFiles),
+            ATTR_SET => {
+                self.apply_attribute_file(byte(1) & 0x3F);
+                if byte(1) & 0x40 != 0 {
+                    self.mask = MASK_NONE;
+                }
+            }
+            MASK_EN => self.mask = byte(1) & 0x03,
+            _ => (),
+        }
+
+        None
+    }
+
+    // Color 0 followed by colors 1-3 of both palettes
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |index: usize| combine!(data[index * 2 + 2], data[index * 2 + 1]);
+
+        self.share_color_0(color(0));
+        for index in 1..4 {
+            self.palettes[first][index] = color(index);
+            self.palettes[second][index] = color(index + 3);
+        }
+    }
+
+    fn share_color_0(&mut self, color: u16) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color;
+        }
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_X && y < CELLS_Y {
+            self.attributes[y * CELLS_X + x] = palette & 0x03;
+        }
+    }
+
+    // Data sets of 6 bytes: what to color, the palettes, then the corners X1, Y1, X2, Y2
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let sets = data[1] as usize;
+
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            // With only the inside or the outside colored, the border goes with it
+            let border = match control {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                _ if control & 0x02 != 0 => Some((set[1] >> 2) & 0x03),
+                _ => None,
+            };
+            let [x1, y1, x2, y2] = [set[2], set[3], set[4], set[5]].map(|value| value & 0x1F);
+
+            for y in 0..CELLS_Y as u8 {
+                for x in 0..CELLS_X as u8 {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = x == x1 || x == x2 || y == y1 || y == y2;
+
+                    let palette = match (within, on_edge) {
+                        (true, false) if control & 0x01 != 0 => Some(inside),
+                        (true, true) => border,
+                        (false, _) if control & 0x04 != 0 => Some(outside),
+                        _ => None,
+                    };
+                    if let Some(palette) = palette {
+                        self.set_cell(x as usize, y as usize, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    // One byte per line: bit 7 set for a row, bits 6-5 the palette, bits 4-0 the row or column
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let lines = data[1] as usize;
+
+        for &line in data[2..].iter().take(lines) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+
+            if line & 0x80 != 0 {
+                (0..CELLS_X).for_each(|x| self.set_cell(x, index, palette));
+            } else {
+                (0..CELLS_Y).for_each(|y| self.set_cell(index, y, palette));
+            }
+        }
+    }
+
+    // Bit 6 divides along a row instead of a column, bits 5-4 color the line, bits 3-2 what's
+    // above or left of it and bits 1-0 what's below or right of it
+    fn attribute_division(&mut self, control: u8, line: u8) {
+        let after = control & 0x03;
+        let before = (control >> 2) & 0x03;
+        let on_line = (control >> 4) & 0x03;
+        let line = (line & 0x1F) as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if control & 0x40 != 0 { y } else { x };
+                let palette = match position.cmp(&line) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_cell(x, y, palette);
+            }
+        }
+    }
+
+    // Palettes of consecutive cells from X, Y, 4 per byte, going right or down
+    fn attribute_cells(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = combine!(data[4], data[3]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+
+        for index in 0..count.min(CELLS) {
+            let Some(&byte) = data.get(6 + index / 4) else {
+                break;
+            };
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+
+            self.set_cell(x, y, byte >> (6 - (index % 4) * 2));
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+
+        let start = file * ATTRIBUTE_FILE_SIZE;
+        for cell in 0..CELLS {
+            let byte = self.attribute_files[start + cell / 4];
+            self.attributes[cell] = (byte >> (6 - (cell % 4) * 2)) & 0x03;
+        }
+    }
+
+    // Called when a frame is complete, with its shades
+    pub fn end_frame(&mut self, shades: &[u8]) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = transfer_data(shades);
+            let word = |index: usize| combine!(data[index + 1], data[index]);
+
+            match transfer {
+                Transfer::Palettes => {
+                    for (number, palette) in self.system_palettes.iter_mut().enumerate() {
+                        *palette = [0, 1, 2, 3].map(|color| word(number * 8 + color * 2));
+                    }
+                }
+                Transfer::BorderTiles(first) => {
+                    let start = first * BORDER_TILE_SIZE;
+                    self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(&data);
+                }
+                Transfer::BorderMap => {
+                    for (index, entry) in self.border_map.iter_mut().enumerate() {
+                        *entry = word(index * 2);
+                    }
+                    for (number, palette) in self.border_palettes.iter_mut().enumerate() {
+                        for (color, value) in palette.iter_mut().enumerate() {
+                            *value = word(BORDER_PALETTE_OFFSET + number * 32 + color * 2);
+                        }
+                    }
+                }
+                Transfer::AttributeFiles => {
+                    let size = self.attribute_files.len();
+                    self.attribute_files.copy_from_slice(&data[..size]);
+                }
+            }
+        }
+
+        self.render(shades);
+    }
+
+    fn render(&mut self, shades: &[u8]) {
+        let backdrop = rgb555(self.palettes[0][0]);
+
+        if self.mask != MASK_FREEZE {
+            let colors = self.palettes.map(|palette| palette.map(rgb555));
+
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    let color = match self.mask {
+                        MASK_BLACK => 0x000000,
+                        MASK_COLOR_0 => backdrop,
+                        _ => {
+                            let palette = self.attributes[(y / 8) * CELLS_X + x / 8];
+                            colors[palette as usize][shades[y * SCREEN_WIDTH + x] as usize]
+                        }
+                    };
+                    self.screen[(GAME_Y + y) * SGB_SCREEN_WIDTH + GAME_X + x] = color;
+                }
+            }
+        }
+
+        let game_x = GAME_X..GAME_X + SCREEN_WIDTH;
+        let game_y = GAME_Y..GAME_Y + SCREEN_HEIGHT;
+
+        for y in 0..SGB_SCREEN_HEIGHT {
+            for x in 0..SGB_SCREEN_WIDTH {
+                let color_index = self.border_color_index(x, y);
+                let pixel = &mut self.screen[y * SGB_SCREEN_WIDTH + x];
+
+                if color_index.1 != 0 {
+                    *pixel = rgb555(self.border_palettes[color_index.0][color_index.1]);
+                } else if !game_x.contains(&x) || !game_y.contains(&y) {
+                    *pixel = backdrop;
+                }
+            }
+        }
+    }
+
+    // Palette and color index of a pixel of the border
+    fn border_color_index(&self, x: usize, y: usize) -> (usize, usize) {
+        let entry = self.border_map[(y / 8) * BORDER_MAP_WIDTH + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        // Bits 10-12 select SNES palette 4 to 7. Palettes 0 to 3 are used by the game screen,
+        // which isn't emulated on the SNES side, so border tiles using them get palette 4
+        let palette = (((entry >> 10) & 0x07) as usize).saturating_sub(BORDER_PALETTES);
+
+        let mut row = y % 8;
+        let mut column = x % 8;
+        if entry & 0x4000 != 0 {
+            column = 7 - column;
+        }
+        if entry & 0x8000 != 0 {
+            row = 7 - row;
+        }
+
+        // Bitplanes 0 and 1 of every row come first, then bitplanes 2 and 3
+        let bytes = &self.border_tiles[tile * BORDER_TILE_SIZE..];
+        let bit = 7 - column;
+        let plane = |offset: usize| ((bytes[offset] >> bit) & 0x01) as usize;
+
+        let color_index = plane(row * 2)
+            | plane(row * 2 + 1) << 1
+            | plane(16 + row * 2) << 2
+            | plane(16 + row * 2 + 1) << 3;
+
+        (palette, color_index)
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.receiving);
+        writer.write_u8(self.bit_index as u8);
+        writer.write_u8(match self.pending_bit {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        });
+        writer.write_bytes(&self.packet);
+        writer.write_u8((self.command.len() / PACKET_SIZE) as u8);
+        writer.write_bytes(&self.command);
+
+        for palette in self.palettes.iter().chain(&self.system_palettes) {
+            palette.iter().for_each(|&color| writer.write_u16(color));
+        }
+        writer.write_bytes(&self.attributes);
+        writer.write_bytes(&self.attribute_files);
+        writer.write_bytes(&self.border_tiles);
+        self.border_map
+            .iter()
+            .for_each(|&entry| writer.write_u16(entry));
+        for palette in &self.border_palettes {
+            palette.iter().for_each(|&color| writer.write_u16(color));
+        }
+
+        writer.write_u8(self.players);
+        writer.write_u8(self.mask);
+        writer.write_u8(match self.transfer {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::BorderTiles(first)) => 2 + (first / 128) as u8,
+            Some(Transfer::BorderMap) => 4,
+            Some(Transfer::AttributeFiles) => 5,
+        });
+    }
+
+    // The screen isn't stored, it is redrawn at the end of the next frame
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.receiving = reader.read_bool()?;
+        self.bit_index = reader.read_u8()? as usize;
+        self.pending_bit = match reader.read_u8()? {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            _ => return Err(SaveStateError::InvalidData),
+        };
+        reader.read_bytes(&mut self.packet)?;
+        let packets = reader.read_u8()? as usize;
+        if self.bit_index >= PACKET_BITS || packets >= 8 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.command = vec![0; packets * PACKET_SIZE];
+        reader.read_bytes(&mut self.command)?;
+
+        for palette in self.palettes.iter_mut().chain(&mut self.system_palettes) {
+            for color in palette.iter_mut() {
+                *color = reader.read_u16()?;
+            }
+        }
+        reader.read_bytes(&mut self.attributes)?;
+        if self.attributes.iter().any(|&palette| palette > 3) {
+            return Err(SaveStateError::InvalidData);
+        }
+        reader.read_bytes(&mut self.attribute_files)?;
+        reader.read_bytes(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut() {
+            *entry = reader.read_u16()?;
+        }
+        for palette in self.border_palettes.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = reader.read_u16()?;
+            }
+        }
+
+        self.players = reader.read_u8()?;
+        self.mask = reader.read_u8()?;
+        if self.mask > MASK_COLOR_0 {
+            return Err(SaveStateError::InvalidData);
+        }
+        self.transfer = match reader.read_u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::BorderTiles(0)),
+            3 => Some(Transfer::BorderTiles(128)),
+            4 => Some(Transfer::BorderMap),
+            5 => Some(Transfer::AttributeFiles),
+            _ => return Err(SaveStateError::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Sgb {
+        Sgb::new()
+    }
+}
+
+// The 4 KiB a frame carries: its first 256 tiles, 20 per row, back in the 2bpp format
+fn transfer_data(shades: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER_SIZE];
+
+    for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+        let tile_x = (tile % CELLS_X) * 8;
+        let tile_y = (tile / CELLS_X) * 8;
+
+        for row in 0..8 {
+            for column in 0..8 {
+                let shade = shades[(tile_y + row) * SCREEN_WIDTH + tile_x + column];
+                let bit = 7 - column;
+                bytes[row * 2] |= (shade & 0x01) << bit;
+                bytes[row * 2 + 1] |= ((shade >> 1) & 0x01) << bit;
+            }
+        }
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pulses the packets on P1 like a game would, returns what the last one gave back
+    fn send(sgb: &mut Sgb, packets: &[[u8; PACKET_SIZE]]) -> Option<u8> {
+        let mut result = None;
+
+        for packet in packets {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for bit in 0..PACKET_BITS {
+                let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+                sgb.write_joypad(if one { 0x10 } else { 0x20 });
+                result = sgb.write_joypad(0x30).or(result);
+            }
+            // Stop bit
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+
+        result
+    }
+
+    // A command from its number and the bytes after the first, over as many packets as needed
+    fn command(number: u8, parameters: &[u8]) -> Vec<[u8; PACKET_SIZE]> {
+        let packets = parameters.len() / PACKET_SIZE + 1;
+        let mut data = vec![0; packets * PACKET_SIZE];
+        data[0] = number << 3 | packets as u8;
+        data[1..=parameters.len()].copy_from_slice(parameters);
+
+        data.chunks_exact(PACKET_SIZE)
+            .map(|packet| packet.try_into().unwrap())
+            .collect()
+    }
+
+    fn cell(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attributes[y * CELLS_X + x]
+    }
+
+    #[test]
+    fn assembles_packets() {
+        let mut sgb = Sgb::new();
+
+        assert_eq!(send(&mut sgb, &command(MLT_REQ, &[0x01])), Some(2));
+        assert_eq!(send(&mut sgb, &command(MLT_REQ, &[0x03])), Some(4));
+        assert_eq!(send(&mut sgb, &command(MLT_REQ, &[0x00])), Some(1));
+    }
+
+    #[test]
+    fn ignores_bits_outside_of_packets() {
+        let mut sgb = Sgb::new();
+
+        // Pulses without the reset pulse first, then a line going low twice counting once
+        for _ in 0..PACKET_BITS {
+            assert_eq!(sgb.write_joypad(0x10), None);
+            assert_eq!(sgb.write_joypad(0x30), None);
+        }
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        sgb.write_joypad(0x20);
+        sgb.write_joypad(0x20);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.bit_index, 1);
+        // Both lines high with no pulse before
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.bit_index, 1);
+    }
+
+    #[test]
+    fn waits_for_every_packet_of_a_command() {
+        let mut sgb = Sgb::new();
+
+        // ATTR_LIN over 2 packets, coloring rows 0 to 17 with palette 1
+        let lines: Vec<u8> = (0..CELLS_Y as u8).map(|row| 0x80 | 0x20 | row).collect();
+        let packets = command(ATTR_LIN, &[&[lines.len() as u8][..], &lines].concat());
+        assert_eq!(packets.len(), 2);
+
+        send(&mut sgb, &packets[..1]);
+        assert!(sgb.attributes.iter().all(|&palette| palette == 0));
+        assert_eq!(sgb.command.len(), PACKET_SIZE);
+
+        send(&mut sgb, &packets[1..]);
+        assert!(sgb.attributes.iter().all(|&palette| palette == 1));
+        assert!(sgb.command.is_empty());
+    }
+
+    #[test]
+    fn sets_palettes_sharing_color_0() {
+        let mut sgb = Sgb::new();
+
+        let colors: Vec<u8> = (1..=7u16).flat_map(|color| color.to_le_bytes()).collect();
+        send(&mut sgb, &command(PAL12, &colors));
+
+        assert_eq!(sgb.palettes[1], [1, 2, 3, 4]);
+        assert_eq!(sgb.palettes[2], [1, 5, 6, 7]);
+        assert_eq!(sgb.palettes[0], [1, 0x5294, 0x294A, 0x0000]);
+        assert_eq!(sgb.palettes[3][0], 1);
+    }
+
+    #[test]
+    fn decodes_attribute_blocks() {
+        let mut sgb = Sgb::new();
+
+        // Inside 1, border 2, outside 3, from (2, 2) to (5, 4)
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, &[1, 0x07, 0b11_10_01, 2, 2, 5, 4]),
+        );
+        assert_eq!(cell(&sgb, 3, 3), 1);
+        assert_eq!(cell(&sgb, 4, 3), 1);
+        for (x, y) in [(2, 2), (5, 4), (3, 2), (2, 3), (5, 3)] {
+            assert_eq!(cell(&sgb, x, y), 2, "({}, {})", x, y);
+        }
+        for (x, y) in [(0, 0), (1, 2), (6, 4), (3, 5), (19, 17)] {
+            assert_eq!(cell(&sgb, x, y), 3, "({}, {})", x, y);
+        }
+
+        // Only the inside, the border goes with it and the outside is left alone
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, &[1, 0x01, 0b00_11_00, 0, 0, 2, 2]),
+        );
+        assert_eq!(cell(&sgb, 0, 0), 0);
+        assert_eq!(cell(&sgb, 1, 1), 0);
+        assert_eq!(cell(&sgb, 2, 2), 0);
+        assert_eq!(cell(&sgb, 3, 3), 1);
+
+        // Only the outside, with the border
+        let mut sgb = Sgb::new();
+        send(
+            &mut sgb,
+            &command(ATTR_BLK, &[1, 0x04, 0b10_00_00, 1, 1, 3, 3]),
+        );
+        assert_eq!(cell(&sgb, 0, 0), 2);
+        assert_eq!(cell(&sgb, 1, 1), 2);
+        assert_eq!(cell(&sgb, 2, 2), 0);
+    }
+
+    #[test]
+    fn decodes_attribute_lines() {
+        let mut sgb = Sgb::new();
+
+        // Column 4 with palette 2, then row 6 with palette 3 over it
+        send(
+            &mut sgb,
+            &command(ATTR_LIN, &[2, 0x40 | 4, 0x80 | 0x60 | 6]),
+        );
+        assert_eq!(cell(&sgb, 4, 0), 2);
+        assert_eq!(cell(&sgb, 4, 17), 2);
+        assert_eq!(cell(&sgb, 4, 6), 3);
+        assert_eq!(cell(&sgb, 0, 6), 3);
+        assert_eq!(cell(&sgb, 19, 6), 3);
+        assert_eq!(cell(&sgb, 5, 0), 0);
+    }
+
+    #[test]
+    fn decodes_attribute_divisions() {
+        let mut sgb = Sgb::new();
+
+        // Divided along column 10: 1 left of it, 2 on it and 3 right of it
+        send(&mut sgb, &command(ATTR_DIV, &[0b10_01_11, 10]));
+        assert_eq!(cell(&sgb, 9, 0), 1);
+        assert_eq!(cell(&sgb, 10, 17), 2);
+        assert_eq!(cell(&sgb, 11, 5), 3);
+
+        // Along row 0, nothing above it
+        send(&mut sgb, &command(ATTR_DIV, &[0x40 | 0b01_00_10, 0]));
+        assert_eq!(cell(&sgb, 9, 0), 1);
+        assert_eq!(cell(&sgb, 19, 1), 2);
+    }
+
+    #[test]
+    fn decodes_attribute_cells() {
+        let mut sgb = Sgb::new();
+
+        // Going right from (18, 0), wrapping to the next row
+        send(
+            &mut sgb,
+            &command(ATTR_CHR, &[18, 0, 5, 0, 0, 0b11_10_01_11, 0b10 << 6]),
+        );
+        assert_eq!(cell(&sgb, 18, 0), 3);
+        assert_eq!(cell(&sgb, 19, 0), 2);
+        assert_eq!(cell(&sgb, 0, 1), 1);
+        assert_eq!(cell(&sgb, 1, 1), 3);
+        assert_eq!(cell(&sgb, 2, 1), 2);
+        assert_eq!(cell(&sgb, 3, 1), 0);
+
+        // Going down from (5, 16), wrapping to the next column
+        send(
+            &mut sgb,
+            &command(ATTR_CHR, &[5, 16, 3, 0, 1, 0b01_10_11 << 2]),
+        );
+        assert_eq!(cell(&sgb, 5, 16), 1);
+        assert_eq!(cell(&sgb, 5, 17), 2);
+        assert_eq!(cell(&sgb, 6, 0), 3);
+        assert_eq!(cell(&sgb, 6, 1), 0);
+    }
+
+    #[test]
+    fn applies_attribute_files() {
+        let mut sgb = Sgb::new();
+
+        // File 2 colors every cell with palette 2
+        let start = 2 * ATTRIBUTE_FILE_SIZE;
+        sgb.attribute_files[start..start + ATTRIBUTE_FILE_SIZE].fill(0b10_10_10_10);
+        sgb.mask = MASK_BLACK;
+
+        send(&mut sgb, &command(ATTR_SET, &[0x40 | 2]));
+        assert!(sgb.attributes.iter().all(|&palette| palette == 2));
+        assert_eq!(sgb.mask, MASK_NONE);
+
+        // Past the last file
+        send(&mut sgb, &command(ATTR_SET, &[ATTRIBUTE_FILES as u8]));
+        assert!(sgb.attributes.iter().all(|&palette| palette == 2));
+    }
+
+    #[test]
+    fn border_tiles_use_palettes_4_to_7() {
+        let mut sgb = Sgb::new();
+        // Tile 0 is all color 1
+        for row in 0..8 {
+            sgb.border_tiles[row * 2] = 0xFF;
+        }
+
+        for (snes_palette, palette) in [(4, 0), (5, 1), (6, 2), (7, 3), (0, 0), (3, 0)] {
+            // Flipped both ways, which doesn't change the palette
+            sgb.border_map[0] = 0xC000 | snes_palette << 10;
+            assert_eq!(
+                sgb.border_color_index(0, 0),
+                (palette, 1),
+                "{}",
+                snes_palette
+            );
+        }
+    }
+}
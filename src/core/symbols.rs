@@ -0,0 +1,240 @@
+//! Symbol files
+//!
+//! Labels produced by the assembler, used to name addresses in the disassembly, trace logs,
+//! breakpoints and call stacks. A file named like the ROM with the .sym extension is loaded
+//! alongside it. Both the RGBDS and the no$gmb formats are one label per line:
+//!
+//! Line                   Explanation                                            \
+//! ; comment              Ignored, as is anything after a ; on other lines       \
+//! 00:0150 Main           RGBDS, bank and address in hex                         \
+//! 0001:4000 Level.load   no$gmb, banks may have up to 4 digits                  \
+//! 0000:0150 .code:0010   no$gmb region annotations starting with . are skipped  \
+//!
+//! The bank is the ROM bank for 0x4000-0x7FFF, and the WRAM, VRAM or cart RAM bank elsewhere.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use crate::core::{
+    disassembler::Labels,
+    memory_map::{ROM_BANK_END, ROM_BANK_START},
+};
+
+#[derive(Clone)]
+pub struct SymbolTable {
+    labels: BTreeMap<(usize, u16), String>,
+    // First label of every address regardless of bank, for regions the bank isn't known in
+    by_address: HashMap<u16, String>,
+    by_name: HashMap<String, (usize, u16)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            labels: BTreeMap::new(),
+            by_address: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    // Lines that aren't symbols are skipped
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (Some(location), Some(name)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let Some((bank, address)) = location.split_once(':') else {
+                continue;
+            };
+            let (Ok(bank), Ok(address)) = (
+                usize::from_str_radix(bank, 16),
+                u16::from_str_radix(address, 16),
+            ) else {
+                continue;
+            };
+
+            symbols.insert(bank, address, name);
+        }
+
+        symbols
+    }
+
+    pub fn load(path: &Path) -> io::Result<SymbolTable> {
+        Ok(SymbolTable::parse(&fs::read_to_string(path)?))
+    }
+
+    // The symbol file next to a ROM, e.g. game.sym, if there is one
+    pub fn for_rom(rom_path: &Path) -> Option<SymbolTable> {
+        let path = rom_path.with_extension("sym");
+        if !path.exists() {
+            return None;
+        }
+
+        match SymbolTable::load(&path) {
+            Ok(symbols) => {
+                println!("Loaded {} symbols from {}", symbols.len(), path.display());
+                Some(symbols)
+            }
+            Err(error) => {
+                println!("Unable to load symbols from {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, bank: usize, address: u16, name: &str) {
+        self.labels.insert((bank, address), name.to_string());
+        self.by_address
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), (bank, address));
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // Bank and address of a label
+    pub fn address(&self, name: &str) -> Option<(usize, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    // The closest label at or before an address, with the offset from it, e.g. Main+$0C
+    pub fn describe(&self, bank: usize, address: u16) -> Option<String> {
+        if let Some(label) = self.label(bank, address) {
+            return Some(label.to_string());
+        }
+
+        // Only ROM banks are known when looking up, so offsets are limited to them
+        let (bank, region_start) = match address {
+            0..ROM_BANK_START => (0, 0),
+            ROM_BANK_START..=ROM_BANK_END => (bank, ROM_BANK_START),
+            _ => return None,
+        };
+
+        self.labels
+            .range((bank, region_start)..=(bank, address))
+            .next_back()
+            .map(|((_, start), label)| format!("{}+${:02X}", label, address - start))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+impl Labels for SymbolTable {
+    fn label(&self, bank: usize, address: u16) -> Option<&str> {
+        match address {
+            ROM_BANK_START..=ROM_BANK_END => self.labels.get(&(bank, address)),
+            _ => self
+                .labels
+                .get(&(0, address))
+                .or_else(|| self.by_address.get(&address)),
+        }
+        .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGBDS: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:0200 Main.loop ; local label
+01:4000 LoadLevel
+02:4000 DrawSprites
+00:c000 wBuffer
+01:d000 wBank1Data
+";
+
+    const NO_GMB: &str = "\
+;no$gmb format .sym file
+0000:0150 Main
+0000:0100 .code:0050
+0010:4000 BankSixteen
+0001:a000 sSave
+";
+
+    #[test]
+    fn parses_rgbds() {
+        let symbols = SymbolTable::parse(RGBDS);
+
+        assert_eq!(symbols.len(), 6);
+        assert_eq!(symbols.address("Main"), Some((0, 0x0150)));
+        assert_eq!(symbols.address("Main.loop"), Some((0, 0x0200)));
+        assert_eq!(symbols.address("DrawSprites"), Some((2, 0x4000)));
+        assert_eq!(symbols.address("wBank1Data"), Some((1, 0xD000)));
+        assert_eq!(symbols.address("missing"), None);
+    }
+
+    #[test]
+    fn parses_no_gmb() {
+        let symbols = SymbolTable::parse(NO_GMB);
+
+        // The region annotation is skipped
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.address("Main"), Some((0, 0x0150)));
+        assert_eq!(symbols.address("BankSixteen"), Some((0x10, 0x4000)));
+        assert_eq!(symbols.address("sSave"), Some((1, 0xA000)));
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let symbols =
+            SymbolTable::parse("0150 NoBank\nzz:0150 BadBank\n00:10000 TooFar\n00:0150\n");
+        assert!(symbols.is_empty());
+    }
+
+    #[test]
+    fn labels_depend_on_the_rom_bank() {
+        let symbols = SymbolTable::parse(RGBDS);
+
+        assert_eq!(symbols.label(1, 0x4000), Some("LoadLevel"));
+        assert_eq!(symbols.label(2, 0x4000), Some("DrawSprites"));
+        assert_eq!(symbols.label(3, 0x4000), None);
+        // Outside of the switchable ROM bank, any bank matches
+        assert_eq!(symbols.label(5, 0xC000), Some("wBuffer"));
+        assert_eq!(symbols.label(0, 0xD000), Some("wBank1Data"));
+    }
+
+    #[test]
+    fn describe() {
+        let symbols = SymbolTable::parse(RGBDS);
+
+        assert_eq!(symbols.describe(1, 0x0150).as_deref(), Some("Main"));
+        assert_eq!(symbols.describe(1, 0x015C).as_deref(), Some("Main+$0C"));
+        assert_eq!(
+            symbols.describe(1, 0x0210).as_deref(),
+            Some("Main.loop+$10")
+        );
+        assert_eq!(
+            symbols.describe(2, 0x4123).as_deref(),
+            Some("DrawSprites+$123")
+        );
+        // Nothing before the address in bank 0 or in the mapped bank
+        assert_eq!(symbols.describe(1, 0x0100), None);
+        assert_eq!(symbols.describe(3, 0x4010), None);
+        // Offsets aren't computed outside of ROM
+        assert_eq!(symbols.describe(0, 0xC000).as_deref(), Some("wBuffer"));
+        assert_eq!(symbols.describe(0, 0xC001), None);
+    }
+}
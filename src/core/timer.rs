@@ -0,0 +1,118 @@
+use super::{
+    memory_map::*,
+    save_state::{SaveStateError, StateReader, StateWriter},
+};
+
+/// Timer
+///
+/// Register    Explanation                                                   \
+/// DIV         Upper 8 bits of the internal 16-bit counter, reset on write   \
+/// TIMA        Incremented at the rate selected by TAC                       \
+/// TMA         Loaded into TIMA when it overflows                            \
+/// TAC         Bit 2: enable, bits 1-0: clock select (4096, 262144, 65536 or \
+///             16384 Hz)                                                     \
+///
+/// TIMA is incremented on the falling edge of a bit of the internal counter, so resetting DIV
+/// or changing TAC can cause an extra increment. When TIMA overflows a timer interrupt is
+/// requested.
+///
+/// (Docs sourced from [Pan Docs](https://gbdev.io/pandocs/Timer_and_Divider_Registers.html))
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV => high!(self.counter),
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns true if the write caused TIMA to overflow
+    pub fn write(&mut self, address: u16, data: u8) -> bool {
+        let old_signal = self.signal();
+
+        match address {
+            DIV => self.counter = 0,
+            TIMA => self.tima = data,
+            TMA => self.tma = data,
+            TAC => self.tac = data & 0x07,
+            _ => unreachable!(),
+        }
+
+        old_signal && !self.signal() && self.increment_tima()
+    }
+
+    // Advances the timer by the given amount of T-cycles, returns true if TIMA overflowed
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut overflowed = false;
+
+        // The selected bits are never below bit 3, so stepping a machine cycle at a time
+        // doesn't miss any edge
+        for _ in 0..cycles / 4 {
+            let old_signal = self.signal();
+            self.counter = self.counter.wrapping_add(4);
+
+            if old_signal && !self.signal() {
+                overflowed |= self.increment_tima();
+            }
+        }
+
+        overflowed
+    }
+
+    // The counter bit selected by TAC, ANDed with the enable bit
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+
+        self.tac & 0x04 != 0 && (self.counter >> bit) & 1 != 0
+    }
+
+    fn increment_tima(&mut self) -> bool {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = if overflowed { self.tma } else { tima };
+        overflowed
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+        writer.write_u8(self.tima);
+        writer.write_u8(self.tma);
+        writer.write_u8(self.tac);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = reader.read_u16()?;
+        self.tima = reader.read_u8()?;
+        self.tma = reader.read_u8()?;
+        self.tac = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
@@ -0,0 +1,156 @@
+//! Instruction trace logging
+//!
+//! One line is written before every executed instruction, with the state the instruction
+//! starts from. Steps that dispatch an interrupt or idle in HALT aren't logged.
+//!
+//! Format    Example                                                                          \
+//! Doctor    A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01        \
+//! Rich      [         4] 00:0101  C3 50 01  JP $0150         A:01 F:Z-HC BC:0013 DE:00D8 ...    \
+//!
+//! Doctor is the format of [Gameboy Doctor](https://github.com/robert/gameboy-doctor), so logs
+//! can be diffed against reference ones. Rich adds the T-cycles elapsed since tracing started,
+//! the ROM bank, the disassembled instruction and readable flags. When symbols are loaded, Rich
+//! names the operands after them and writes a `Label:` line before labelled instructions.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::core::{
+    disassembler::{disassemble, Labels},
+    gbc::GioBoyColor,
+    memory_map::{ROM_BANK_END, ROM_BANK_START},
+    symbols::SymbolTable,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceFormat {
+    Doctor,
+    Rich,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "doctor" => Some(TraceFormat::Doctor),
+            "rich" => Some(TraceFormat::Rich),
+            _ => None,
+        }
+    }
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    output: Box<dyn Write>,
+    // T-cycles elapsed since tracing started
+    cycles: u64,
+    symbols: SymbolTable,
+}
+
+impl Tracer {
+    pub fn new(format: TraceFormat, output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            format,
+            output,
+            cycles: 0,
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    pub fn to_file(format: TraceFormat, path: &Path) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(format, Box::new(BufWriter::new(file))))
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    // Writes the line of the instruction at PC, before it's executed
+    pub fn log(&mut self, gbc: &GioBoyColor) -> io::Result<()> {
+        let registers = &gbc.cpu.registers;
+        let pc = registers.pc;
+        let memory = [0, 1, 2, 3].map(|offset| gbc.peek(pc.wrapping_add(offset)));
+
+        match self.format {
+            TraceFormat::Doctor => writeln!(
+                self.output,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+                 SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                registers.a,
+                registers.f,
+                registers.b,
+                registers.c,
+                registers.d,
+                registers.e,
+                registers.h,
+                registers.l,
+                registers.sp,
+                pc,
+                memory[0],
+                memory[1],
+                memory[2],
+                memory[3]
+            ),
+            TraceFormat::Rich => {
+                let bank = match pc {
+                    0..=ROM_BANK_END => format!("{:02X}", gbc.rom.rom_bank(pc)),
+                    _ => "--".to_string(),
+                };
+                let mapped_bank = gbc.rom.rom_bank(ROM_BANK_START);
+                let instruction = disassemble(&memory, pc, mapped_bank, &self.symbols);
+                let bytes: Vec<String> = memory[..instruction.length]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                let flags: String = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+                    .iter()
+                    .map(|(bit, name)| {
+                        if registers.f & (1 << bit) != 0 {
+                            *name
+                        } else {
+                            '-'
+                        }
+                    })
+                    .collect();
+
+                if let Some(label) = self.symbols.label(mapped_bank, pc) {
+                    writeln!(self.output, "{}:", label)?;
+                }
+
+                writeln!(
+                    self.output,
+                    "[{:>10}] {}:{:04X}  {:<8}  {:<15}  A:{:02X} F:{} BC:{:04X} DE:{:04X} \
+                     HL:{:04X} SP:{:04X} IME:{}",
+                    self.cycles,
+                    bank,
+                    pc,
+                    bytes.join(" "),
+                    instruction.text,
+                    registers.a,
+                    flags,
+                    registers.bc(),
+                    registers.de(),
+                    registers.hl(),
+                    registers.sp,
+                    gbc.cpu.ime as u8
+                )
+            }
+        }
+    }
+
+    // Machine cycles taken by a step
+    pub fn add_cycles(&mut self, cycles: usize) {
+        self.cycles += cycles as u64 * 4;
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
@@ -0,0 +1,468 @@
+//! Debugger console
+//!
+//! Commands are read from the terminal the emulator was started from, so the window keeps
+//! updating while paused. Numbers are hex, with an optional `$` or `0x` prefix. Addresses can
+//! also be labels from the symbol file loaded with the ROM, labels take precedence over hex.
+//!
+//! Command                           Explanation                                        \
+//! c, continue                       Resume execution                                   \
+//! p, pause                          Pause execution                                    \
+//! s, step                           Execute one instruction                            \
+//! n, next                           Execute one instruction, running over calls        \
+//! f, finish                         Run until the current function returns             \
+//! v, vblank                         Run until the next VBlank                          \
+//! b [bank:]addr [if reg op value]   Break at addr or label, op is == != < <= > >=      \
+//! w r|w|x|rw|rwx start[-end]        Watch reads, writes and/or execution of a range    \
+//! d b|w index                       Delete a breakpoint or a watchpoint                \
+//! l, list                           List breakpoints and watchpoints                   \
+//! r, regs                           Show the registers                                 \
+//! stack [n]                         Show n words on the stack and the call stack       \
+//! x addr [n]                        Dump n bytes of memory                             \
+//! dis [addr] [n]                    Disassemble n instructions                         \
+//! cheat [add code [name]]           List cheats, or add a GameShark or Game Genie code \
+//! cheat on|off|rm index             Enable, disable or remove a cheat                  \
+//! cheat save                        Write the cheats to the cheat file of the ROM      \
+//! help                              List the commands                                  \
+
+use std::{
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::core::{
+    debugger::{Breakpoint, Comparison, Condition, Debugger, Register, StopReason, Watchpoint},
+    disassembler::{disassemble, Labels},
+    gbc::GioBoyColor,
+};
+
+const HELP: &str = "\
+c, continue                       Resume execution
+p, pause                          Pause execution
+s, step                           Execute one instruction
+n, next                           Execute one instruction, running over calls
+f, finish                         Run until the current function returns
+v, vblank                         Run until the next VBlank
+b [bank:]addr [if reg op value]   Break at addr or label, op is == != < <= > >=
+w r|w|x|rw|rwx start[-end]        Watch reads, writes and/or execution of a range
+d b|w index                       Delete a breakpoint or a watchpoint
+l, list                           List breakpoints and watchpoints
+r, regs                           Show the registers
+stack [n]                         Show n words on the stack and the call stack
+x addr [n]                        Dump n bytes of memory
+dis [addr] [n]                    Disassemble n instructions
+cheat [add code [name]]           List cheats, or add a GameShark or Game Genie code
+cheat on|off|rm index             Enable, disable or remove a cheat
+cheat save                        Write the cheats to the cheat file of the ROM";
+
+const DEFAULT_STACK_DEPTH: usize = 8;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+const DEFAULT_DISASSEMBLY_LENGTH: usize = 10;
+
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let (sender, lines) = mpsc::channel();
+
+        // Reading stdin blocks, so it's done on its own thread
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("Debugger console, type help for the list of commands");
+        prompt();
+
+        Console { lines }
+    }
+
+    // Lines typed since the last call
+    pub fn poll(&self) -> Vec<String> {
+        self.lines.try_iter().collect()
+    }
+}
+
+impl Default for Console {
+    fn default() -> Console {
+        Console::new()
+    }
+}
+
+pub fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+pub fn report_stop(reason: StopReason, debugger: &Debugger, gbc: &GioBoyColor) {
+    println!();
+    println!("{}", reason);
+    print_disassembly(debugger, gbc, gbc.cpu.registers.pc, 1);
+    prompt();
+}
+
+pub fn execute(line: &str, debugger: &mut Debugger, gbc: &mut GioBoyColor) {
+    if let Err(error) = run_command(line, debugger, gbc) {
+        println!("{}", error);
+    }
+}
+
+fn run_command(line: &str, debugger: &mut Debugger, gbc: &mut GioBoyColor) -> Result<(), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((command, args)) = words.split_first() else {
+        return Ok(());
+    };
+
+    match *command {
+        "c" | "continue" => debugger.resume(),
+        "p" | "pause" => {
+            debugger.pause();
+            print_disassembly(debugger, gbc, gbc.cpu.registers.pc, 1);
+        }
+        "s" | "step" => {
+            debugger.step_into(gbc);
+            print_disassembly(debugger, gbc, gbc.cpu.registers.pc, 1);
+        }
+        "n" | "next" => {
+            if debugger.step_over(gbc).is_some() {
+                print_disassembly(debugger, gbc, gbc.cpu.registers.pc, 1);
+            }
+        }
+        "f" | "finish" => debugger.step_out(gbc),
+        "v" | "vblank" => debugger.run_to_vblank(),
+        "b" | "break" => {
+            let breakpoint = parse_breakpoint(args, debugger)?;
+            let index = debugger.add_breakpoint(breakpoint);
+            println!(
+                "Breakpoint {}: {}",
+                index,
+                describe_breakpoint(&breakpoint, debugger)
+            );
+        }
+        "w" | "watch" => {
+            let watchpoint = parse_watchpoint(args, debugger)?;
+            let index = debugger.add_watchpoint(watchpoint);
+            println!("Watchpoint {}: {}", index, describe_watchpoint(&watchpoint));
+        }
+        "d" | "delete" => {
+            let (kind, index) = match args {
+                [kind, index] => (*kind, parse_index(index)?),
+                _ => return Err("Usage: d b|w index".to_string()),
+            };
+            let removed = match kind {
+                "b" => debugger.remove_breakpoint(index),
+                "w" => debugger.remove_watchpoint(index),
+                _ => return Err("Usage: d b|w index".to_string()),
+            };
+            if !removed {
+                return Err(format!("No such index: {}", index));
+            }
+        }
+        "l" | "list" => {
+            for (index, breakpoint) in debugger.breakpoints().iter().enumerate() {
+                println!(
+                    "Breakpoint {}: {}",
+                    index,
+                    describe_breakpoint(breakpoint, debugger)
+                );
+            }
+            for (index, watchpoint) in debugger.watchpoints().iter().enumerate() {
+                println!("Watchpoint {}: {}", index, describe_watchpoint(watchpoint));
+            }
+        }
+        "r" | "regs" => print_registers(gbc),
+        "stack" => {
+            let depth = match args.first() {
+                Some(depth) => parse_index(depth)?,
+                None => DEFAULT_STACK_DEPTH,
+            };
+            for (address, value) in debugger.stack(gbc, depth) {
+                println!("{:04X}: {:04X}", address, value);
+            }
+            println!("Call stack:");
+            for frame in debugger.call_stack().iter().rev() {
+                let kind = if frame.interrupt { "interrupt" } else { "call" };
+                let symbols = debugger.symbols();
+                let name = |address| match symbols.describe(frame.bank, address) {
+                    Some(label) => format!("{:04X} <{}>", address, label),
+                    None => format!("{:04X}", address),
+                };
+                println!(
+                    "  {} from {} ({}), returns to {:04X}",
+                    name(frame.target),
+                    name(frame.caller),
+                    kind,
+                    frame.return_address
+                );
+            }
+        }
+        "x" => {
+            let (address, length) = match args {
+                [address] => (parse_address(address, debugger)?, DEFAULT_DUMP_LENGTH),
+                [address, length] => (
+                    parse_address(address, debugger)?,
+                    parse_hex(length)? as usize,
+                ),
+                _ => return Err("Usage: x addr [n]".to_string()),
+            };
+            print_memory(gbc, address, length);
+        }
+        "dis" => {
+            let address = match args.first() {
+                Some(address) => parse_address(address, debugger)?,
+                None => gbc.cpu.registers.pc,
+            };
+            let count = match args.get(1) {
+                Some(count) => parse_index(count)?,
+                None => DEFAULT_DISASSEMBLY_LENGTH,
+            };
+            print_disassembly(debugger, gbc, address, count);
+        }
+        "cheat" => run_cheat_command(args, gbc)?,
+        "help" => println!("{}", HELP),
+        _ => return Err(format!("Unknown command: {}, type help", command)),
+    }
+
+    Ok(())
+}
+
+fn run_cheat_command(args: &[&str], gbc: &mut GioBoyColor) -> Result<(), String> {
+    let cheats = &mut gbc.cheats;
+
+    match args {
+        [] => {
+            for (index, cheat) in cheats.cheats().iter().enumerate() {
+                let state = if cheat.enabled { "on" } else { "off" };
+                println!("Cheat {}: {} {} {}", index, cheat.text, state, cheat.name);
+            }
+        }
+        ["add", code, name @ ..] => {
+            let index = cheats.add(code, &name.join(" "))?;
+            println!("Cheat {}: {}", index, code.to_uppercase());
+        }
+        [action @ ("on" | "off"), index] => {
+            let index = parse_index(index)?;
+            if !cheats.set_enabled(index, *action == "on") {
+                return Err(format!("No such index: {}", index));
+            }
+        }
+        ["rm", index] => {
+            let index = parse_index(index)?;
+            if !cheats.remove(index) {
+                return Err(format!("No such index: {}", index));
+            }
+        }
+        ["save"] => {
+            cheats
+                .save()
+                .map_err(|error| format!("Unable to save cheats: {}", error))?;
+            if let Some(path) = cheats.path() {
+                println!("Saved cheats to {}", path.display());
+            }
+        }
+        _ => return Err("Usage: cheat [add code [name] | on|off|rm index | save]".to_string()),
+    }
+
+    Ok(())
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex value: {}", text))
+}
+
+// A label, or a hex address
+fn parse_address(text: &str, debugger: &Debugger) -> Result<u16, String> {
+    match debugger.symbols().address(text) {
+        Some((_, address)) => Ok(address),
+        None => parse_hex(text),
+    }
+}
+
+fn parse_index(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("Invalid number: {}", text))
+}
+
+// [bank:]addr [if reg op value], addr can be a label
+fn parse_breakpoint(args: &[&str], debugger: &Debugger) -> Result<Breakpoint, String> {
+    let (location, condition) = match args {
+        [location] => (*location, None),
+        [location, "if", register, comparison, value] => {
+            let condition = Condition {
+                register: Register::from_name(register)
+                    .ok_or(format!("Unknown register: {}", register))?,
+                comparison: Comparison::from_symbol(comparison)
+                    .ok_or(format!("Unknown comparison: {}", comparison))?,
+                value: parse_hex(value)?,
+            };
+            (*location, Some(condition))
+        }
+        _ => return Err("Usage: b [bank:]addr [if reg op value]".to_string()),
+    };
+
+    let (bank, address) = match (
+        debugger.symbols().address(location),
+        location.split_once(':'),
+    ) {
+        // Only the switchable ROM bank is checked by breakpoints
+        (Some((bank, address @ 0x4000..=0x7FFF)), _) => (Some(bank), address),
+        (Some((_, address)), _) => (None, address),
+        (None, Some((bank, address))) => (Some(parse_hex(bank)? as usize), parse_hex(address)?),
+        (None, None) => (None, parse_hex(location)?),
+    };
+
+    Ok(Breakpoint {
+        bank,
+        address,
+        condition,
+    })
+}
+
+// r|w|x|rw|rwx start[-end], start and end can be labels
+fn parse_watchpoint(args: &[&str], debugger: &Debugger) -> Result<Watchpoint, String> {
+    let [access, range] = args else {
+        return Err("Usage: w r|w|x|rw|rwx start[-end]".to_string());
+    };
+
+    if access.is_empty() || !access.chars().all(|access| "rwx".contains(access)) {
+        return Err(format!(
+            "Invalid access: {}, expected r, w and/or x",
+            access
+        ));
+    }
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (
+            parse_address(start, debugger)?,
+            parse_address(end, debugger)?,
+        ),
+        None => (
+            parse_address(range, debugger)?,
+            parse_address(range, debugger)?,
+        ),
+    };
+
+    if start > end {
+        return Err(format!("Invalid range: {}", range));
+    }
+
+    Ok(Watchpoint {
+        start,
+        end,
+        read: access.contains('r'),
+        write: access.contains('w'),
+        execute: access.contains('x'),
+    })
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint, debugger: &Debugger) -> String {
+    let mut description = match breakpoint.bank {
+        Some(bank) => format!("{:02X}:{:04X}", bank, breakpoint.address),
+        None => format!("{:04X}", breakpoint.address),
+    };
+
+    let label = debugger
+        .symbols()
+        .label(breakpoint.bank.unwrap_or(0), breakpoint.address);
+    if let Some(label) = label {
+        description += &format!(" <{}>", label);
+    }
+
+    if let Some(condition) = &breakpoint.condition {
+        description += &format!(
+            " if {:?} {} {:X}",
+            condition.register,
+            condition.comparison.symbol(),
+            condition.value
+        );
+    }
+
+    description
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let access: String = [
+        (watchpoint.read, 'r'),
+        (watchpoint.write, 'w'),
+        (watchpoint.execute, 'x'),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| *name)
+    .collect();
+
+    format!("{} {:04X}-{:04X}", access, watchpoint.start, watchpoint.end)
+}
+
+fn print_registers(gbc: &GioBoyColor) {
+    let registers = &gbc.cpu.registers;
+    let flags: String = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+        .iter()
+        .map(|(bit, name)| {
+            if registers.f & (1 << bit) != 0 {
+                *name
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    println!(
+        "A:{:02X} F:{} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X} IME:{} HALT:{}",
+        registers.a,
+        flags,
+        registers.bc(),
+        registers.de(),
+        registers.hl(),
+        registers.sp,
+        registers.pc,
+        gbc.cpu.ime as u8,
+        gbc.cpu.halted as u8
+    );
+}
+
+fn print_memory(gbc: &GioBoyColor, address: u16, length: usize) {
+    for row in (0..length).step_by(16) {
+        let start = address.wrapping_add(row as u16);
+        let bytes: Vec<String> = (0..16.min(length - row))
+            .map(|offset| format!("{:02X}", gbc.peek(start.wrapping_add(offset as u16))))
+            .collect();
+        println!("{:04X}: {}", start, bytes.join(" "));
+    }
+}
+
+fn print_disassembly(debugger: &Debugger, gbc: &GioBoyColor, mut address: u16, count: usize) {
+    let symbols = debugger.symbols();
+    // Labels in 0x4000-0x7FFF belong to the bank currently mapped there
+    let bank = gbc.rom.rom_bank(0x4000);
+
+    for _ in 0..count {
+        let bytes = [0, 1, 2].map(|offset| gbc.peek(address.wrapping_add(offset)));
+        let instruction = disassemble(&bytes, address, bank, symbols);
+
+        if let Some(label) = symbols.label(bank, address) {
+            println!("{}:", label);
+        }
+        let marker = if address == gbc.cpu.registers.pc {
+            '>'
+        } else {
+            ' '
+        };
+
+        println!("{} {:04X}  {}", marker, address, instruction.text);
+        address = address.wrapping_add(instruction.length as u16);
+    }
+}
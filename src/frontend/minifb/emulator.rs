@@ -1,38 +1,77 @@
 use std::ffi::OsStr;
+use std::fs;
+use std::net::Ipv4Addr;
 use std::path::PathBuf;
 
-use minifb::MENU_KEY_CTRL;
-use minifb::{InputCallback, Key, Menu, Window, WindowOptions};
+use minifb::{Key, Menu, Window, WindowOptions};
+use minifb::{MENU_KEY_CTRL, MENU_KEY_SHIFT};
 
 use rfd::FileDialog;
 
+use crate::core::cheats::Cheats;
+use crate::core::compat_palettes::BootCombo;
+use crate::core::debugger::Debugger;
 use crate::core::gbc::GioBoyColor;
+use crate::core::gdb::{GdbServer, DEFAULT_PORT};
+use crate::core::infrared::ScriptedPeer;
+use crate::core::link::{self, LinkCable, LinkListener};
+use crate::core::printer::Printer;
+use crate::core::rewind::{RewindBuffer, DEFAULT_REWIND_SECONDS};
+use crate::core::symbols::SymbolTable;
+use crate::core::trace::{TraceFormat, Tracer};
 
-const WIDTH: usize = 160;
-const HEIGHT: usize = 144;
+use super::console::{self, Console};
+use super::input::KeyBindings;
+use super::memory_viewer::MemoryViewer;
+use super::vram_viewer::VramViewer;
 
 const FILE_OPEN_MENU_ID: usize = 1;
 const FILE_CLOSE_MENU_ID: usize = 2;
+// One menu item per BootCombo, offset by its index
+const PALETTE_MENU_ID_START: usize = 100;
+// One menu item per save state slot, offset by the slot number
+const SAVE_STATE_MENU_ID_START: usize = 200;
+const LOAD_STATE_MENU_ID_START: usize = 300;
+const TRACE_DOCTOR_MENU_ID: usize = 400;
+const TRACE_RICH_MENU_ID: usize = 401;
+const CONSOLE_MENU_ID: usize = 402;
+const PAUSE_MENU_ID: usize = 403;
+const GDB_MENU_ID: usize = 404;
+const VRAM_VIEWER_MENU_ID: usize = 405;
+const MEMORY_VIEWER_MENU_ID: usize = 406;
+const LINK_HOST_MENU_ID: usize = 500;
+const LINK_JOIN_MENU_ID: usize = 501;
+const LINK_DISCONNECT_MENU_ID: usize = 502;
+const PRINTER_MENU_ID: usize = 503;
+const IR_SCRIPT_MENU_ID: usize = 504;
 
-struct KeyCharCallback;
-
-impl InputCallback for KeyCharCallback {
-    fn add_char(&mut self, c: u32) {
-        println!("add_char {}", c);
-    }
-}
+const SAVE_STATE_SLOTS: usize = 4;
+const SLOT_KEYS: [Key; SAVE_STATE_SLOTS] = [Key::F1, Key::F2, Key::F3, Key::F4];
 
 pub struct Emulator {
     window: Window,
     gbc: GioBoyColor,
+    key_bindings: KeyBindings,
+    rom_path: Option<PathBuf>,
+    rewind: RewindBuffer,
+    debugger: Debugger,
+    // Opened from the Debug menu, execution then goes through the debugger
+    console: Option<Console>,
+    // Started from the Debug menu, execution then goes through the debugger too
+    gdb: Option<GdbServer>,
+    vram_viewer: Option<VramViewer>,
+    memory_viewer: Option<MemoryViewer>,
+    // Waiting for the other end of the link cable, from the Link menu
+    link_listener: Option<LinkListener>,
 }
 
 impl Emulator {
-    pub fn new() -> Emulator {
+    // The window and its menus, made again when the screen size changes
+    fn open_window(width: usize, height: usize) -> Window {
         let mut window = Window::new(
             "GioBoyColor",
-            WIDTH,
-            HEIGHT,
+            width,
+            height,
             WindowOptions {
                 resize: false,
                 scale: minifb::Scale::X2,
@@ -41,7 +80,8 @@ impl Emulator {
         )
         .expect("Unable to Open Window");
 
-        window.set_input_callback(Box::new(KeyCharCallback {}));
+        // Limit to max ~60 fps update rate
+        window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
         let mut file_menu: Menu = Menu::new("File").unwrap();
 
@@ -52,39 +92,207 @@ impl Emulator {
 
         file_menu.add_item("Close ROM", FILE_CLOSE_MENU_ID).build();
 
+        file_menu.add_separator();
+
+        for (slot, key) in SLOT_KEYS.iter().enumerate() {
+            file_menu
+                .add_item(
+                    &format!("Save State {}", slot + 1),
+                    SAVE_STATE_MENU_ID_START + slot,
+                )
+                .shortcut(*key, MENU_KEY_SHIFT)
+                .build();
+        }
+
+        for (slot, key) in SLOT_KEYS.iter().enumerate() {
+            file_menu
+                .add_item(
+                    &format!("Load State {}", slot + 1),
+                    LOAD_STATE_MENU_ID_START + slot,
+                )
+                .shortcut(*key, 0)
+                .build();
+        }
+
         window.add_menu(&file_menu);
 
+        let mut palette_menu: Menu = Menu::new("Palette").unwrap();
+
+        for (index, combo) in BootCombo::ALL.iter().enumerate() {
+            palette_menu
+                .add_item(combo.name(), PALETTE_MENU_ID_START + index)
+                .build();
+        }
+
+        window.add_menu(&palette_menu);
+
+        let mut debug_menu: Menu = Menu::new("Debug").unwrap();
+
+        debug_menu
+            .add_item("Toggle Trace (Gameboy Doctor)", TRACE_DOCTOR_MENU_ID)
+            .shortcut(Key::T, MENU_KEY_CTRL)
+            .build();
+
+        debug_menu
+            .add_item("Toggle Trace (Rich)", TRACE_RICH_MENU_ID)
+            .shortcut(Key::T, MENU_KEY_CTRL | MENU_KEY_SHIFT)
+            .build();
+
+        debug_menu.add_separator();
+
+        debug_menu
+            .add_item("Open Console", CONSOLE_MENU_ID)
+            .shortcut(Key::D, MENU_KEY_CTRL)
+            .build();
+
+        debug_menu
+            .add_item("Pause/Resume", PAUSE_MENU_ID)
+            .shortcut(Key::P, MENU_KEY_CTRL)
+            .build();
+
+        debug_menu.add_item("Start GDB Server", GDB_MENU_ID).build();
+
+        debug_menu.add_separator();
+
+        debug_menu
+            .add_item("VRAM Viewer", VRAM_VIEWER_MENU_ID)
+            .shortcut(Key::V, MENU_KEY_CTRL)
+            .build();
+
+        debug_menu
+            .add_item("Memory Viewer", MEMORY_VIEWER_MENU_ID)
+            .shortcut(Key::M, MENU_KEY_CTRL)
+            .build();
+
+        window.add_menu(&debug_menu);
+
+        let mut link_menu: Menu = Menu::new("Link").unwrap();
+
+        link_menu
+            .add_item("Host Link Cable", LINK_HOST_MENU_ID)
+            .build();
+
+        link_menu
+            .add_item("Join Link Cable", LINK_JOIN_MENU_ID)
+            .build();
+
+        link_menu
+            .add_item("Connect Printer...", PRINTER_MENU_ID)
+            .build();
+
+        link_menu
+            .add_item("Load IR Script...", IR_SCRIPT_MENU_ID)
+            .build();
+
+        link_menu
+            .add_item("Disconnect", LINK_DISCONNECT_MENU_ID)
+            .build();
+
+        window.add_menu(&link_menu);
+
+        window
+    }
+    pub fn new() -> Emulator {
         let gbc = GioBoyColor::new();
+        let (width, height) = gbc.screen_size();
 
-        return Emulator { window, gbc };
+        return Emulator {
+            window: Emulator::open_window(width, height),
+            gbc,
+            key_bindings: KeyBindings::new(),
+            rom_path: None,
+            rewind: RewindBuffer::new(DEFAULT_REWIND_SECONDS),
+            debugger: Debugger::new(),
+            console: None,
+            gdb: None,
+            vram_viewer: None,
+            memory_viewer: None,
+            link_listener: None,
+        };
     }
     pub fn run(&mut self) {
-        // TODO: Get buffer from GBC Core
-        let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-
-        // Limit to max ~60 fps update rate
-        self.window
-            .limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+        let mut buffer: Vec<u32> = Vec::new();
 
         while self.window.is_open() {
+            self.gbc
+                .set_buttons(self.key_bindings.buttons(&self.window));
             self.handle_menus();
 
-            if self.gbc.rom.is_loaded {
-                // TODO: Update buffer from GBC Core
-                for i in buffer.iter_mut() {
-                    *i = 0; // write something more funny here!
+            if let Some(console) = &self.console {
+                for line in console.poll() {
+                    console::execute(&line, &mut self.debugger, &mut self.gbc);
+                    console::prompt();
                 }
+            }
+
+            if let Some(gdb) = &mut self.gdb {
+                gdb.poll(&mut self.debugger, &mut self.gbc);
+            }
+
+            self.poll_link();
 
-                let cycles = self.gbc.step();
-                println!("Cycles executed: {cycles}");
+            let (width, height) = self.gbc.screen_size();
+            buffer.resize(width * height, 0);
+
+            if self.gbc.rom.is_loaded {
+                if self.console.is_some() || self.gdb.is_some() {
+                    if let Some(reason) = self.debugger.run_frame(&mut self.gbc) {
+                        if self.console.is_some() {
+                            console::report_stop(reason, &self.debugger, &self.gbc);
+                        }
+                        if let Some(gdb) = &mut self.gdb {
+                            gdb.report_stop(reason, &mut self.debugger);
+                        }
+                    }
+                    buffer.copy_from_slice(self.gbc.screen());
+                } else if self.key_bindings.rewind_held(&self.window) {
+                    // The frame buffer isn't part of the snapshots, so a frame is run to redraw it
+                    if self.rewind.rewind_frame(&mut self.gbc) {
+                        self.gbc.run_frame();
+                        buffer.copy_from_slice(self.gbc.screen());
+                    }
+                } else {
+                    self.gbc.run_frame();
+                    self.rewind.record_frame(&self.gbc);
+                    buffer.copy_from_slice(self.gbc.screen());
+                }
             }
 
             // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
             self.window
-                .update_with_buffer(&buffer, WIDTH, HEIGHT)
+                .update_with_buffer(&buffer, width, height)
                 .unwrap();
+
+            if self
+                .vram_viewer
+                .as_ref()
+                .is_some_and(|viewer| !viewer.is_open())
+            {
+                self.vram_viewer = None;
+            }
+            if let Some(viewer) = &mut self.vram_viewer {
+                viewer.update(&self.gbc.gpu);
+            }
+
+            if self
+                .memory_viewer
+                .as_ref()
+                .is_some_and(|viewer| !viewer.is_open())
+            {
+                self.memory_viewer = None;
+            }
+            if let Some(viewer) = &mut self.memory_viewer {
+                viewer.update(&mut self.gbc);
+            }
         }
     }
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+    // Seconds of gameplay that can be rewound
+    pub fn set_rewind_seconds(&mut self, seconds: u32) {
+        self.rewind.set_seconds(seconds);
+    }
     fn handle_menus(&mut self) {
         if let Some(menu_id) = self.window.is_menu_pressed() {
             match menu_id {
@@ -101,17 +309,142 @@ impl Emulator {
                     }
                 }
                 FILE_CLOSE_MENU_ID => self.unload_rom(),
+                id if (PALETTE_MENU_ID_START..PALETTE_MENU_ID_START + BootCombo::ALL.len())
+                    .contains(&id) =>
+                {
+                    self.gbc
+                        .set_fallback_palettes(BootCombo::ALL[id - PALETTE_MENU_ID_START]);
+                }
+                id if (SAVE_STATE_MENU_ID_START..SAVE_STATE_MENU_ID_START + SAVE_STATE_SLOTS)
+                    .contains(&id) =>
+                {
+                    self.save_state(id - SAVE_STATE_MENU_ID_START);
+                }
+                id if (LOAD_STATE_MENU_ID_START..LOAD_STATE_MENU_ID_START + SAVE_STATE_SLOTS)
+                    .contains(&id) =>
+                {
+                    self.load_state(id - LOAD_STATE_MENU_ID_START);
+                }
+                TRACE_DOCTOR_MENU_ID => self.toggle_trace(TraceFormat::Doctor),
+                TRACE_RICH_MENU_ID => self.toggle_trace(TraceFormat::Rich),
+                CONSOLE_MENU_ID if self.console.is_none() => {
+                    self.console = Some(Console::new());
+                }
+                GDB_MENU_ID if self.gdb.is_none() => match GdbServer::bind(DEFAULT_PORT) {
+                    Ok(gdb) => {
+                        println!("GDB server listening on localhost:{}", gdb.port());
+                        self.gdb = Some(gdb);
+                    }
+                    Err(error) => println!("Unable to start the GDB server: {}", error),
+                },
+                VRAM_VIEWER_MENU_ID if self.vram_viewer.is_none() => {
+                    self.vram_viewer = Some(VramViewer::new());
+                }
+                MEMORY_VIEWER_MENU_ID if self.memory_viewer.is_none() => {
+                    self.memory_viewer = Some(MemoryViewer::new());
+                }
+                LINK_HOST_MENU_ID if self.link_listener.is_none() => {
+                    match LinkListener::bind(link::DEFAULT_PORT) {
+                        Ok(listener) => {
+                            println!(
+                                "Waiting for the link cable on localhost:{}",
+                                link::DEFAULT_PORT
+                            );
+                            self.link_listener = Some(listener);
+                        }
+                        Err(error) => println!("Unable to host the link cable: {}", error),
+                    }
+                }
+                LINK_JOIN_MENU_ID => {
+                    match LinkCable::connect((Ipv4Addr::LOCALHOST, link::DEFAULT_PORT)) {
+                        Ok(cable) => {
+                            println!("Link cable connected");
+                            self.gbc.serial.connect(Box::new(cable));
+                        }
+                        Err(error) => println!("Unable to join the link cable: {}", error),
+                    }
+                }
+                PRINTER_MENU_ID => {
+                    // Printouts are saved to the chosen folder
+                    if let Some(directory) = FileDialog::new().pick_folder() {
+                        self.link_listener = None;
+                        self.gbc.serial.connect(Box::new(Printer::new(&directory)));
+                        println!("Printer connected, printing to {}", directory.display());
+                    }
+                }
+                IR_SCRIPT_MENU_ID => {
+                    let file = FileDialog::new()
+                        .add_filter("IR Script", &["txt"])
+                        .pick_file();
+
+                    if let Some(path) = file {
+                        match ScriptedPeer::load(&path) {
+                            Ok(peer) => {
+                                self.gbc.infrared.connect(Box::new(peer));
+                                println!("IR script loaded from {}", path.display());
+                            }
+                            Err(error) => println!("Unable to load {}: {}", path.display(), error),
+                        }
+                    }
+                }
+                LINK_DISCONNECT_MENU_ID => {
+                    self.link_listener = None;
+                    self.gbc.serial.disconnect();
+                    self.gbc.infrared.disconnect();
+                }
+                PAUSE_MENU_ID => {
+                    if self.debugger.is_paused() {
+                        self.debugger.resume();
+                    } else {
+                        self.debugger.pause();
+                    }
+                }
                 _ => (),
             }
         }
     }
+    fn poll_link(&mut self) {
+        let Some(listener) = &self.link_listener else {
+            return;
+        };
+
+        match listener.accept() {
+            Ok(Some(cable)) => {
+                println!("Link cable connected");
+                self.gbc.serial.connect(Box::new(cable));
+                self.link_listener = None;
+            }
+            Ok(None) => (),
+            Err(error) => {
+                println!("Unable to accept the link cable: {}", error);
+                self.link_listener = None;
+            }
+        }
+    }
     fn load_rom(&mut self, rom_path: &PathBuf) {
         // Update window title
         let filename = rom_path.file_name().and_then(OsStr::to_str);
 
         match filename {
             Some(filename_str) => {
-                self.gbc.load_rom(rom_path);
+                let size = self.gbc.screen_size();
+                if let Err(error) = self.gbc.load_rom(rom_path) {
+                    println!("Unable to load {}: {}", rom_path.display(), error);
+                    return;
+                }
+                // SGB games are shown inside their border
+                if self.gbc.screen_size() != size {
+                    let (width, height) = self.gbc.screen_size();
+                    self.window = Emulator::open_window(width, height);
+                }
+                self.rom_path = Some(rom_path.clone());
+                self.rewind.clear();
+                self.debugger.reset();
+                // Symbols are loaded from game.sym next to game.gbc
+                self.debugger
+                    .set_symbols(SymbolTable::for_rom(rom_path).unwrap_or_default());
+                // And cheats from game.cht
+                self.gbc.cheats = Cheats::for_rom(rom_path);
                 let window_title = format!("GioBoyColor - {}", &filename_str);
                 self.window.set_title(&window_title);
             }
@@ -119,6 +452,83 @@ impl Emulator {
         }
     }
     fn unload_rom(&mut self) {
+        self.rom_path = None;
+        self.rewind.clear();
+        self.debugger.reset();
+        self.debugger.set_symbols(SymbolTable::new());
+        self.gbc.unload_rom();
         self.window.set_title("GioBoyColor");
     }
+    // Slots are stored next to the ROM, e.g. game.ss1
+    fn state_path(&self, slot: usize) -> Option<PathBuf> {
+        self.rom_path
+            .as_ref()
+            .map(|rom_path| rom_path.with_extension(format!("ss{}", slot + 1)))
+    }
+    fn save_state(&mut self, slot: usize) {
+        let Some(path) = self.state_path(slot) else {
+            println!("No ROM loaded, nothing to save");
+            return;
+        };
+
+        match fs::write(&path, self.gbc.save_state()) {
+            Ok(()) => println!("Saved state to {}", path.display()),
+            Err(error) => println!("Unable to save state to {}: {}", path.display(), error),
+        }
+    }
+    fn load_state(&mut self, slot: usize) {
+        let Some(path) = self.state_path(slot) else {
+            println!("No ROM loaded, nothing to load");
+            return;
+        };
+
+        let result = fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|state| {
+                self.gbc
+                    .load_state(&state)
+                    .map_err(|error| error.to_string())
+            });
+
+        match result {
+            Ok(()) => {
+                self.debugger.reset();
+                println!("Loaded state from {}", path.display());
+            }
+            Err(error) => println!("Unable to load state from {}: {}", path.display(), error),
+        }
+    }
+
+    // Traces are written next to the ROM, e.g. game.log
+    fn toggle_trace(&mut self, format: TraceFormat) {
+        if self.gbc.tracer().is_some() {
+            self.gbc.set_tracer(None);
+            println!("Stopped tracing");
+            return;
+        }
+
+        let Some(path) = self
+            .rom_path
+            .as_ref()
+            .map(|rom_path| rom_path.with_extension("log"))
+        else {
+            println!("No ROM loaded, nothing to trace");
+            return;
+        };
+
+        match Tracer::to_file(format, &path) {
+            Ok(mut tracer) => {
+                tracer.set_symbols(self.debugger.symbols().clone());
+                self.gbc.set_tracer(Some(tracer));
+                println!("Tracing to {}", path.display());
+            }
+            Err(error) => println!("Unable to trace to {}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Emulator {
+        Emulator::new()
+    }
 }
@@ -0,0 +1,64 @@
+//! Hex digits for the debug windows, minifb has no text rendering of its own
+//!
+//! Digits are 3x5 pixels, drawn at twice the size.
+
+pub const GLYPH_SCALE: usize = 2;
+// Including a column of spacing
+pub const CHAR_WIDTH: usize = 4 * GLYPH_SCALE;
+pub const CHAR_HEIGHT: usize = 5 * GLYPH_SCALE;
+
+const GLYPHS: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b111, 0b100, 0b111],
+    [0b111, 0b100, 0b111, 0b100, 0b100],
+];
+
+// Draws the lowest digits of a value, most significant first, into a buffer rows of width
+// pixels wide. Pixels outside the buffer are skipped
+pub fn draw_hex(
+    buffer: &mut [u32],
+    width: usize,
+    value: u32,
+    digits: usize,
+    x: usize,
+    y: usize,
+    color: u32,
+) {
+    for index in 0..digits {
+        let digit = (value >> ((digits - 1 - index) * 4)) & 0x0F;
+        let glyph_x = x + index * CHAR_WIDTH;
+
+        for (row, bits) in GLYPHS[digit as usize].iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        let pixel_x = glyph_x + column * GLYPH_SCALE + dx;
+                        let pixel_y = y + row * GLYPH_SCALE + dy;
+                        if pixel_x < width {
+                            if let Some(pixel) = buffer.get_mut(pixel_y * width + pixel_x) {
+                                *pixel = color;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use minifb::{Key, Window};
+
+use crate::core::joypad::Buttons;
+
+// Keyboard keys mapped to each joypad button and emulator hotkey
+pub struct KeyBindings {
+    pub right: Key,
+    pub left: Key,
+    pub up: Key,
+    pub down: Key,
+    pub a: Key,
+    pub b: Key,
+    pub select: Key,
+    pub start: Key,
+    // Plays the game backwards while held
+    pub rewind: Key,
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        KeyBindings {
+            right: Key::Right,
+            left: Key::Left,
+            up: Key::Up,
+            down: Key::Down,
+            a: Key::X,
+            b: Key::Z,
+            select: Key::Backspace,
+            start: Key::Enter,
+            rewind: Key::R,
+        }
+    }
+
+    // Reads the state of the mapped keys from the window
+    pub fn buttons(&self, window: &Window) -> Buttons {
+        Buttons {
+            right: window.is_key_down(self.right),
+            left: window.is_key_down(self.left),
+            up: window.is_key_down(self.up),
+            down: window.is_key_down(self.down),
+            a: window.is_key_down(self.a),
+            b: window.is_key_down(self.b),
+            select: window.is_key_down(self.select),
+            start: window.is_key_down(self.start),
+        }
+    }
+
+    pub fn rewind_held(&self, window: &Window) -> bool {
+        window.is_key_down(self.rewind)
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings::new()
+    }
+}
@@ -0,0 +1,330 @@
+//! Memory viewer
+//!
+//! A hex dump of the address space or of a single bank, refreshed every frame. Bytes that
+//! changed recently are highlighted, fading out over a second. Reads never disturb emulation,
+//! see GioBoyColor::peek. The region shown is in the window title.
+//!
+//! Key                    Explanation                                                  \
+//! Arrows                 Move the cursor                                              \
+//! Page Up, Page Down     Move the cursor by a page                                    \
+//! Home, End              Move the cursor to the start or the end of the region        \
+//! Tab, Shift+Tab         Show the next or the previous region                         \
+//! 0-9, A-F               Type a new value for the byte under the cursor, two digits   \
+//! G                      Go to an address, typed as four digits                       \
+//! Escape                 Cancel typing                                                \
+//!
+//! Values are written with GioBoyColor::poke_region, so the address space goes through the
+//! memory map while banks are written directly.
+
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+use super::font::{self, CHAR_HEIGHT, CHAR_WIDTH};
+use crate::core::gbc::{GioBoyColor, Region};
+
+const BYTES_PER_ROW: usize = 16;
+const ROWS: usize = 32;
+const PAGE: usize = BYTES_PER_ROW * ROWS;
+
+const MARGIN: usize = 8;
+const LINE_HEIGHT: usize = CHAR_HEIGHT + 4;
+// Address, then the bytes, with an extra space in the middle of the row
+const BYTES_X: usize = MARGIN + 6 * CHAR_WIDTH;
+const BYTE_WIDTH: usize = 3 * CHAR_WIDTH;
+
+const WIDTH: usize = BYTES_X + BYTES_PER_ROW * BYTE_WIDTH + CHAR_WIDTH + MARGIN;
+const HEIGHT: usize = MARGIN * 2 + ROWS * LINE_HEIGHT;
+
+const BACKGROUND: u32 = 0x202020;
+const ADDRESS_COLOR: u32 = 0x808080;
+const TEXT_COLOR: u32 = 0xE0E0E0;
+const CURSOR_COLOR: u32 = 0x3050A0;
+const CHANGED_COLOR: u32 = 0xFF4040;
+// Frames a changed byte stays highlighted for
+const HIGHLIGHT_FRAMES: u8 = 60;
+
+const HEX_KEYS: [Key; 16] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+];
+
+// What typed digits are for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Input {
+    None,
+    // The high nibble of the new value
+    Value(u8),
+    // Digits of the address typed so far, and how many
+    GoTo(u16, usize),
+}
+
+pub struct MemoryViewer {
+    window: Window,
+    buffer: Vec<u32>,
+    region: Region,
+    // Offsets in the region
+    top: usize,
+    cursor: usize,
+    input: Input,
+    // Contents in the previous frame and frames since each byte changed
+    previous: Vec<u8>,
+    ages: Vec<u8>,
+}
+
+impl MemoryViewer {
+    pub fn new() -> MemoryViewer {
+        let mut window = Window::new(
+            &title(Region::AddressSpace, Input::None),
+            WIDTH,
+            HEIGHT,
+            WindowOptions::default(),
+        )
+        .expect("Unable to Open Window");
+
+        // The main window already limits the frame rate
+        window.limit_update_rate(None);
+
+        MemoryViewer {
+            window,
+            buffer: vec![BACKGROUND; WIDTH * HEIGHT],
+            region: Region::AddressSpace,
+            top: 0,
+            cursor: 0,
+            input: Input::None,
+            previous: Vec::new(),
+            ages: Vec::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn update(&mut self, gbc: &mut GioBoyColor) {
+        // The region may be gone after loading another ROM
+        if !gbc.regions().contains(&self.region) {
+            self.show_region(Region::AddressSpace);
+        }
+
+        self.handle_keys(gbc);
+
+        let size = gbc.region_size(self.region);
+        let contents: Vec<u8> = (0..size)
+            .map(|offset| gbc.peek_region(self.region, offset))
+            .collect();
+
+        if self.previous.len() == size {
+            for (offset, age) in self.ages.iter_mut().enumerate() {
+                *age = if contents[offset] != self.previous[offset] {
+                    0
+                } else {
+                    age.saturating_add(1).min(HIGHLIGHT_FRAMES)
+                };
+            }
+        } else {
+            self.ages = vec![HIGHLIGHT_FRAMES; size];
+        }
+
+        self.draw(&contents);
+        self.previous = contents;
+
+        // We unwrap here as we want this code to exit if it fails, like the main window
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .unwrap();
+    }
+
+    fn handle_keys(&mut self, gbc: &mut GioBoyColor) {
+        let size = gbc.region_size(self.region);
+        let shift =
+            self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+
+        for key in self.window.get_keys_pressed(KeyRepeat::Yes) {
+            if let Some(digit) = HEX_KEYS.iter().position(|&hex_key| hex_key == key) {
+                self.type_digit(digit as u8, gbc);
+                continue;
+            }
+
+            match key {
+                Key::Left => self.move_cursor(-1, size),
+                Key::Right => self.move_cursor(1, size),
+                Key::Up => self.move_cursor(-(BYTES_PER_ROW as isize), size),
+                Key::Down => self.move_cursor(BYTES_PER_ROW as isize, size),
+                Key::PageUp => self.move_cursor(-(PAGE as isize), size),
+                Key::PageDown => self.move_cursor(PAGE as isize, size),
+                Key::Home => self.move_cursor(-(size as isize), size),
+                Key::End => self.move_cursor(size as isize, size),
+                Key::Tab => {
+                    let regions = gbc.regions();
+                    let index = regions
+                        .iter()
+                        .position(|&region| region == self.region)
+                        .unwrap_or(0);
+                    let next = if shift {
+                        (index + regions.len() - 1) % regions.len()
+                    } else {
+                        (index + 1) % regions.len()
+                    };
+                    self.show_region(regions[next]);
+                }
+                Key::G => self.set_input(Input::GoTo(0, 0)),
+                Key::Escape => self.set_input(Input::None),
+                _ => (),
+            }
+        }
+    }
+
+    fn type_digit(&mut self, digit: u8, gbc: &mut GioBoyColor) {
+        match self.input {
+            Input::None => self.set_input(Input::Value(digit)),
+            Input::Value(high) => {
+                gbc.poke_region(self.region, self.cursor, high << 4 | digit);
+                self.set_input(Input::None);
+                self.move_cursor(1, gbc.region_size(self.region));
+            }
+            Input::GoTo(address, 3) => {
+                let address = address << 4 | digit as u16;
+                self.set_input(Input::None);
+                self.go_to(address, gbc.region_size(self.region));
+            }
+            Input::GoTo(address, digits) => {
+                self.set_input(Input::GoTo(address << 4 | digit as u16, digits + 1))
+            }
+        }
+    }
+
+    // Addresses outside of a bank are ignored
+    fn go_to(&mut self, address: u16, size: usize) {
+        let Some(offset) = (address as usize).checked_sub(self.region.base_address() as usize)
+        else {
+            return;
+        };
+
+        if offset < size {
+            self.cursor = offset;
+            self.scroll_to_cursor();
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize, size: usize) {
+        self.cursor = self.cursor.saturating_add_signed(delta).min(size - 1);
+        self.scroll_to_cursor();
+
+        // Moving away abandons a half typed value
+        if matches!(self.input, Input::Value(_)) {
+            self.set_input(Input::None);
+        }
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        let row = self.cursor / BYTES_PER_ROW * BYTES_PER_ROW;
+
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + PAGE {
+            self.top = row + BYTES_PER_ROW - PAGE;
+        }
+    }
+
+    fn show_region(&mut self, region: Region) {
+        self.region = region;
+        self.top = 0;
+        self.cursor = 0;
+        self.previous.clear();
+        self.set_input(Input::None);
+    }
+
+    fn set_input(&mut self, input: Input) {
+        self.input = input;
+        self.window.set_title(&title(self.region, input));
+    }
+
+    fn draw(&mut self, contents: &[u8]) {
+        self.buffer.fill(BACKGROUND);
+
+        let base = self.region.base_address() as u32;
+        let rows = (self.top..contents.len()).step_by(BYTES_PER_ROW).take(ROWS);
+
+        for (line, row) in rows.enumerate() {
+            let y = MARGIN + line * LINE_HEIGHT;
+            self.text(base + row as u32, 4, MARGIN, y, ADDRESS_COLOR);
+
+            for column in 0..BYTES_PER_ROW.min(contents.len() - row) {
+                let offset = row + column;
+                // A gap between the two halves of the row
+                let x = BYTES_X + column * BYTE_WIDTH + (column / 8) * CHAR_WIDTH;
+
+                let mut value = contents[offset] as u32;
+                let mut digits = 2;
+                if offset == self.cursor {
+                    self.fill(x - 2, y - 2, 2 * CHAR_WIDTH + 2, LINE_HEIGHT, CURSOR_COLOR);
+                    // Only the typed digit is shown until the value is complete
+                    if let Input::Value(high) = self.input {
+                        value = high as u32;
+                        digits = 1;
+                    }
+                }
+
+                let color = highlight(self.ages[offset]);
+                self.text(value, digits, x, y, color);
+            }
+        }
+    }
+
+    fn text(&mut self, value: u32, digits: usize, x: usize, y: usize, color: u32) {
+        font::draw_hex(&mut self.buffer, WIDTH, value, digits, x, y, color);
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..(y + height).min(HEIGHT) {
+            for column in x..(x + width).min(WIDTH) {
+                self.buffer[row * WIDTH + column] = color;
+            }
+        }
+    }
+}
+
+impl Default for MemoryViewer {
+    fn default() -> MemoryViewer {
+        MemoryViewer::new()
+    }
+}
+
+fn title(region: Region, input: Input) -> String {
+    match input {
+        Input::GoTo(_, 0) => format!("GioBoyColor - Memory - {} - Go to", region.name()),
+        Input::GoTo(address, digits) => format!(
+            "GioBoyColor - Memory - {} - Go to {:0width$X}",
+            region.name(),
+            address,
+            width = digits
+        ),
+        _ => format!("GioBoyColor - Memory - {}", region.name()),
+    }
+}
+
+// Fades from the changed color to the text color as the byte ages
+fn highlight(age: u8) -> u32 {
+    let mix = |shift: u32| {
+        let from = (CHANGED_COLOR >> shift) & 0xFF;
+        let to = (TEXT_COLOR >> shift) & 0xFF;
+        let value =
+            (from * (HIGHLIGHT_FRAMES - age) as u32 + to * age as u32) / HIGHLIGHT_FRAMES as u32;
+        value << shift
+    };
+
+    mix(16) | mix(8) | mix(0)
+}
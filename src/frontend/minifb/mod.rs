@@ -1 +1,6 @@
-pub mod emulator;
\ No newline at end of file
+pub mod console;
+pub mod emulator;
+pub mod font;
+pub mod input;
+pub mod memory_viewer;
+pub mod vram_viewer;
@@ -0,0 +1,235 @@
+//! VRAM viewer
+//!
+//! A second window showing the state of the PPU, redrawn every frame:
+//!
+//! Panel      Explanation                                                                \
+//! Tiles      The 384 tiles of VRAM bank 0 and bank 1, in grayscale                     \
+//! Maps       Both BG tile maps (0x9800 and 0x9C00) with their attributes and palettes,  \
+//!            the screen (SCX/SCY) outlined on the one the background uses               \
+//! Palettes   The 8 BG palettes, then the 8 OBJ palettes                                 \
+//! OAM        The 40 sprites, each one's preview followed by Y, X, tile and attributes   \
+//!            as stored in OAM, in hex                                                   \
+
+use minifb::{Window, WindowOptions};
+
+use super::font::{self, CHAR_WIDTH};
+use crate::core::gpu::{
+    Gpu, BG_TILE_MAPS, PALETTE_COUNT, SCREEN_HEIGHT, SCREEN_WIDTH, SPRITE_COUNT, TILES_PER_BANK,
+    TILE_MAP_SIZE, VRAM_BANKS,
+};
+
+const GAP: usize = 8;
+const TILES_PER_ROW: usize = 16;
+const TILES_WIDTH: usize = TILES_PER_ROW * 8;
+const TILES_HEIGHT: usize = TILES_PER_BANK / TILES_PER_ROW * 8;
+
+const MAPS_Y: usize = TILES_HEIGHT + GAP;
+
+const SWATCH_SIZE: usize = 8;
+const PALETTE_WIDTH: usize = SWATCH_SIZE * 4 + 4;
+const PALETTES_Y: usize = MAPS_Y + TILE_MAP_SIZE + GAP;
+
+const OAM_X: usize = (TILE_MAP_SIZE + GAP) * 2;
+const OAM_COLUMNS: usize = 2;
+const OAM_ROWS: usize = SPRITE_COUNT / OAM_COLUMNS;
+const OAM_ROW_HEIGHT: usize = 20;
+// Preview, then "YY XX TT AA"
+const OAM_COLUMN_WIDTH: usize = 8 + 4 + 11 * CHAR_WIDTH + GAP;
+
+const WIDTH: usize = OAM_X + OAM_COLUMN_WIDTH * OAM_COLUMNS;
+const HEIGHT: usize = PALETTES_Y + (SWATCH_SIZE + 4) * 2;
+
+const BACKGROUND: u32 = 0x202020;
+const TEXT_COLOR: u32 = 0xE0E0E0;
+const VIEWPORT_COLOR: u32 = 0xFF0000;
+// Color indices of the tile data, which has no palette of its own
+const TILE_SHADES: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+
+pub struct VramViewer {
+    window: Window,
+    buffer: Vec<u32>,
+}
+
+impl VramViewer {
+    pub fn new() -> VramViewer {
+        let mut window = Window::new(
+            "GioBoyColor - VRAM",
+            WIDTH,
+            HEIGHT,
+            WindowOptions::default(),
+        )
+        .expect("Unable to Open Window");
+
+        // The main window already limits the frame rate
+        window.limit_update_rate(None);
+
+        VramViewer {
+            window,
+            buffer: vec![BACKGROUND; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn update(&mut self, gpu: &Gpu) {
+        self.buffer.fill(BACKGROUND);
+
+        for bank in 0..VRAM_BANKS {
+            self.draw_tiles(gpu, bank, bank * (TILES_WIDTH + GAP), 0);
+        }
+
+        for (index, &map) in BG_TILE_MAPS.iter().enumerate() {
+            let x = index * (TILE_MAP_SIZE + GAP);
+            self.draw_tile_map(gpu, map, x, MAPS_Y);
+        }
+
+        for palette in 0..PALETTE_COUNT {
+            let x = palette * PALETTE_WIDTH;
+            self.draw_palette(gpu.bg_palette(palette as u8), x, PALETTES_Y);
+            self.draw_palette(
+                gpu.obj_palette(palette as u8),
+                x,
+                PALETTES_Y + SWATCH_SIZE + 4,
+            );
+        }
+
+        for index in 0..SPRITE_COUNT {
+            let x = OAM_X + (index / OAM_ROWS) * OAM_COLUMN_WIDTH;
+            let y = (index % OAM_ROWS) * OAM_ROW_HEIGHT;
+            self.draw_sprite(gpu, index, x, y);
+        }
+
+        // We unwrap here as we want this code to exit if it fails, like the main window
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .unwrap();
+    }
+
+    fn draw_tiles(&mut self, gpu: &Gpu, bank: usize, x: usize, y: usize) {
+        for tile in 0..TILES_PER_BANK {
+            let tile_x = x + (tile % TILES_PER_ROW) * 8;
+            let tile_y = y + (tile / TILES_PER_ROW) * 8;
+
+            for row in 0..8 {
+                for (column, color_index) in gpu.tile_row(bank, tile, row as u8).iter().enumerate()
+                {
+                    self.set_pixel(
+                        tile_x + column,
+                        tile_y + row,
+                        TILE_SHADES[*color_index as usize],
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw_tile_map(&mut self, gpu: &Gpu, map: u16, x: usize, y: usize) {
+        for (index, color) in gpu.render_tile_map(map).into_iter().enumerate() {
+            self.set_pixel(x + index % TILE_MAP_SIZE, y + index / TILE_MAP_SIZE, color);
+        }
+
+        if map != gpu.bg_tile_map() {
+            return;
+        }
+
+        // The screen wraps around the edges of the map
+        let (scx, scy) = gpu.scroll();
+        let wrap = |start: u8, offset: usize| (start as usize + offset) % TILE_MAP_SIZE;
+
+        for offset in 0..SCREEN_WIDTH {
+            let map_x = x + wrap(scx, offset);
+            self.set_pixel(map_x, y + wrap(scy, 0), VIEWPORT_COLOR);
+            self.set_pixel(map_x, y + wrap(scy, SCREEN_HEIGHT - 1), VIEWPORT_COLOR);
+        }
+        for offset in 0..SCREEN_HEIGHT {
+            let map_y = y + wrap(scy, offset);
+            self.set_pixel(x + wrap(scx, 0), map_y, VIEWPORT_COLOR);
+            self.set_pixel(x + wrap(scx, SCREEN_WIDTH - 1), map_y, VIEWPORT_COLOR);
+        }
+    }
+
+    fn draw_palette(&mut self, colors: [u32; 4], x: usize, y: usize) {
+        for (index, color) in colors.iter().enumerate() {
+            self.fill(x + index * SWATCH_SIZE, y, SWATCH_SIZE, SWATCH_SIZE, *color);
+        }
+    }
+
+    fn draw_sprite(&mut self, gpu: &Gpu, index: usize, x: usize, y: usize) {
+        let sprite = gpu.sprite(index);
+        let height = gpu.sprite_height();
+
+        let (bank, palette) = if gpu.cgb_mode {
+            (
+                ((sprite.attributes >> 3) & 0x01) as usize,
+                sprite.attributes & 0x07,
+            )
+        } else {
+            (0, (sprite.attributes >> 4) & 0x01)
+        };
+        let colors = gpu.obj_palette(palette);
+        // 8x16 sprites ignore bit 0 of the tile index
+        let tile = if height == 16 {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        } as usize;
+
+        for row in 0..height {
+            let tile_row = if sprite.attributes & 0x40 != 0 {
+                height - 1 - row
+            } else {
+                row
+            };
+            let pixels = gpu.tile_row(bank, tile + tile_row / 8, (tile_row % 8) as u8);
+
+            for column in 0..8 {
+                let tile_column = if sprite.attributes & 0x20 != 0 {
+                    7 - column
+                } else {
+                    column
+                };
+                // Color 0 is transparent and left as the background
+                let color_index = pixels[tile_column];
+                if color_index != 0 {
+                    self.set_pixel(x + column, y + row, colors[color_index as usize]);
+                }
+            }
+        }
+
+        let text = [sprite.y, sprite.x, sprite.tile, sprite.attributes];
+        for (field, value) in text.iter().enumerate() {
+            let text_x = x + 12 + field * 3 * CHAR_WIDTH;
+            font::draw_hex(
+                &mut self.buffer,
+                WIDTH,
+                *value as u32,
+                2,
+                text_x,
+                y + 3,
+                TEXT_COLOR,
+            );
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.set_pixel(column, row, color);
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < WIDTH && y < HEIGHT {
+            self.buffer[y * WIDTH + x] = color;
+        }
+    }
+}
+
+impl Default for VramViewer {
+    fn default() -> VramViewer {
+        VramViewer::new()
+    }
+}
@@ -0,0 +1,3 @@
+pub mod core;
+#[cfg(feature = "minifb-frontend")]
+pub mod frontend;
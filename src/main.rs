@@ -1,7 +1,4 @@
-mod core;
-mod frontend;
-
-use frontend::minifb::emulator::Emulator;
+use gioboycolor::frontend::minifb::emulator::Emulator;
 
 fn main() {
     let mut app = Emulator::new();
@@ -0,0 +1,193 @@
+//! Linked instances
+//!
+//! Runs two GioBoyColor instances side by side with Lockstep, their ports connected with
+//! LinkCable::pair or InfraredLink::pair, on small ROMs built by the tests. The ROMs are
+//! straight-line code: the header is executed as NOPs and the code at 0x0150 is followed by
+//! NOPs up to the end.
+
+use std::{env, fs, path::PathBuf, process};
+
+use gioboycolor::core::{
+    gbc::GioBoyColor,
+    infrared::InfraredLink,
+    link::{LinkCable, Lockstep, SYNC_CYCLES},
+};
+
+const ROM_SIZE: usize = 0x8000;
+const CODE_START: usize = 0x0150;
+
+const SB: u8 = 0x01;
+const SC: u8 = 0x02;
+const RP: u8 = 0x56;
+const IF: u16 = 0xFF0F;
+const SERIAL_INTERRUPT: u8 = 0x08;
+const NOP: u8 = 0x00;
+const LD_HLI_A: u8 = 0x22;
+
+const RP_READ_ENABLE: u8 = 0xC0;
+const RP_RECEIVING: u8 = 0x02;
+const SAMPLES_START: u16 = 0xC000;
+// LDH A,(n) and LD (HL+),A
+const SAMPLE_CYCLES: u32 = 20;
+
+// Enough for a transfer at 8192 Hz, 8 bits of 512 T-cycles, and a few sync periods
+const TRANSFER_CYCLES: u32 = 20_000;
+
+// LD A,n
+fn ld_a(value: u8) -> [u8; 2] {
+    [0x3E, value]
+}
+
+// LDH (n),A
+fn ldh_to(register: u8) -> [u8; 2] {
+    [0xE0, register]
+}
+
+// LD HL,nn
+fn ld_hl(value: u16) -> [u8; 3] {
+    [0x21, value as u8, (value >> 8) as u8]
+}
+
+// LDH A,(n)
+fn ldh_from(register: u8) -> [u8; 2] {
+    [0xF0, register]
+}
+
+// Writes a ROM running code, to a file named after the test
+fn build_rom(name: &str, code: &[u8]) -> PathBuf {
+    build_rom_for(name, code, false)
+}
+
+// The CGB flag is the operand of an LD A,n so the header still runs
+fn build_rom_for(name: &str, code: &[u8], cgb: bool) -> PathBuf {
+    let mut rom = vec![0; ROM_SIZE];
+    rom[CODE_START..CODE_START + code.len()].copy_from_slice(code);
+    if cgb {
+        rom[0x0142] = 0x3E;
+        rom[0x0143] = 0x80;
+    }
+
+    let path = env::temp_dir().join(format!("gioboycolor-{}-{}.gb", name, process::id()));
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+fn load(path: &PathBuf) -> GioBoyColor {
+    let mut gbc = GioBoyColor::new();
+    gbc.load_rom(path).unwrap();
+    fs::remove_file(path).unwrap();
+    gbc
+}
+
+// Runs a transfer of 0x42 from an instance with the internal clock to one waiting for an
+// external clock with 0x99 in SB, returns both instances
+fn exchange(name: &str) -> (GioBoyColor, GioBoyColor) {
+    // The internal clock only reaches the other end once it was seen waiting at a sync point
+    let delay = vec![NOP; 2 * SYNC_CYCLES as usize / 4];
+    let master = [
+        &delay[..],
+        &ld_a(0x42),
+        &ldh_to(SB),
+        &ld_a(0x81),
+        &ldh_to(SC),
+    ]
+    .concat();
+    let slave = [ld_a(0x99), ldh_to(SB), ld_a(0x80), ldh_to(SC)].concat();
+    let mut master = load(&build_rom(&format!("{}-master", name), &master));
+    let mut slave = load(&build_rom(&format!("{}-slave", name), &slave));
+
+    let (first, second) = LinkCable::pair();
+    master.serial.connect(Box::new(first));
+    slave.serial.connect(Box::new(second));
+
+    Lockstep::new().run(&mut master, &mut slave, TRANSFER_CYCLES);
+    (master, slave)
+}
+
+#[test]
+fn bytes_are_exchanged() {
+    let (master, slave) = exchange("exchange");
+
+    assert_eq!(master.peek(0xFF00 | SB as u16), 0x99);
+    assert_eq!(slave.peek(0xFF00 | SB as u16), 0x42);
+    // Both transfers are over and requested an interrupt
+    for gbc in [&master, &slave] {
+        assert_eq!(gbc.peek(0xFF00 | SC as u16) & 0x80, 0);
+        assert_ne!(gbc.peek(IF) & SERIAL_INTERRUPT, 0);
+    }
+}
+
+#[test]
+fn exchanges_are_deterministic() {
+    let (first_master, first_slave) = exchange("deterministic-1");
+    let (second_master, second_slave) = exchange("deterministic-2");
+
+    assert!(first_master.save_state() == second_master.save_state());
+    assert!(first_slave.save_state() == second_slave.save_state());
+}
+
+// Sends pulses of the given widths in T-cycles from one instance to another sampling RP,
+// returns the widths seen by the receiver in samples. Lockstep runs the first instance given
+// ahead of the other in each turn
+fn send_pulses(widths: &[u32], receiver_first: bool) -> Vec<u32> {
+    // Leaves time for the receiver to enable reading
+    let mut sender = vec![NOP; 100];
+    for &width in widths {
+        // The LED goes off width T-cycles after it went on, LD A,n and LDH (n),A take 20
+        sender.extend([ld_a(0x01), ldh_to(RP)].concat());
+        sender.extend(vec![NOP; (width as usize - 20) / 4]);
+        sender.extend([ld_a(0x00), ldh_to(RP)].concat());
+        sender.extend(vec![NOP; 100]);
+    }
+
+    let samples = 400;
+    let mut receiver = [
+        &ld_a(RP_READ_ENABLE)[..],
+        &ldh_to(RP),
+        &ld_hl(SAMPLES_START),
+    ]
+    .concat();
+    for _ in 0..samples {
+        receiver.extend(ldh_from(RP));
+        receiver.push(LD_HLI_A);
+    }
+
+    let mut sender = load(&build_rom_for("infrared-sender", &sender, true));
+    let mut receiver = load(&build_rom_for("infrared-receiver", &receiver, true));
+    let (first, second) = InfraredLink::pair();
+    sender.infrared.connect(Box::new(first));
+    receiver.infrared.connect(Box::new(second));
+
+    let cycles = (samples + 100) * SAMPLE_CYCLES;
+    if receiver_first {
+        Lockstep::new().run(&mut receiver, &mut sender, cycles);
+    } else {
+        Lockstep::new().run(&mut sender, &mut receiver, cycles);
+    }
+
+    let mut runs = Vec::new();
+    let mut run = 0;
+    for address in SAMPLES_START..SAMPLES_START + samples as u16 {
+        if receiver.peek(address) & RP_RECEIVING == 0 {
+            run += 1;
+        } else if run > 0 {
+            runs.push(run);
+            run = 0;
+        }
+    }
+    runs
+}
+
+#[test]
+fn infrared_pulses_keep_their_width() {
+    let widths = [200, 400, 800];
+
+    for receiver_first in [false, true] {
+        let runs = send_pulses(&widths, receiver_first);
+        assert_eq!(runs.len(), widths.len(), "{:?}", runs);
+        for (run, width) in runs.into_iter().zip(widths) {
+            let expected = width / SAMPLE_CYCLES;
+            assert!(run.abs_diff(expected) <= 1, "{} for {}", run, expected);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Save states
+//!
+//! Saves and restores an instance running a small ROM built by the test, which fills WRAM with
+//! straight-line code, and checks that rejected states leave the machine as it was.
+
+use std::{env, fs, path::PathBuf, process};
+
+use gioboycolor::core::{
+    gbc::GioBoyColor,
+    save_state::{SaveStateError, VERSION},
+};
+
+const ROM_SIZE: usize = 0x8000;
+const CODE_START: usize = 0x0150;
+const CYCLES: u32 = 20_000;
+
+// Offsets in the header of a state
+const VERSION_OFFSET: usize = 4;
+const CHECKSUM_OFFSET: usize = 6;
+
+// LD HL,0xC000, then LD A,n and LD (HL+),A until the end of the ROM, with seed varying n
+fn build_rom(name: &str, seed: u8) -> PathBuf {
+    let mut rom = vec![0; ROM_SIZE];
+    let mut code = vec![0x21, 0x00, 0xC0];
+    for index in 0..(ROM_SIZE - CODE_START - code.len()) / 3 {
+        code.extend([0x3E, (index as u8).wrapping_mul(seed), 0x22]);
+    }
+    rom[CODE_START..CODE_START + code.len()].copy_from_slice(&code);
+
+    let path = env::temp_dir().join(format!("gioboycolor-{}-{}.gb", name, process::id()));
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+fn load(name: &str, seed: u8) -> GioBoyColor {
+    let path = build_rom(name, seed);
+    let mut gbc = GioBoyColor::new();
+    gbc.load_rom(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    gbc
+}
+
+#[test]
+fn states_round_trip() {
+    let mut gbc = load("round-trip", 3);
+    gbc.run_cycles(CYCLES);
+
+    let state = gbc.save_state();
+    gbc.run_cycles(CYCLES);
+    gbc.load_state(&state).unwrap();
+
+    assert!(gbc.save_state() == state);
+}
+
+#[test]
+fn resumed_states_run_the_same() {
+    let mut gbc = load("resume", 5);
+    gbc.run_cycles(CYCLES);
+    let state = gbc.save_state();
+
+    gbc.run_cycles(CYCLES);
+    let expected = gbc.save_state();
+
+    let mut resumed = load("resume-other", 5);
+    resumed.load_state(&state).unwrap();
+    resumed.run_cycles(CYCLES);
+
+    assert!(resumed.save_state() == expected);
+    // The 258th store, of 257 * 5
+    assert_eq!(resumed.peek(0xC101), 5);
+}
+
+// Loads a state made earlier and altered, which must be rejected without changing anything
+fn assert_rejected(
+    name: &str,
+    alter: impl FnOnce(&mut Vec<u8>),
+    check: impl FnOnce(SaveStateError) -> bool,
+) {
+    let mut gbc = load(name, 7);
+    gbc.run_cycles(CYCLES);
+    let mut state = gbc.save_state();
+    gbc.run_cycles(CYCLES);
+    let before = gbc.save_state();
+
+    alter(&mut state);
+    let error = gbc.load_state(&state).unwrap_err();
+
+    assert!(check(error));
+    assert!(gbc.save_state() == before);
+}
+
+#[test]
+fn rejects_wrong_magic() {
+    assert_rejected(
+        "magic",
+        |state| state[0] = b'X',
+        |error| matches!(error, SaveStateError::InvalidMagic),
+    );
+}
+
+#[test]
+fn rejects_future_versions() {
+    let future = VERSION + 1;
+    assert_rejected(
+        "version",
+        |state| state[VERSION_OFFSET..CHECKSUM_OFFSET].copy_from_slice(&future.to_le_bytes()),
+        |error| matches!(error, SaveStateError::UnsupportedVersion(version) if version == future),
+    );
+}
+
+#[test]
+fn rejects_checksum_mismatches() {
+    assert_rejected(
+        "checksum",
+        |state| state[CHECKSUM_OFFSET] ^= 0xFF,
+        |error| matches!(error, SaveStateError::RomMismatch),
+    );
+}
+
+#[test]
+fn rejects_states_of_other_roms() {
+    let mut other = load("other", 11);
+    other.run_cycles(CYCLES);
+    let state = other.save_state();
+
+    assert_rejected(
+        "other-rom",
+        |altered| *altered = state,
+        |error| matches!(error, SaveStateError::RomMismatch),
+    );
+}
+
+#[test]
+fn rejects_truncated_states() {
+    // In the header, then halfway through the components and at the end, which are rolled back
+    assert_rejected(
+        "truncated-header",
+        |state| state.truncate(CHECKSUM_OFFSET),
+        |error| matches!(error, SaveStateError::Truncated),
+    );
+    assert_rejected(
+        "truncated-half",
+        |state| state.truncate(state.len() / 2),
+        |error| matches!(error, SaveStateError::Truncated),
+    );
+    assert_rejected(
+        "truncated-end",
+        |state| state.truncate(state.len() - 1),
+        |error| matches!(error, SaveStateError::Truncated),
+    );
+}
+
+#[test]
+fn rejects_trailing_data() {
+    // Every component is loaded before the leftover byte is noticed
+    assert_rejected(
+        "trailing",
+        |state| state.push(0),
+        |error| matches!(error, SaveStateError::InvalidData),
+    );
+}
+
+#[test]
+fn rejects_states_without_a_rom() {
+    let state = load("no-rom", 13).save_state();
+    let mut gbc = GioBoyColor::new();
+
+    assert!(matches!(
+        gbc.load_state(&state),
+        Err(SaveStateError::NoRomLoaded)
+    ));
+}
@@ -0,0 +1,222 @@
+//! SM83 per-instruction tests
+//!
+//! Runs the community JSON test vectors (SingleStepTests sm83 format) from the directory given
+//! by GIOBOYCOLOR_SM83_TESTS, one file per opcode. The tests are skipped when it isn't set.
+//!
+//! Each case gives the registers and RAM before and after a single instruction, plus the bus
+//! activity of every machine cycle as [address, data, flags], where flags is "r-m" for reads,
+//! "-wm" for writes and "---" for internal cycles. The CPU runs on a flat 64 KiB bus so cases
+//! can use any address, wrapped by a tracing bus recording every cycle.
+//!
+//! GIOBOYCOLOR_TEST_FILTER can be set to only run the files whose name contains it.
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+};
+
+use serde_json::Value;
+
+use gioboycolor::core::{
+    bus::{BusCycle, TracingBus},
+    cpu::Cpu,
+    flat_bus::FlatBus,
+};
+
+const TESTS_ENV: &str = "GIOBOYCOLOR_SM83_TESTS";
+const FILTER_ENV: &str = "GIOBOYCOLOR_TEST_FILTER";
+
+const REGISTERS: [&str; 8] = ["a", "f", "b", "c", "d", "e", "h", "l"];
+
+fn number(state: &Value, field: &str) -> u64 {
+    state[field]
+        .as_u64()
+        .unwrap_or_else(|| panic!("Missing field {}", field))
+}
+
+// A fresh CPU per case, so no state (e.g. a pending EI) leaks from the previous one
+fn setup(bus: &mut TracingBus<FlatBus>, initial: &Value) -> Cpu {
+    let mut cpu = Cpu::new();
+    let registers = &mut cpu.registers;
+    for (register, name) in [
+        &mut registers.a,
+        &mut registers.f,
+        &mut registers.b,
+        &mut registers.c,
+        &mut registers.d,
+        &mut registers.e,
+        &mut registers.h,
+        &mut registers.l,
+    ]
+    .into_iter()
+    .zip(REGISTERS)
+    {
+        *register = number(initial, name) as u8;
+    }
+    registers.pc = number(initial, "pc") as u16;
+    registers.sp = number(initial, "sp") as u16;
+
+    cpu.ime = number(initial, "ime") != 0;
+
+    bus.inner.memory.fill(0);
+    bus.take_cycles();
+    for entry in initial["ram"].as_array().unwrap() {
+        bus.inner.memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+
+    cpu
+}
+
+// Returns a description of the first difference with the expected state
+fn check(
+    cpu: &Cpu,
+    bus: &mut TracingBus<FlatBus>,
+    expected: &Value,
+    expected_cycles: &[Value],
+) -> Result<(), String> {
+    let registers = &cpu.registers;
+    let actual = [
+        registers.a,
+        registers.f,
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ];
+
+    for (name, value) in REGISTERS.iter().zip(actual) {
+        if value as u64 != number(expected, name) {
+            return Err(format!(
+                "{} is {:02X}, expected {:02X}",
+                name,
+                value,
+                number(expected, name)
+            ));
+        }
+    }
+
+    for (name, value) in [("pc", registers.pc), ("sp", registers.sp)] {
+        if value as u64 != number(expected, name) {
+            return Err(format!(
+                "{} is {:04X}, expected {:04X}",
+                name,
+                value,
+                number(expected, name)
+            ));
+        }
+    }
+
+    if cpu.ime != (number(expected, "ime") != 0) {
+        return Err(format!("ime is {}, expected {}", cpu.ime, !cpu.ime));
+    }
+
+    for entry in expected["ram"].as_array().unwrap() {
+        let address = entry[0].as_u64().unwrap() as usize;
+        let value = entry[1].as_u64().unwrap() as u8;
+        if bus.inner.memory[address] != value {
+            return Err(format!(
+                "[{:04X}] is {:02X}, expected {:02X}",
+                address, bus.inner.memory[address], value
+            ));
+        }
+    }
+
+    let cycles = bus.take_cycles();
+    if cycles.len() != expected_cycles.len() {
+        return Err(format!(
+            "took {} cycles, expected {}",
+            cycles.len(),
+            expected_cycles.len()
+        ));
+    }
+
+    for (index, (cycle, entry)) in cycles.iter().zip(expected_cycles).enumerate() {
+        let address = entry[0].as_u64().unwrap_or(0) as u16;
+        let data = entry[1].as_u64().unwrap_or(0) as u8;
+        let expected_cycle = match entry[2].as_str().unwrap_or("---") {
+            "r-m" => BusCycle::Read { address, data },
+            "-wm" => BusCycle::Write { address, data },
+            _ => BusCycle::Internal,
+        };
+
+        if *cycle != expected_cycle {
+            return Err(format!(
+                "cycle {} is {:?}, expected {:?}",
+                index, cycle, expected_cycle
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Runs every case of a file, stopping at the first failure
+fn run_file(path: &PathBuf) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let cases: Vec<Value> = serde_json::from_str(&text).map_err(|error| error.to_string())?;
+
+    let mut bus = TracingBus::new(FlatBus::new());
+
+    for case in &cases {
+        let name = case["name"].as_str().unwrap_or("?");
+        let mut cpu = setup(&mut bus, &case["initial"]);
+
+        // Unimplemented instructions panic
+        panic::catch_unwind(AssertUnwindSafe(|| cpu.step(&mut bus)))
+            .map_err(|_| format!("{}: panicked", name))?;
+
+        let expected_cycles = case["cycles"].as_array().cloned().unwrap_or_default();
+        check(&cpu, &mut bus, &case["final"], &expected_cycles)
+            .map_err(|error| format!("{}: {}", name, error))?;
+    }
+
+    Ok(cases.len())
+}
+
+#[test]
+fn sm83() {
+    let Some(dir) = std::env::var_os(TESTS_ENV).map(PathBuf::from) else {
+        println!("{} is not set, skipping", TESTS_ENV);
+        return;
+    };
+
+    let filter = std::env::var(FILTER_ENV).unwrap_or_default();
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|error| panic!("Unable to read {}: {}", dir.display(), error))
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+                && path.to_string_lossy().contains(&filter)
+        })
+        .collect();
+    files.sort();
+
+    let mut failures = Vec::new();
+    let mut cases = 0;
+
+    for path in &files {
+        let file = path.file_stem().unwrap().to_string_lossy();
+
+        match run_file(path) {
+            Ok(count) => cases += count,
+            Err(error) => failures.push(format!("{}: {}", file, error)),
+        }
+    }
+
+    println!(
+        "SM83: {}/{} files passed ({} cases)",
+        files.len() - failures.len(),
+        files.len(),
+        cases
+    );
+    for failure in &failures {
+        println!("  FAIL {}", failure);
+    }
+
+    assert!(failures.is_empty(), "{} opcodes failed", failures.len());
+}
@@ -0,0 +1,291 @@
+//! Test ROM harness
+//!
+//! Runs the Blargg, Mooneye and acid2 test suites from a local directory, given by the
+//! GIOBOYCOLOR_TEST_ROMS environment variable. The suites are ignored by default, run them with
+//! `cargo test --test test_roms -- --ignored`, which fails if the variable isn't set.
+//!
+//! Suite     Expected location                       Pass condition                       \
+//! Blargg    blargg/**/{cpu_instrs,instr_timing,     Serial output contains "Passed"      \
+//!           mem_timing}*.gb                                                              \
+//! Mooneye   mooneye/acceptance/**/*.gb              Fibonacci registers at LD B,B        \
+//! acid2     acid2/{dmg,cgb}-acid2.gb                Frame at LD B,B matches the PNG with \
+//!                                                   the same name next to the ROM        \
+//!
+//! GIOBOYCOLOR_TEST_FILTER can be set to only run the ROMs whose path contains it.
+//!
+//! Add `--nocapture` to see the summary of passing suites.
+
+use std::{
+    fs::{self, File},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use gioboycolor::core::gbc::GioBoyColor;
+
+const ROMS_ENV: &str = "GIOBOYCOLOR_TEST_ROMS";
+const FILTER_ENV: &str = "GIOBOYCOLOR_TEST_FILTER";
+
+// cpu_instrs takes almost a minute of emulated time
+const BLARGG_MAX_FRAMES: u32 = 60 * 120;
+const MOONEYE_MAX_FRAMES: u32 = 60 * 20;
+const ACID2_MAX_FRAMES: u32 = 60 * 5;
+
+const BLARGG_ROMS: [&str; 3] = ["cpu_instrs", "instr_timing", "mem_timing"];
+// LD B,B is used by Mooneye and acid2 as a software breakpoint
+const LD_B_B: u8 = 0x40;
+// B, C, D, E, H, L on success, failures set them all to 0x42
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+struct Outcome {
+    rom: PathBuf,
+    result: Result<(), String>,
+}
+
+fn roms_dir() -> PathBuf {
+    let dir = std::env::var_os(ROMS_ENV)
+        .unwrap_or_else(|| panic!("{} must be set to the test ROMs directory", ROMS_ENV));
+    PathBuf::from(dir)
+}
+
+// Every .gb/.gbc file below dir accepted by the predicate and the filter, sorted by path
+fn find_roms(dir: &Path, predicate: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let filter = std::env::var(FILTER_ENV).unwrap_or_default();
+    let mut roms = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let is_rom = path
+                .extension()
+                .is_some_and(|extension| extension == "gb" || extension == "gbc");
+
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_rom && predicate(&path) && path.to_string_lossy().contains(&filter) {
+                roms.push(path);
+            }
+        }
+    }
+
+    roms.sort();
+    roms
+}
+
+// Runs the ROM until the condition holds, returns None if it didn't within max_frames
+fn run_until(
+    rom: &Path,
+    max_frames: u32,
+    condition: impl Fn(&GioBoyColor) -> bool,
+) -> Result<Option<GioBoyColor>, String> {
+    let mut gbc = GioBoyColor::new();
+    gbc.load_rom(&rom.to_path_buf())
+        .map_err(|error| format!("unable to load: {}", error))?;
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        for _ in 0..max_frames {
+            if gbc.run_frame_until(&condition).condition_met {
+                return Some(gbc);
+            }
+        }
+
+        None
+    }));
+
+    // Unimplemented instructions and registers panic
+    result.map_err(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| {
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+            })
+            .unwrap_or_default();
+        format!("panicked: {}", message)
+    })
+}
+
+fn at_breakpoint(gbc: &GioBoyColor) -> bool {
+    gbc.peek(gbc.cpu.registers.pc) == LD_B_B
+}
+
+fn run_blargg(rom: &Path) -> Result<(), String> {
+    let serial = |gbc: &GioBoyColor| String::from_utf8_lossy(gbc.serial.output()).into_owned();
+
+    match run_until(rom, BLARGG_MAX_FRAMES, |gbc| {
+        // Failures are followed by details, e.g. "Failed #3", so wait for the end of the line
+        let output = serial(gbc);
+        output.contains("Passed") || (output.contains("Failed") && output.ends_with('\n'))
+    })? {
+        Some(gbc) if serial(&gbc).contains("Passed") => Ok(()),
+        Some(gbc) => Err(serial(&gbc).trim().replace('\n', " ")),
+        None => Err("timed out".to_string()),
+    }
+}
+
+fn run_mooneye(rom: &Path) -> Result<(), String> {
+    let Some(gbc) = run_until(rom, MOONEYE_MAX_FRAMES, at_breakpoint)? else {
+        return Err("timed out".to_string());
+    };
+
+    let registers = &gbc.cpu.registers;
+    let signature = [
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ];
+
+    if signature == MOONEYE_PASS {
+        Ok(())
+    } else {
+        Err(format!("registers {:02X?}", signature))
+    }
+}
+
+// Replaces every color by its rank in brightness, so palettes and color correction that keep
+// the order of the shades don't matter
+fn normalize(pixels: &[u32]) -> Vec<usize> {
+    let luma = |pixel: u32| {
+        let (red, green, blue) = ((pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF);
+        red * 299 + green * 587 + blue * 114
+    };
+
+    let mut shades: Vec<u32> = pixels.iter().map(|pixel| luma(*pixel)).collect();
+    shades.sort_unstable();
+    shades.dedup();
+
+    pixels
+        .iter()
+        .map(|pixel| shades.binary_search(&luma(*pixel)).unwrap())
+        .collect()
+}
+
+fn load_reference(path: &Path) -> Result<Vec<u32>, String> {
+    let file = File::open(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut data)
+        .map_err(|error| error.to_string())?;
+
+    let channels = info.color_type.samples();
+    Ok(data[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| match channels {
+            1 | 2 => (pixel[0] as u32) * 0x010101,
+            _ => (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32,
+        })
+        .collect())
+}
+
+fn run_acid2(rom: &Path) -> Result<(), String> {
+    let reference = load_reference(&rom.with_extension("png"))?;
+
+    let Some(gbc) = run_until(rom, ACID2_MAX_FRAMES, at_breakpoint)? else {
+        return Err("timed out".to_string());
+    };
+
+    // The breakpoint is reached during VBlank, once the whole frame has been drawn
+    let frame = gbc.gpu.frame_buffer();
+    if frame.len() != reference.len() {
+        return Err(format!(
+            "reference has {} pixels, expected {}",
+            reference.len(),
+            frame.len()
+        ));
+    }
+
+    let mismatches = normalize(frame)
+        .iter()
+        .zip(normalize(&reference))
+        .filter(|(actual, expected)| **actual != *expected)
+        .count();
+
+    if mismatches == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} pixels differ from the reference", mismatches))
+    }
+}
+
+// Prints the summary and fails the test if any ROM failed
+fn report(suite: &str, outcomes: &[Outcome]) {
+    let passed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_ok())
+        .count();
+
+    println!("{}: {}/{} passed", suite, passed, outcomes.len());
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(()) => println!("  PASS {}", outcome.rom.display()),
+            Err(reason) => println!("  FAIL {}: {}", outcome.rom.display(), reason),
+        }
+    }
+
+    assert_eq!(passed, outcomes.len(), "{} test ROMs failed", suite);
+}
+
+fn run_suite(suite: &str, roms: Vec<PathBuf>, run: fn(&Path) -> Result<(), String>) {
+    if roms.is_empty() {
+        println!("{}: no ROMs found, skipping", suite);
+        return;
+    }
+
+    let outcomes: Vec<Outcome> = roms
+        .into_iter()
+        .map(|rom| Outcome {
+            result: run(&rom),
+            rom,
+        })
+        .collect();
+
+    report(suite, &outcomes);
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see GIOBOYCOLOR_TEST_ROMS"]
+fn blargg() {
+    let dir = roms_dir();
+
+    let roms = find_roms(&dir.join("blargg"), &|path| {
+        let name = path.file_name().unwrap().to_string_lossy();
+        BLARGG_ROMS.iter().any(|prefix| name.starts_with(prefix))
+    });
+
+    run_suite("Blargg", roms, run_blargg);
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see GIOBOYCOLOR_TEST_ROMS"]
+fn mooneye() {
+    let dir = roms_dir();
+
+    let roms = find_roms(&dir.join("mooneye").join("acceptance"), &|_| true);
+
+    run_suite("Mooneye", roms, run_mooneye);
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see GIOBOYCOLOR_TEST_ROMS"]
+fn acid2() {
+    let dir = roms_dir();
+
+    let roms = find_roms(&dir.join("acid2"), &|path| {
+        let name = path.file_name().unwrap().to_string_lossy();
+        name.starts_with("dmg-acid2") || name.starts_with("cgb-acid2")
+    });
+
+    run_suite("acid2", roms, run_acid2);
+}